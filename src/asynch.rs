@@ -1,297 +1,6449 @@
+mod audit;
+mod batch;
+#[cfg(feature = "bench")]
+mod bench;
+mod blocking;
+mod bridge;
+mod budget;
+mod buffer;
+mod checksum;
+mod chunk;
+mod clock;
+mod config;
+mod consumer;
+mod ctx;
+mod dedup;
+mod errors;
+mod export;
+mod fault;
+#[cfg(feature = "http")]
+mod http;
+mod lineage;
+mod metrics;
+mod multiplier;
+mod namespace;
+mod pattern;
+mod poller;
+mod producer;
+mod progress;
+mod queue;
+mod readiness;
+mod receipt;
+mod routing;
+mod sign;
+#[cfg(feature = "sim")]
+mod sim;
+mod stats;
+mod stream;
+mod supervisor;
+mod validate;
+mod wait;
+mod wal;
+mod writer;
+
+pub use audit::{AuditConsumer, AuditReason, AuditRecord};
+pub use batch::{BatchConsumer, FlushTriggers};
+#[cfg(feature = "bench")]
+pub use bench::{BenchConfig, BenchReport};
+pub use blocking::{
+    block_on, block_on_with, AsyncConsumer, BlockOn, ConsumeFuture, Executor, ThreadExecutor,
+};
+pub use bridge::Bridge;
+pub use budget::{Overflow, SpillFile};
+pub use checksum::ChecksumError;
+pub use clock::{Clock, MockClock, SystemClock};
+pub use config::{CarouselConfig, ConfigDelta};
+pub use consumer::{
+    from_fn, ConsumerExt, DedupWindow, Filter, FromFn, LogEvery, Map, SharedConsumer, Tee,
+};
+pub use ctx::{Ctx, CtxConsumer};
+pub use dedup::{DedupStore, FileDedupStore, MemoryDedupStore};
+pub use errors::{CarouselError, DropReason};
+pub use fault::{Faults, InjectedPanic};
+#[cfg(feature = "http")]
+pub use http::StatusServer;
+pub use lineage::{Lineage, Stage};
+pub use multiplier::Multiplier;
+pub use namespace::Namespace;
+pub use pattern::{PatternError, TopicPattern};
+pub use poller::{Poller, PollerOptions, PollerStats};
+pub use producer::ProducerQuota;
+pub use readiness::Readiness;
+pub use receipt::Receipt;
+pub use routing::RoutingTable;
+pub use sign::{verify, KeyProvider, SignatureError, StaticKey, Verify};
+#[cfg(feature = "sim")]
+pub use sim::SimCarousel;
+pub use stats::{
+    CarouselStats, ConsumerReport, ConsumerStats, Latency, NamespaceStats, ProducerStats, Rate,
+    ShutdownReport, Throughput,
+};
+pub use stream::StreamingConsumer;
+pub use supervisor::{ConsumerPanic, Escalated, PanicPolicy, Supervisor};
+pub use validate::{PutError, ValidationError};
+pub use wait::WaitStrategy;
+pub use writer::Writer;
+
+use std::any::Any;
+use std::collections::hash_map::{DefaultHasher, RandomState};
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io;
+use std::ops::RangeBounds;
+use std::path::Path;
 use std::sync;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The payload of a message, shared by all consumers it is forwarded to. The
+/// last consumer to get to a message takes the vector itself, the others get
+/// a copy.
+type Data = sync::Arc<Vec<u8>>;
+
+/// Takes the vector out of `data`, copying it if other consumers still share it.
+fn unshare(data: Data) -> Vec<u8> {
+    sync::Arc::try_unwrap(data).unwrap_or_else(|data| data.to_vec())
+}
+
+/// The maximum number of events a poller takes off its queue at once.
+const POLL_BATCH: usize = 64;
+
+/// The most events a multiplier takes off its channel before forwarding.
+const FORWARD_BATCH: usize = 64;
+
+/// `Context` is an opaque, propagated context captured by a `Propagator`.
+pub type Context = sync::Arc<dyn Any + Send + Sync>;
 
-type Data = Box<Vec<u8>>;
+/// `Envelope` wraps a message with everything that travels along with it
+/// from the producer to the consumers.
+#[derive(Clone)]
+struct Envelope {
+    data: Data,
+    id: Option<u64>,
+    offset: Option<u64>,
+    topic: Option<sync::Arc<str>>,
+    namespace: Option<sync::Arc<namespace::Tenant>>,
+    expires: Option<Instant>,
+    context: Option<Context>,
+    charge: Option<sync::Arc<budget::Charge>>,
+    quota: Option<sync::Arc<budget::Charge>>,
+    /// The charge of this copy to the memory limit of its consumer, see
+    /// `Builder::consumer_memory_limit`.
+    backlog: Option<sync::Arc<budget::Charge>>,
+    spilled: Option<sync::Arc<budget::Spilled>>,
+    chunk: Option<chunk::Chunk>,
+    /// The CRC-32 of the whole payload, see `Builder::checksums`.
+    crc: Option<u32>,
+    /// When the message was put, see `ConsumerStats::latency`.
+    put: Instant,
+    receipt: Option<sync::Arc<receipt::Ticket>>,
+    lineage: Option<sync::Arc<Lineage>>,
+    /// The bridges the message crossed, see `Bridge`.
+    hop: Option<bridge::Hop>,
+    /// Whether the poller of the primary consumer hands the message on to the
+    /// other consumers, see `Builder::primary`.
+    fan_out: bool,
+}
+
+impl Envelope {
+    fn namespace(&self) -> Option<&str> {
+        self.namespace.as_ref().map(|tenant| &*tenant.name)
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+
+    /// Returns the envelope of a message read back from the write-ahead log,
+    /// which is handed to the consumers of `shared` again.
+    fn replayed(record: &wal::Record, shared: &Shared) -> Envelope {
+        Envelope {
+            data: sync::Arc::new(record.data.clone()),
+            id: None,
+            offset: Some(record.offset),
+            topic: record.topic.as_deref().map(sync::Arc::from),
+            namespace: record.namespace.as_deref().map(|name| shared.tenant(name)),
+            expires: None,
+            context: None,
+            charge: None,
+            quota: None,
+            backlog: None,
+            spilled: None,
+            chunk: None,
+            crc: None,
+            put: shared.clock.now(),
+            receipt: None,
+            lineage: None,
+            hop: None,
+            fan_out: false,
+        }
+    }
+}
 
 /// `Event` is an enum that offers various type of events that will be
 /// handled by an mp2c carousel.
-#[derive(Debug, Clone)]
 enum Event {
-    Message(Data),
+    Message(Envelope),
+    Subscribe(Subscription),
+    /// Changes a running consumer, handled by the multiplier.
+    Control(usize, Control),
+    /// Changes several running consumers at once, handled by the multiplier.
+    Routing(RoutingTable),
+    /// Changes the settings of the carousel, handled by the multiplier.
+    Reconfigure(ConfigDelta),
+    /// Changes the filter or rate limit of a consumer, handled by its poller.
+    Gate(routing::GateChange),
     Terminate,
 }
 
+/// `Control` is a change to a running consumer, see `SubscriptionId`.
+enum Control {
+    Pause,
+    Resume,
+    Filter(MessageFilter),
+    Remove,
+}
+
+/// `MessageFilter` selects the messages a consumer consumes.
+type MessageFilter = Box<dyn FnMut(&[u8]) -> bool + Send + 'static>;
+
+/// `SubscriptionId` identifies a consumer of a running `Carousel`, e.g. to
+/// pause it with `Carousel::pause`. Consumers registered with the `Builder`
+/// have the ids `0`, `1`, ... in registration order; consumers subscribed with
+/// `Carousel::subscribe` get theirs returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SubscriptionId(pub usize);
+
+/// `Subscription` is a consumer waiting to be started by the `Multiplier`.
+struct Subscription {
+    id: usize,
+    namespace: Option<sync::Arc<namespace::Tenant>>,
+    topic: Option<Selector>,
+    sample: Option<routing::Sample>,
+    factory: Factory,
+}
+
+/// `Selector` selects the topics a consumer subscribed to.
+#[derive(Clone)]
+pub(crate) enum Selector {
+    Topic(String),
+    Pattern(TopicPattern),
+}
+
 /// `Consumer` enables to implement handling logic for a vector of bytes.
 ///
 /// Each consumer which would like to receive a message should implement
 /// this trait.
+///
+/// A consumer is owned by exactly one poller thread, which is why `consume`
+/// takes `&mut self`: counters, buffers and connections can be plain fields
+/// without any interior mutability.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::Consumer;
+///
+/// struct ByteCounter {
+///   bytes: usize,
+/// }
+///
+/// impl Consumer for ByteCounter {
+///   fn consume(&mut self, data: Vec<u8>) {
+///     self.bytes += data.len();
+///   }
+/// }
+/// ```
 pub trait Consumer {
     fn consume(&mut self, data: Vec<u8>);
 }
 
-/// `Poller` is a simple struct that encapsulates a polling thread that calls
-/// the encapsulating `Consumer` for each `Event`.
-struct Poller {
-    thread: Option<thread::JoinHandle<()>>,
+impl<T: Consumer + ?Sized> Consumer for Box<T> {
+    fn consume(&mut self, data: Vec<u8>) {
+        (**self).consume(data);
+    }
 }
 
-impl Poller {
-    fn new<T: ?Sized>(consumer: Box<T>, rx: sync::Arc<sync::Mutex<mpsc::Receiver<Event>>>) -> Poller
-    where
-        T: Consumer + Send + 'static,
-    {
-        let mut consumer = consumer;
-        let thread = thread::spawn(move || loop {
-            match rx.lock().unwrap().recv() {
-                Ok(event) => match event {
-                    Event::Message(data) => {
-                        let data = data.clone();
-                        consumer.consume(*data);
-                    }
-                    Event::Terminate => {
-                        break;
-                    }
-                },
-                Err(e) => println!("Poller error receiving an event: {}", e),
-            }
-        });
+/// `Error` is the error type returned by a failing `TryConsumer`.
+pub type Error = Box<dyn error::Error + Send + Sync + 'static>;
 
-        Poller {
-            thread: Some(thread),
-        }
-    }
+/// `TryConsumer` is a `Consumer` that can fail to handle a message.
+///
+/// Failed messages are retried as configured with `Builder::retries` and then
+/// handed to the handler registered with `Builder::error_handler`.
+///
+/// Every `Consumer` is a `TryConsumer` that never fails, so both kinds of
+/// consumers are registered with `Builder::consumer`.
+pub trait TryConsumer {
+    fn try_consume(&mut self, data: Vec<u8>) -> Result<(), Error>;
 }
 
-struct Multipier {
-    pollers: Vec<Poller>,
-    thread: Option<thread::JoinHandle<()>>,
+impl<T: Consumer + ?Sized> TryConsumer for T {
+    fn try_consume(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        self.consume(data);
+        Ok(())
+    }
 }
 
-impl Multipier {
-    fn new<T: ?Sized>(
-        consumers: Vec<Box<T>>,
-        rx: sync::Arc<sync::Mutex<mpsc::Receiver<Event>>>,
-    ) -> Multipier
-    where
-        T: Consumer + Send + 'static,
-    {
-        let mut multiplier_txs: Vec<mpsc::Sender<Event>> = Vec::with_capacity(consumers.len());
+/// `ConsumeError` reports a message that a `TryConsumer` failed to handle,
+/// including the message itself so that it can be dead lettered.
+#[derive(Debug)]
+pub struct ConsumeError {
+    /// The position of the consumer in registration order.
+    pub consumer: usize,
+    /// The number of times the message was handed to the consumer.
+    pub attempts: usize,
+    /// The message that could not be consumed.
+    pub data: Vec<u8>,
+    /// The error returned by the last attempt.
+    pub error: Error,
+}
 
-        let pollers: Vec<Poller> = consumers
-            .into_iter()
-            .map(|c| {
-                let (ctx, crx) = mpsc::channel::<Event>();
+impl fmt::Display for ConsumeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "consumer {} failed after {} attempt(s): {}",
+            self.consumer, self.attempts, self.error
+        )
+    }
+}
 
-                let crx = sync::Arc::new(sync::Mutex::new(crx));
+/// `Sink` is a consumer as seen by its poller.
+enum Sink {
+    /// A consumer of whole messages.
+    Whole(Box<dyn CtxConsumer>),
+    /// A consumer of whole messages that can move to another thread, so its
+    /// poller may exit while idle, see `Builder::idle_timeout`.
+    Mobile(Box<dyn CtxConsumer + Send>),
+    /// A consumer reading messages as streams.
+    Stream(Box<dyn StreamingConsumer>),
+}
 
-                multiplier_txs.push(ctx);
+/// `Factory` creates a consumer on its poller thread.
+type Factory = Box<dyn FnOnce() -> Sink + Send + 'static>;
 
-                Poller::new(c, sync::Arc::clone(&crx))
-            })
-            .collect();
+type ErrorHandler = Box<dyn Fn(ConsumeError) + Send + Sync + 'static>;
 
-        let thread = thread::spawn(move || loop {
-            let cloned = multiplier_txs.clone();
-            match rx.lock().unwrap().recv() {
-                Ok(event) => {
-                    cloned.into_iter().for_each(|tx| {
-                        tx.send(event.clone()).unwrap();
-                    });
-                    if let Event::Terminate = event {
-                        break;
-                    }
-                }
-                Err(e) => println!("Multiplier error receiving an event: {}", e),
-            }
-        });
+type ShutdownHandler = Box<dyn Fn(ShutdownReport) + Send + Sync + 'static>;
 
-        Multipier {
-            pollers,
-            thread: Some(thread),
-        }
-    }
-}
+type Transform = Box<dyn Fn(Vec<u8>) -> Option<Vec<u8>> + Send + Sync + 'static>;
 
-/// `Carousel` represents a multi producer multi polling consumer data carousel. It enables
-/// message passing from multiple producers to multiple consumers asynchronously.
-///
-/// It accepts a vector of bytes as a message/ event.
-///
-/// A mp2c `Carousel` can be created for a list of consumers. However, each consumer
-/// is expected to implement the `Consumer` trait.
-///
-/// A multiplier thread is started which receives one end of an async channel.
-/// Each message `put` on the `Carousel` is sent to this multiplier thread. The job
-/// of the `Multiplier` is to clone each incoming event/ message and send it to each
-/// polling consumer.
-///
-/// For each consumer, a poller thread is started which receives one end of an async
-/// channel. The transmitting end of the channel is with the `Multiplier` thread. The
-/// poller calls `Consumer::consume` on it's registered consumer.
-///
-/// An `Carousel` can be cloned and the clone creates a clone of the `Sender` from which it is
-/// cloned. When `Carousel::put` is called to send a message, it'll be sent to the pollers in
-/// the originating `Carousel`.
+/// `Propagator` carries ambient context, such as the current trace context,
+/// from the producer calling `Carousel::put` to consumers handling the message.
 ///
 /// # Example
-/// ```
-/// use mp2c::asynch::{Carousel, Consumer};
 ///
-/// struct TestConsumer1;
+/// An OpenTelemetry propagator that makes spans created inside consumers
+/// children of the producing span.
+/// ```ignore
+/// use mp2c::asynch::{Context, Propagator};
 ///
-/// impl Consumer for TestConsumer1 {
-///   fn consume(&mut self, data: Vec<u8>) {
-///     let msg = String::from_utf8(data).unwrap();
-///     // do something with msg
-///   }
-/// }
+/// struct OtelPropagator;
 ///
-/// struct TestConsumer2;
+/// impl Propagator for OtelPropagator {
+///   fn capture(&self) -> Context {
+///     std::sync::Arc::new(opentelemetry::Context::current())
+///   }
 ///
-/// impl Consumer for TestConsumer2 {
-///  fn consume(&mut self, data: Vec<u8>) {
-///    let msg = String::from_utf8(data).unwrap();
-///    // do something with msg   
-///  }
+///   fn scope(&self, context: &Context, consume: &mut dyn FnMut()) {
+///     let cx = context.downcast_ref::<opentelemetry::Context>().unwrap();
+///     let _guard = cx.clone().attach();
+///     consume();
+///   }
 /// }
+/// ```
+pub trait Propagator: Send + Sync {
+    /// Captures the context of the calling thread. Called by `Carousel::put`.
+    fn capture(&self) -> Context;
+
+    /// Calls `consume` with `context` restored on the poller thread.
+    fn scope(&self, context: &Context, consume: &mut dyn FnMut());
+}
+
+/// `DepthAlert` is handed to the callback registered with `Builder::depth_alert`
+/// whenever a consumer's queue depth crosses one of the configured thresholds.
 ///
-/// let mut v: Vec<Box<dyn Consumer + Send + 'static>> = Vec::new();
-/// v.push(Box::new(TestConsumer1));
-/// v.push(Box::new(TestConsumer2));
-///
-/// let c = Carousel::new(v);
-///
-/// for _ in 1..10 {
-///   let cloned_c = c.clone();
-///   let t = std::thread::spawn(move || {
-///     cloned_c.put(String::from("test").into_bytes());
-///   });
-///   t.join().unwrap();
-/// }
+/// `consumer` is the position of the consumer in registration order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthAlert {
+    /// The queue depth of a consumer reached the high threshold.
+    Raised { consumer: usize, depth: usize },
+    /// The queue depth of a previously alerted consumer recovered to the low threshold.
+    Cleared { consumer: usize, depth: usize },
+}
+
+struct DepthAlerts {
+    high: usize,
+    low: usize,
+    handler: Box<dyn Fn(DepthAlert) + Send + Sync + 'static>,
+}
+
+/// `Slot` is the state of a single consumer that is shared between the
+/// multiplier and the consumer's `PollerThread`.
 ///
-/// ```
+/// The queue depth counts messages forwarded to a consumer which it has not
+/// finished consuming yet.
+struct Slot {
+    id: usize,
+    queue: queue::Queue,
+    depth: AtomicUsize,
+    alerted: AtomicBool,
+    alerts: Option<sync::Arc<DepthAlerts>>,
+    /// The memory limit of the consumer, see `Builder::consumer_memory_limit`.
+    budget: Option<sync::Arc<budget::Budget>>,
+    progress: sync::Arc<progress::Progress>,
+    consumed: AtomicU64,
+    expired: AtomicU64,
+    corrupted: AtomicU64,
+    /// The random seed and the number of draws of a sampled consumer.
+    seed: u64,
+    draws: AtomicU64,
+    throughput: metrics::Window,
+    latency: metrics::Histogram,
+    /// The consumer of a poller that exited while idle.
+    dormant: sync::Mutex<Option<Dormant>>,
+    /// The current thread of the poller.
+    thread: sync::Mutex<Option<thread::JoinHandle<()>>>,
+    /// The call to the consumer in progress, see `Builder::consume_timeout`.
+    call: sync::Mutex<Option<Call>>,
+    timed_out: AtomicU64,
+    panics: AtomicU64,
+    discarded: AtomicU64,
+}
 
-pub struct Carousel {
-    tx: mpsc::Sender<Event>,
-    multiplier: Option<Multipier>,
+/// `Call` is a call to a consumer watched for taking too long.
+struct Call {
+    started: Instant,
+    attempt: usize,
+    timed_out: bool,
 }
 
-impl Carousel {
-    /// Creates a new `Carousel` for a vector of consumers.
-    pub fn new<T: ?Sized>(consumers: Vec<Box<T>>) -> Carousel
-    where
-        T: Consumer + Send + 'static,
-    {
-        assert!(consumers.len() > 0);
+impl Slot {
+    fn is_alerted(&self) -> bool {
+        self.alerted.load(Ordering::SeqCst)
+    }
 
-        let (tx, rx) = mpsc::channel::<Event>();
+    fn new(
+        id: usize,
+        alerts: Option<sync::Arc<DepthAlerts>>,
+        budget: Option<sync::Arc<budget::Budget>>,
+        progress: sync::Arc<progress::Progress>,
+        now: Instant,
+    ) -> Slot {
+        Slot {
+            id,
+            queue: queue::Queue::new(),
+            depth: AtomicUsize::new(0),
+            alerted: AtomicBool::new(false),
+            alerts,
+            budget,
+            progress,
+            consumed: AtomicU64::new(0),
+            expired: AtomicU64::new(0),
+            corrupted: AtomicU64::new(0),
+            seed: RandomState::new().build_hasher().finish(),
+            draws: AtomicU64::new(0),
+            throughput: metrics::Window::new(now),
+            latency: metrics::Histogram::new(),
+            dormant: sync::Mutex::new(None),
+            thread: sync::Mutex::new(None),
+            call: sync::Mutex::new(None),
+            timed_out: AtomicU64::new(0),
+            panics: AtomicU64::new(0),
+            discarded: AtomicU64::new(0),
+        }
+    }
 
-        let rx = sync::Arc::new(sync::Mutex::new(rx));
+    fn stats(&self, now: Instant) -> ConsumerStats {
+        ConsumerStats {
+            id: self.id,
+            depth: self.depth.load(Ordering::SeqCst),
+            consumed: self.consumed.load(Ordering::SeqCst),
+            expired: self.expired.load(Ordering::SeqCst),
+            corrupted: self.corrupted.load(Ordering::SeqCst),
+            throughput: self.throughput.throughput(now),
+            latency: self.latency.latency(),
+            dormant: self.dormant.lock().unwrap().is_some(),
+            timed_out: self.timed_out.load(Ordering::SeqCst),
+            panics: self.panics.load(Ordering::SeqCst),
+            discarded: self.discarded.load(Ordering::SeqCst),
+            queued_bytes: self.budget.as_ref().map_or(0, |b| b.used()),
+            dropped: self.budget.as_ref().map_or(0, |b| b.dropped()),
+        }
+    }
 
-        let multiplier = Multipier::new(consumers, rx);
+    /// Counts a message of `bytes` bytes put at `put` and handed to the
+    /// consumer at `now`.
+    fn handed(&self, put: Instant, now: Instant, bytes: usize) {
+        self.latency.record(now.saturating_duration_since(put));
+        self.throughput.record(now, bytes);
+    }
 
-        Carousel {
-            tx,
-            multiplier: Some(multiplier),
+    fn enqueue(&self, mut envelope: Envelope, shared: &Shared) {
+        if !self.admit(&mut envelope, shared) {
+            return;
         }
+        self.enqueued(1);
+        self.push(Event::Message(envelope));
     }
 
-    /// Puts a message on the `Carousel` which will be asynchronously
-    /// sent to all it's consumers.
-    pub fn put(&self, data: Vec<u8>) {
-        let data = Box::new(data);
-        let event = Event::Message(data);
-        self.tx.send(event).unwrap();
+    /// Enqueues all `envelopes`, leaving the vector empty for reuse.
+    fn enqueue_batch(&self, envelopes: &mut Vec<Envelope>, shared: &Shared) {
+        // A consumer with a memory limit may block on the bytes of the
+        // messages queued before, so these have to be queued one by one.
+        if self.budget.is_some() {
+            for envelope in envelopes.drain(..) {
+                self.enqueue(envelope, shared);
+            }
+            return;
+        }
+        if envelopes.is_empty() {
+            return;
+        }
+        self.enqueued(envelopes.len());
+        let woken = self
+            .queue
+            .push_batch(envelopes.drain(..).map(Event::Message));
+        if woken {
+            self.wake();
+        }
     }
-}
 
-impl Clone for Carousel {
-    fn clone(&self) -> Self {
-        Carousel {
-            tx: self.tx.clone(),
-            multiplier: Option::None,
+    /// Charges `envelope` to the memory limit of the consumer, if any.
+    /// Returns `false` if the message is dropped for this consumer.
+    fn admit(&self, envelope: &mut Envelope, shared: &Shared) -> bool {
+        let budget = match &self.budget {
+            Some(budget) => budget,
+            None => return true,
+        };
+        if budget.admit_backlog(envelope) {
+            return true;
         }
+        shared.audit(AuditReason::Overflow, envelope);
+        shared.dropped(Some(self.id), DropReason::Overflow, 1);
+        false
     }
-}
 
-impl Drop for Carousel {
-    fn drop(&mut self) {
-        if let Some(multiplier) = &mut self.multiplier {
-            println!("Sending terminate message to all pollers.");
+    /// Hands `event` to the poller, starting it again if it exited while idle.
+    fn push(&self, event: Event) {
+        if self.queue.push(event) {
+            self.wake();
+        }
+    }
 
-            self.tx.send(Event::Terminate).unwrap();
+    fn wake(&self) {
+        if let Some(dormant) = self.dormant.lock().unwrap().take() {
+            dormant.resume();
+        }
+    }
+
+    /// Removes all messages from the queue, see `DropPolicy::Discard`.
+    fn discard(&self, shared: &Shared) {
+        let discarded = self
+            .queue
+            .retain(|event| !matches!(event, Event::Message(_)));
+        for _ in 0..discarded {
+            self.dequeued();
+        }
+        self.discarded.fetch_add(discarded as u64, Ordering::SeqCst);
+        shared.dropped(Some(self.id), DropReason::Discarded, discarded as u64);
+    }
 
-            if let Some(multiplier_thread) = multiplier.thread.take() {
-                multiplier_thread.join().unwrap();
+    /// Removes messages that expired before `now` from the queue.
+    fn sweep(&self, now: Instant, shared: &Shared) {
+        let swept = self.queue.retain(|event| match event {
+            // Chunks are left to the poller, which has to know that a
+            // message lost a chunk.
+            Event::Message(envelope) if envelope.chunk.is_none() && envelope.is_expired(now) => {
+                shared.audit(AuditReason::Expired { consumer: self.id }, envelope);
+                false
             }
+            _ => true,
+        });
+        for _ in 0..swept {
+            self.dequeued();
+        }
+        self.expired.fetch_add(swept as u64, Ordering::SeqCst);
+        shared.dropped(Some(self.id), DropReason::Expired, swept as u64);
+    }
 
-            println!("Shutting down all pollers.");
+    fn enqueued(&self, n: usize) {
+        let depth = self.depth.fetch_add(n, Ordering::SeqCst) + n;
+        if let Some(alerts) = &self.alerts {
+            if depth >= alerts.high && !self.alerted.swap(true, Ordering::SeqCst) {
+                (alerts.handler)(DepthAlert::Raised {
+                    consumer: self.id,
+                    depth,
+                });
+            }
+        }
+    }
 
-            for poller in &mut multiplier.pollers {
-                if let Some(thread) = poller.thread.take() {
-                    thread.join().unwrap();
-                }
+    fn dequeued(&self) {
+        let depth = self.depth.fetch_sub(1, Ordering::SeqCst) - 1;
+        if let Some(alerts) = &self.alerts {
+            if depth <= alerts.low && self.alerted.swap(false, Ordering::SeqCst) {
+                (alerts.handler)(DepthAlert::Cleared {
+                    consumer: self.id,
+                    depth,
+                });
+                self.progress.notify();
+                return;
             }
         }
+        if depth == 0 {
+            self.progress.notify();
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use crate::asynch::{Carousel, Consumer};
+/// `PollerThread` is a simple struct that encapsulates a polling thread that
+/// calls the encapsulating `Consumer` for each `Event`.
+struct PollerThread {
+    slot: sync::Arc<Slot>,
+}
 
-    #[test]
-    fn basic() {
-        struct TestConsumer1;
+/// `PollState` is what a poller keeps about its consumer besides the
+/// consumer itself.
+struct PollState {
+    namespace: Option<sync::Arc<namespace::Tenant>>,
+    assembler: chunk::Assembler,
+    gate: routing::Gate,
+    injector: Option<fault::Injector>,
+}
 
-        impl Consumer for TestConsumer1 {
-            fn consume(&mut self, data: Vec<u8>) {
-                assert_eq!(String::from_utf8(data).unwrap(), String::from("test"));
-            }
-        }
+/// `Dormant` is a consumer whose poller exited while idle, waiting in its
+/// slot to be handed to a new poller thread once a message arrives.
+struct Dormant {
+    consumer: Box<dyn CtxConsumer + Send>,
+    state: PollState,
+    slot: sync::Arc<Slot>,
+    shared: sync::Arc<Shared>,
+}
 
-        struct TestConsumer2;
+impl Dormant {
+    fn resume(self) {
+        let Dormant {
+            consumer,
+            state,
+            slot,
+            shared,
+        } = self;
+        PollerThread::start(slot, shared, move || (Sink::Mobile(consumer), state));
+    }
+}
 
-        impl Consumer for TestConsumer2 {
-            fn consume(&mut self, data: Vec<u8>) {
-                assert_eq!(String::from_utf8(data).unwrap(), String::from("test"));
-            }
+impl PollerThread {
+    /// Starts a poller for `subscription` and returns it along with the route
+    /// the multiplier forwards its messages through. Messages in `replay` the
+    /// consumer hasn't handled yet are forwarded right away.
+    fn spawn(
+        subscription: Subscription,
+        shared: &sync::Arc<Shared>,
+        replay: &[wal::Record],
+    ) -> (Route, PollerThread) {
+        let slot = sync::Arc::new(Slot::new(
+            subscription.id,
+            shared.alerts.clone(),
+            shared.consumer_budgets.get(&subscription.id).cloned(),
+            sync::Arc::clone(&shared.progress),
+            shared.clock.now(),
+        ));
+
+        let route = Route {
+            namespace: subscription.namespace.clone(),
+            topic: subscription.topic,
+            sample: subscription.sample,
+            slot: sync::Arc::clone(&slot),
+        };
+
+        // The poller runs before the replay is forwarded, which may wait for
+        // it to make room, see `Builder::consumer_memory_limit`.
+        let poller = PollerThread::new(
+            subscription.factory,
+            subscription.namespace,
+            sync::Arc::clone(&slot),
+            sync::Arc::clone(shared),
+        );
+
+        if let Some(wal) = &shared.wal {
+            let committed = wal.committed(slot.id).unwrap_or_else(|e| {
+                println!(
+                    "Multiplier error reading offset of consumer {}: {}",
+                    slot.id, e
+                );
+                None
+            });
+            replay
+                .iter()
+                .filter(|r| committed.is_none_or(|c| r.offset > c))
+                .map(|r| Envelope::replayed(r, shared))
+                .filter(|envelope| route.accepts(envelope))
+                .for_each(|envelope| route.forward(envelope, shared));
         }
 
-        let mut v: Vec<Box<dyn Consumer + Send + 'static>> = Vec::new();
-        v.push(Box::new(TestConsumer1));
-        v.push(Box::new(TestConsumer2));
-        let c = Carousel::new(v);
+        (route, poller)
+    }
 
-        c.put(String::from("test").into_bytes());
-        c.put(String::from("test").into_bytes());
-        c.put(String::from("test").into_bytes());
-        c.put(String::from("test").into_bytes());
+    fn new(
+        factory: Factory,
+        namespace: Option<sync::Arc<namespace::Tenant>>,
+        slot: sync::Arc<Slot>,
+        shared: sync::Arc<Shared>,
+    ) -> PollerThread {
+        let state = PollState {
+            namespace,
+            assembler: chunk::Assembler::default(),
+            gate: routing::Gate::new(sync::Arc::clone(&shared.clock)),
+            injector: shared
+                .faults
+                .map(|faults| fault::Injector::new(faults, slot.id)),
+        };
+        PollerThread::start(sync::Arc::clone(&slot), shared, move || (factory(), state));
 
-        std::thread::sleep(std::time::Duration::from_secs(2));
+        PollerThread { slot }
     }
 
-    #[test]
-    fn multi_producer() {
-        struct TestConsumer1;
+    /// Starts a poller thread for `slot` that consumes with the consumer
+    /// returned by `init`. While `Builder::idle_timeout` passes without a
+    /// message the thread leaves the consumer in the slot and exits.
+    fn start<F>(slot: sync::Arc<Slot>, shared: sync::Arc<Shared>, init: F)
+    where
+        F: FnOnce() -> (Sink, PollState) + Send + 'static,
+    {
+        // Holding the lock keeps a later thread from being stored first.
+        let mut thread = slot.thread.lock().unwrap();
+        let name = format!("poller-{}", slot.id);
+        let poller = sync::Arc::clone(&slot);
+        *thread = Some(sync::Arc::clone(&shared).spawn(name, move || {
+            let slot = poller;
+            let (mut sink, mut state) = init();
+            while PollerThread::poll(&mut sink, &mut state, &slot, &shared) {
+                let consumer = match sink {
+                    Sink::Mobile(consumer) => consumer,
+                    _ => unreachable!("only mobile consumers idle"),
+                };
+                *slot.dormant.lock().unwrap() = Some(Dormant {
+                    consumer,
+                    state,
+                    slot: sync::Arc::clone(&slot),
+                    shared: sync::Arc::clone(&shared),
+                });
+                if slot.queue.sleep() {
+                    return;
+                }
+                let dormant = slot.dormant.lock().unwrap().take().unwrap();
+                sink = Sink::Mobile(dormant.consumer);
+                state = dormant.state;
+            }
+        }));
+    }
 
-        impl Consumer for TestConsumer1 {
-            fn consume(&mut self, data: Vec<u8>) {
-                assert_eq!(String::from_utf8(data).unwrap(), String::from("test"));
+    /// Handles the events of `slot` until the `Carousel` terminates. Returns
+    /// `true` instead if no event arrived within `Builder::idle_timeout`.
+    fn poll(
+        sink: &mut Sink,
+        state: &mut PollState,
+        slot: &Slot,
+        shared: &sync::Arc<Shared>,
+    ) -> bool {
+        let carousel = Carousel::handle(shared);
+        let namespace = state.namespace.clone().map(|tenant| Namespace {
+            carousel: carousel.clone(),
+            tenant,
+        });
+        let idle_timeout = match sink {
+            Sink::Mobile(_) => shared.idle_timeout,
+            _ => None,
+        };
+        let mut inbox = queue::Inbox::new(POLL_BATCH, shared.wait);
+        loop {
+            let event = match idle_timeout {
+                Some(timeout) => match inbox.next_within(&slot.queue, timeout) {
+                    Some(event) => event,
+                    None => return true,
+                },
+                None => inbox.next(&slot.queue),
+            };
+            let envelope = match event {
+                Event::Message(envelope) => envelope,
+                Event::Gate(change) => {
+                    state.gate.change(change);
+                    continue;
+                }
+                Event::Subscribe(_)
+                | Event::Control(..)
+                | Event::Routing(_)
+                | Event::Reconfigure(_) => {
+                    unreachable!("subscriptions are handled by the multiplier")
+                }
+                Event::Terminate => return false,
+            };
+            let offset = envelope.offset;
+            let fan_out = match envelope.fan_out {
+                true => Some(envelope.clone()),
+                false => None,
+            };
+            // Whether a durable writer persisted the message, see
+            // `Builder::durable_writer`.
+            let mut persisted = !shared.persist_first;
+            let consumer: &mut dyn CtxConsumer = match sink {
+                Sink::Whole(consumer) => consumer.as_mut(),
+                Sink::Mobile(consumer) => consumer.as_mut(),
+                Sink::Stream(consumer) => {
+                    persisted |=
+                        stream::handle(consumer.as_mut(), envelope, &mut inbox, slot, shared);
+                    if let (Some(envelope), true) = (fan_out, persisted) {
+                        shared.fan_out(envelope, slot.id);
+                    }
+                    PollerThread::done(offset, slot, shared);
+                    continue;
+                }
+            };
+            match state.assembler.assemble(slot.id, envelope) {
+                Some(envelope) if envelope.is_expired(shared.clock.now()) => {
+                    slot.expired.fetch_add(1, Ordering::SeqCst);
+                    shared.audit(AuditReason::Expired { consumer: slot.id }, &envelope);
+                    shared.dropped(Some(slot.id), DropReason::Expired, 1);
+                }
+                Some(envelope) => {
+                    let (handed, consumed) = handle(
+                        consumer,
+                        envelope,
+                        &mut state.gate,
+                        state.injector.as_mut(),
+                        slot,
+                        &carousel,
+                        namespace.as_ref(),
+                    );
+                    slot.consumed.fetch_add(handed as u64, Ordering::SeqCst);
+                    persisted |= consumed;
+                }
+                None => {}
             }
+            if let (Some(envelope), true) = (fan_out, persisted) {
+                shared.fan_out(envelope, slot.id);
+            }
+            PollerThread::done(offset, slot, shared);
         }
+    }
 
-        struct TestConsumer2;
+    /// Commits `offset` once the message at it was handled.
+    fn done(offset: Option<u64>, slot: &Slot, shared: &Shared) {
+        if let (Some(wal), Some(offset)) = (&shared.wal, offset) {
+            if let Err(e) = wal.commit(slot.id, offset) {
+                println!("Poller error committing offset {}: {}", offset, e);
+                shared.errors.emit(CarouselError::WalFailed {
+                    error: e.to_string(),
+                });
+            }
+        }
+        slot.dequeued();
+    }
+}
 
-        impl Consumer for TestConsumer2 {
-            fn consume(&mut self, data: Vec<u8>) {
-                assert_eq!(String::from_utf8(data).unwrap(), String::from("test"));
+/// `Route` is the multiplier's end of the queue of a poller.
+#[derive(Clone)]
+struct Route {
+    namespace: Option<sync::Arc<namespace::Tenant>>,
+    topic: Option<Selector>,
+    sample: Option<routing::Sample>,
+    slot: sync::Arc<Slot>,
+}
+
+impl Route {
+    fn namespace(&self) -> Option<&str> {
+        self.namespace.as_ref().map(|tenant| &*tenant.name)
+    }
+
+    /// Returns `true` if the consumer behind this route receives `envelope`.
+    /// Consumers without a topic receive every message of their namespace,
+    /// sampled consumers a random share of them.
+    fn accepts(&self, envelope: &Envelope) -> bool {
+        if self.namespace() != envelope.namespace() {
+            return false;
+        }
+        let routed = match (&self.topic, envelope.topic.as_deref()) {
+            (None, _) => true,
+            (Some(Selector::Topic(topic)), Some(put)) => topic == put,
+            (Some(Selector::Pattern(pattern)), Some(put)) => pattern.matches(put),
+            (Some(_), None) => false,
+        };
+        match &self.sample {
+            // All chunks of a message share its fate.
+            Some(sample) if routed => {
+                let draw = match envelope.chunk {
+                    Some(chunk) => chunk.message,
+                    None => self.slot.draws.fetch_add(1, Ordering::Relaxed),
+                };
+                sample.admits(draw ^ self.slot.seed)
             }
+            _ => routed,
         }
+    }
 
-        let mut v: Vec<Box<dyn Consumer + Send + 'static>> = Vec::new();
-        v.push(Box::new(TestConsumer1));
-        v.push(Box::new(TestConsumer2));
-        let c = Carousel::new(v);
+    fn forward(&self, envelope: Envelope, shared: &Shared) {
+        self.slot.enqueue(envelope, shared);
+    }
 
-        for _ in 1..10 {
-            let cloned_c = c.clone();
-            let t = std::thread::spawn(move || {
-                cloned_c.put(String::from("test").into_bytes());
-            });
+    /// Hands `pending[i]` to `routes[i]`, leaving the vectors of `pending`
+    /// empty but allocated for the next batch.
+    fn forward_all(routes: &[Route], pending: &mut [Vec<Envelope>], shared: &Shared) {
+        for (route, pending) in routes.iter().zip(pending.iter_mut()) {
+            route.slot.enqueue_batch(pending, shared);
+        }
+    }
+}
 
-            t.join().unwrap();
+/// Handles a single message on the poller thread of `consumer`, which puts
+/// derived messages on `carousel`, or into `namespace` if the consumer was
+/// subscribed within one. Returns whether the message was handed to the
+/// consumer, `false` if `gate` rejected it, and whether it was consumed
+/// successfully.
+fn handle(
+    consumer: &mut dyn CtxConsumer,
+    mut envelope: Envelope,
+    gate: &mut routing::Gate,
+    injector: Option<&mut fault::Injector>,
+    slot: &Slot,
+    carousel: &Carousel,
+    namespace: Option<&Namespace>,
+) -> (bool, bool) {
+    let shared = &carousel.shared;
+    if let (Some(store), Some(id)) = (&shared.dedup, envelope.id) {
+        if store.is_processed(slot.id, id) {
+            return (true, true);
         }
     }
+
+    let data = match envelope.spilled.take() {
+        Some(spilled) => match spilled.read() {
+            Ok(data) => data,
+            Err(e) => {
+                println!(
+                    "Consumer {} failed to read a spilled message: {}",
+                    slot.id, e
+                );
+                return (true, false);
+            }
+        },
+        None => unshare(envelope.data),
+    };
+    let data = match shared.verify(envelope.crc, data, slot) {
+        Some(data) => data,
+        None => return (true, false),
+    };
+    if !gate.admit(&data) {
+        if shared.audit.is_some() {
+            let filtered = Envelope {
+                data: sync::Arc::new(data),
+                ..envelope
+            };
+            shared.audit(AuditReason::Filtered { consumer: slot.id }, &filtered);
+        }
+        return (false, false);
+    }
+
+    let fault = match injector {
+        Some(injector) => injector.inject(shared.clock.as_ref()),
+        None => fault::Fault::None,
+    };
+    match fault {
+        fault::Fault::Drop => return (false, false),
+        fault::Fault::Panic => {
+            shared.report(ConsumeError {
+                consumer: slot.id,
+                attempts: 1,
+                data,
+                error: Box::new(InjectedPanic),
+            });
+            return (false, false);
+        }
+        fault::Fault::Duplicate | fault::Fault::None => {}
+    }
+
+    slot.handed(envelope.put, shared.clock.now(), data.len());
+    let ctx = Ctx {
+        carousel,
+        namespace,
+        consumer: slot.id,
+        id: envelope.id,
+        offset: envelope.offset,
+        topic: envelope.topic.as_deref(),
+        lineage: envelope.lineage.as_deref(),
+        hop: envelope.hop,
+    };
+    let mut copies = vec![data];
+    if fault == fault::Fault::Duplicate {
+        copies.push(copies[0].clone());
+    }
+    let mut consume = || {
+        let mut consumed = false;
+        for data in copies.drain(..) {
+            consumed |= deliver(consumer, &ctx, data, slot, shared);
+        }
+        consumed
+    };
+    // Messages put while consuming extend the lineage of this one.
+    let context = &envelope.context;
+    let consumed = lineage::scope(envelope.lineage.clone(), || {
+        match (&shared.propagator, context) {
+            (Some(propagator), Some(context)) => {
+                let mut consumed = false;
+                propagator.scope(context, &mut || consumed |= consume());
+                consumed
+            }
+            _ => consume(),
+        }
+    });
+
+    if let (true, Some(store), Some(id)) = (consumed, &shared.dedup, envelope.id) {
+        if let Err(e) = store.mark_processed(slot.id, id) {
+            println!(
+                "Consumer {} failed to mark message {} as processed: {}",
+                slot.id, id, e
+            );
+        }
+    }
+    (true, consumed)
+}
+
+/// Hands `data` to `consumer`, retrying failed attempts as configured with
+/// `Builder::retries` before routing the last error to the error handler.
+///
+/// Returns `true` if the message was consumed successfully.
+fn deliver(
+    consumer: &mut dyn CtxConsumer,
+    ctx: &Ctx<'_>,
+    data: Vec<u8>,
+    slot: &Slot,
+    shared: &Shared,
+) -> bool {
+    let retry = shared
+        .consume_timeout
+        .is_some_and(|(_, action)| action == TimeoutAction::Retry);
+    let keep = shared.retries > 0 || shared.error_handler.is_some();
+    let outcome = poller::attempts(data, shared.retries, keep, |attempts, data| {
+        match attempt(consumer, ctx, data, attempts, slot, shared) {
+            (Ok(()), false) => poller::Attempt::Settled(true),
+            (result, true) if !retry => poller::Attempt::Settled(result.is_ok()),
+            // Timed out attempts were reported by the watchdog already.
+            (_, true) => poller::Attempt::Failed(None),
+            (Err(error), false) => poller::Attempt::Failed(Some(error)),
+        }
+    });
+    match outcome {
+        Ok(consumed) => consumed,
+        Err(poller::Failure {
+            attempts,
+            error,
+            data: Some(data),
+        }) => {
+            shared.report(ConsumeError {
+                consumer: slot.id,
+                attempts,
+                data,
+                error,
+            });
+            false
+        }
+        Err(poller::Failure {
+            attempts, error, ..
+        }) => {
+            println!(
+                "Consumer {} failed to consume a message: {}",
+                slot.id, error
+            );
+            shared.errors.emit(CarouselError::ConsumeFailed {
+                consumer: slot.id,
+                attempts,
+                error: error.to_string(),
+            });
+            false
+        }
+    }
+}
+
+/// Calls `consumer` once, watched by the watchdog if `Builder::consume_timeout`
+/// is set and catching panics if `Builder::panic_policy` is. Returns the
+/// result of the call and whether it timed out.
+fn attempt(
+    consumer: &mut dyn CtxConsumer,
+    ctx: &Ctx<'_>,
+    data: Vec<u8>,
+    attempt: usize,
+    slot: &Slot,
+    shared: &Shared,
+) -> (Result<(), Error>, bool) {
+    watched(attempt, slot, shared, || consumer.consume_with(ctx, data))
+}
+
+/// Makes the call `call` to the consumer of `slot` like `attempt`, for any
+/// kind of consumer.
+fn watched<F>(attempt: usize, slot: &Slot, shared: &Shared, call: F) -> (Result<(), Error>, bool)
+where
+    F: FnOnce() -> Result<(), Error>,
+{
+    if shared.consume_timeout.is_some() {
+        *slot.call.lock().unwrap() = Some(Call {
+            started: shared.clock.now(),
+            attempt,
+            timed_out: false,
+        });
+    }
+    let result = caught(slot, shared, call);
+    let timed_out = shared.consume_timeout.is_some()
+        && slot
+            .call
+            .lock()
+            .unwrap()
+            .take()
+            .is_some_and(|call| call.timed_out);
+    (result, timed_out)
+}
+
+/// Makes the call `call` to the consumer of `slot`, catching a panic and
+/// reacting to it if `Builder::panic_policy` is set.
+fn caught<F>(slot: &Slot, shared: &Shared, call: F) -> Result<(), Error>
+where
+    F: FnOnce() -> Result<(), Error>,
+{
+    let mut panicked = None;
+    let result = match &shared.panic_policy {
+        Some(_) => poller::catch(call).unwrap_or_else(|error| {
+            panicked = Some(error.clone());
+            Err(Box::new(error))
+        }),
+        None => call(),
+    };
+
+    // Supervised consumers catch their own panics.
+    if let Some(panic) = poller::panic_of(&result) {
+        slot.panics.fetch_add(1, Ordering::SeqCst);
+        shared.errors.emit(CarouselError::ConsumerPanicked {
+            consumer: slot.id,
+            panic: panic.clone(),
+        });
+    }
+    if let (Some(policy), Some(error)) = (&shared.panic_policy, panicked) {
+        shared.panicked(slot.id, policy, &error);
+    }
+    result
+}
+
+/// `TimeoutAction` selects what becomes of a message whose consumer call
+/// timed out, see `Builder::consume_timeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimeoutAction {
+    /// The outcome of the call stands once it returns, but a failure is
+    /// neither retried nor reported again.
+    #[default]
+    Skip,
+    /// The call counts as failed, whatever it returns, and the message is
+    /// retried as configured with `Builder::retries`.
+    Retry,
+}
+
+/// `ConsumeTimeout` is the error reported for a consumer call that took
+/// longer than `Builder::consume_timeout`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsumeTimeout {
+    pub timeout: Duration,
+}
+
+impl fmt::Display for ConsumeTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "consume took longer than {:?}", self.timeout)
+    }
+}
+
+impl error::Error for ConsumeTimeout {}
+
+/// `Sharding` selects how messages are spread over the multiplier threads of a
+/// `Carousel` with more than one shard, see `Builder::multiplier_shards`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Sharding {
+    /// Messages are handed to the shards in turn.
+    #[default]
+    RoundRobin,
+    /// Messages are handed to a shard by the hash of their topic, so that all
+    /// messages on a topic pass through the same shard. Messages without a
+    /// topic pass through the first shard.
+    ByTopic,
+}
+
+/// `DropPolicy` selects what dropping a `Carousel` does with the messages
+/// that weren't consumed yet, see `Builder::drop_policy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DropPolicy {
+    /// Every message put is consumed before the threads are joined.
+    #[default]
+    Drain,
+    /// The messages waiting to be forwarded or consumed are discarded and
+    /// counted in `CarouselStats::discarded` and `ConsumerStats::discarded`,
+    /// then the threads are joined. Messages a poller already took off its
+    /// queue are still consumed. With persistence the discarded messages are
+    /// replayed when the `Carousel` is built again.
+    Discard,
+    /// The threads are left running in the background until the process
+    /// exits, consuming the messages put before and through other handles.
+    Detach,
+}
+
+/// `Multipier` is the set of multiplier threads of a `Carousel`, one per shard.
+struct Multipier {
+    threads: Vec<thread::JoinHandle<()>>,
+    /// Whether the threads were told to stop.
+    stopped: bool,
+}
+
+impl Multipier {
+    fn new(
+        subscriptions: Vec<Subscription>,
+        shared: &sync::Arc<Shared>,
+        replay: Vec<wal::Record>,
+        rxs: Vec<mpsc::Receiver<Event>>,
+    ) -> Multipier {
+        for subscription in subscriptions {
+            shared.subscribe(subscription, &replay);
+        }
+
+        let threads = rxs
+            .into_iter()
+            .enumerate()
+            .map(|(i, rx)| {
+                let name = format!("multiplier-{}", i);
+                let run = sync::Arc::clone(shared);
+                shared.spawn(name, move || Multipier::run(rx, &run))
+            })
+            .collect();
+
+        Multipier {
+            threads,
+            stopped: false,
+        }
+    }
+
+    fn run(rx: mpsc::Receiver<Event>, shared: &sync::Arc<Shared>) {
+        let held = shared.held.lock().unwrap();
+        drop(shared.released.wait_while(held, |held| *held).unwrap());
+
+        // The messages of a batch per route, kept across batches so that a
+        // steady stream doesn't allocate.
+        let mut pending = Vec::new();
+        multiplier::run(&rx, shared.wait, |batch| {
+            let messages = batch
+                .as_slice()
+                .iter()
+                .filter(|event| matches!(event, Event::Message(_)))
+                .count();
+            let running = Multipier::dispatch(batch, &mut pending, shared);
+            shared.in_transit.fetch_sub(messages, Ordering::SeqCst);
+            shared.progress.notify();
+            running
+        });
+    }
+
+    /// Forwards a batch of events in order. Messages are collected per route
+    /// and handed to each consumer queue in one go, so a burst costs every
+    /// poller a single wakeup. Returns `false` once `Terminate` was seen.
+    ///
+    /// `pending` holds a vector per route, resized only when the routes
+    /// change.
+    fn dispatch<I>(events: I, pending: &mut Vec<Vec<Envelope>>, shared: &sync::Arc<Shared>) -> bool
+    where
+        I: Iterator<Item = Event>,
+    {
+        // The position of the primary consumer among the routes, if any.
+        let primary = |routes: &[Route]| {
+            shared
+                .primary
+                .and_then(|id| routes.iter().position(|route| route.slot.id == id))
+        };
+        let mut routes = shared.routes.read().unwrap();
+        pending.resize_with(routes.len(), Vec::new);
+        let mut first = primary(&routes);
+        for event in events {
+            match event {
+                Event::Message(_) if shared.discarding.load(Ordering::SeqCst) => {
+                    shared.discarded.fetch_add(1, Ordering::SeqCst);
+                    shared.dropped(None, DropReason::Discarded, 1);
+                }
+                Event::Message(mut envelope) => {
+                    if let (Some(transform), None, None) =
+                        (&shared.transform, &envelope.chunk, &envelope.spilled)
+                    {
+                        let data = std::mem::take(sync::Arc::make_mut(&mut envelope.data));
+                        match transform(data) {
+                            Some(data) => {
+                                if envelope.crc.is_some() {
+                                    envelope.crc = Some(checksum::crc32(&data));
+                                }
+                                envelope.data = sync::Arc::new(data);
+                            }
+                            None => {
+                                shared.filtered.fetch_add(1, Ordering::SeqCst);
+                                continue;
+                            }
+                        }
+                    }
+                    if let Some(wal) = &shared.wal {
+                        let topic = envelope.topic.as_deref();
+                        let time = shared.wall_time(shared.clock.now());
+                        match wal.append(envelope.namespace(), topic, time, &envelope.data) {
+                            Ok(offset) => envelope.offset = Some(offset),
+                            Err(e) => {
+                                println!("Multiplier error persisting a message: {}", e);
+                                shared.errors.emit(CarouselError::WalFailed {
+                                    error: e.to_string(),
+                                });
+                            }
+                        }
+                    }
+                    if let (true, Some(topic), None) =
+                        (shared.retain, &envelope.topic, &envelope.chunk)
+                    {
+                        let retain = Envelope {
+                            offset: None,
+                            charge: None,
+                            quota: None,
+                            receipt: None,
+                            ..envelope.clone()
+                        };
+                        let key = (
+                            envelope.namespace().map(String::from),
+                            sync::Arc::clone(topic),
+                        );
+                        let mut retained = shared.retained.lock().unwrap();
+                        retained.insert(key, retain);
+                    }
+                    match first {
+                        // The primary hands the message on, see `Shared::fan_out`.
+                        Some(i) if envelope.chunk.is_none() && routes[i].accepts(&envelope) => {
+                            envelope.fan_out = true;
+                            pending[i].push(envelope);
+                        }
+                        _ => multiplier::broadcast(
+                            routes.iter().zip(pending.iter_mut()).enumerate(),
+                            envelope,
+                            |(i, (route, _)), envelope| {
+                                (Some(*i) != first || envelope.chunk.is_some())
+                                    && route.accepts(envelope)
+                            },
+                            |(_, (_, pending)), envelope| pending.push(envelope),
+                        ),
+                    }
+                }
+                Event::Subscribe(subscription) => {
+                    // The new consumer must not see messages that were put
+                    // before it subscribed, and subscribing needs the write
+                    // lock, so flush what we have first.
+                    Route::forward_all(&routes, pending, shared);
+                    drop(routes);
+                    shared.subscribe(subscription, &[]);
+                    routes = shared.routes.read().unwrap();
+                    pending.resize_with(routes.len(), Vec::new);
+                    first = primary(&routes);
+                }
+                Event::Control(id, control) => {
+                    // Like a subscription, a change applies to the messages
+                    // put after it.
+                    Route::forward_all(&routes, pending, shared);
+                    drop(routes);
+                    shared.control(id, control);
+                    routes = shared.routes.read().unwrap();
+                    pending.resize_with(routes.len(), Vec::new);
+                    first = primary(&routes);
+                }
+                Event::Routing(table) => {
+                    Route::forward_all(&routes, pending, shared);
+                    drop(routes);
+                    shared.apply_routing(table);
+                    routes = shared.routes.read().unwrap();
+                    pending.resize_with(routes.len(), Vec::new);
+                    first = primary(&routes);
+                }
+                Event::Reconfigure(delta) => {
+                    Route::forward_all(&routes, pending, shared);
+                    drop(routes);
+                    shared.reconfigure(delta);
+                    routes = shared.routes.read().unwrap();
+                    pending.resize_with(routes.len(), Vec::new);
+                    first = primary(&routes);
+                }
+                Event::Gate(_) => unreachable!("gates are handled by the pollers"),
+                Event::Terminate => {
+                    Route::forward_all(&routes, pending, shared);
+                    return false;
+                }
+            }
+        }
+        Route::forward_all(&routes, pending, shared);
+        true
+    }
+}
+
+/// `Carousel` represents a multi producer multi polling consumer data carousel. It enables
+/// message passing from multiple producers to multiple consumers asynchronously.
+///
+/// It accepts a vector of bytes as a message/ event.
+///
+/// A mp2c `Carousel` can be created for a list of consumers. However, each consumer
+/// is expected to implement the `Consumer` trait.
+///
+/// A multiplier thread is started which receives one end of an async channel.
+/// Each message `put` on the `Carousel` is sent to this multiplier thread, or to
+/// one of several with `Builder::multiplier_shards`. The job
+/// of the `Multiplier` is to clone each incoming event/ message and send it to each
+/// polling consumer.
+///
+/// For each consumer, a poller thread is started which waits on its own queue. Messages
+/// are pushed onto the queue by the `Multiplier` thread. The poller calls
+/// `Consumer::consume` on it's registered consumer.
+///
+/// An `Carousel` can be cloned and the clone creates a clone of the `Sender` from which it is
+/// cloned. When `Carousel::put` is called to send a message, it'll be sent to the pollers in
+/// the originating `Carousel`.
+///
+/// # Ordering
+///
+/// With a single multiplier thread, the default, ordering is strict: every
+/// consumer sees messages in exactly the order in which `put` calls were
+/// received by the `Carousel`, across all of its clones. In particular, messages
+/// put by the same producer are consumed in the order they were put. The single
+/// multiplier thread forwards each message to all pollers before it receives the
+/// next one, and each poller consumes its messages one at a time in the order
+/// they were forwarded.
+///
+/// With more multiplier threads, see `Builder::multiplier_shards`, a consumer
+/// sees the messages of different shards interleaved in any order. With
+/// `Sharding::ByTopic` the messages a producer puts on one topic keep their
+/// order; with `Sharding::RoundRobin` even the messages of one producer may be
+/// reordered.
+///
+/// With a primary consumer, see `Builder::primary` and
+/// `Builder::durable_writer`, the other consumers receive a message only once
+/// the primary returned from it, while the messages the primary doesn't
+/// receive, and the chunks of messages split by `Builder::chunk_size`, reach
+/// them right away. They may therefore see such a message before a message
+/// put earlier that is still with the primary.
+///
+/// `Builder::strict_ordering` rules sharding and a primary consumer out for
+/// applications that rely on strict ordering.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{Carousel, Consumer};
+///
+/// struct TestConsumer1;
+///
+/// impl Consumer for TestConsumer1 {
+///   fn consume(&mut self, data: Vec<u8>) {
+///     let msg = String::from_utf8(data).unwrap();
+///     // do something with msg
+///   }
+/// }
+///
+/// struct TestConsumer2;
+///
+/// impl Consumer for TestConsumer2 {
+///  fn consume(&mut self, data: Vec<u8>) {
+///    let msg = String::from_utf8(data).unwrap();
+///    // do something with msg   
+///  }
+/// }
+///
+/// let mut v: Vec<Box<dyn Consumer + Send + 'static>> = Vec::new();
+/// v.push(Box::new(TestConsumer1));
+/// v.push(Box::new(TestConsumer2));
+///
+/// let c = Carousel::new(v);
+///
+/// for _ in 1..10 {
+///   let cloned_c = c.clone();
+///   let t = std::thread::spawn(move || {
+///     cloned_c.put(String::from("test").into_bytes());
+///   });
+///   t.join().unwrap();
+/// }
+///
+/// ```
+pub struct Carousel {
+    shared: sync::Arc<Shared>,
+    producer: Option<sync::Arc<producer::Producer>>,
+    multiplier: Option<Multipier>,
+    sweeper: Option<Sweeper>,
+    watchdog: Option<Sweeper>,
+    compactor: Option<Sweeper>,
+    auditor: Option<audit::Auditor>,
+    /// The buffer of a handle of `Carousel::buffered`.
+    buffer: Option<sync::Arc<buffer::Buffer>>,
+}
+
+/// `RetainKey` is the namespace and topic of a retained message.
+type RetainKey = (Option<String>, sync::Arc<str>);
+
+/// `Shared` is the state shared by a `Carousel` and all of its clones.
+struct Shared {
+    shards: Vec<mpsc::Sender<Event>>,
+    propagator: Option<sync::Arc<dyn Propagator>>,
+    error_handler: Option<ErrorHandler>,
+    retries: usize,
+    dedup: Option<Box<dyn DedupStore>>,
+    wal: Option<wal::Wal>,
+    /// How long superseded messages stay in the log, see
+    /// `Builder::compact_log`.
+    compaction: Option<Duration>,
+    alerts: Option<sync::Arc<DepthAlerts>>,
+    retain: bool,
+    retained: sync::Mutex<HashMap<RetainKey, Envelope>>,
+    expired_retained: AtomicU64,
+    ttl: Option<Duration>,
+    next_id: AtomicUsize,
+    sharding: Sharding,
+    next_shard: AtomicUsize,
+    wait: WaitStrategy,
+    budget: Option<sync::Arc<budget::Budget>>,
+    /// The memory limits of the consumers, see
+    /// `Builder::consumer_memory_limit`.
+    consumer_budgets: HashMap<usize, sync::Arc<budget::Budget>>,
+    chunk_size: Option<usize>,
+    /// The number of the next chunked message, locked while its chunks are
+    /// sent so that they follow one another on their shard.
+    next_message: sync::Mutex<u64>,
+    thread_name: Option<String>,
+    routes: sync::RwLock<Vec<Route>>,
+    pollers: sync::Mutex<Vec<PollerThread>>,
+    namespaces: sync::Mutex<HashMap<String, sync::Arc<namespace::Tenant>>>,
+    producers: sync::Mutex<HashMap<String, sync::Arc<producer::Producer>>>,
+    validator: Option<validate::Validator>,
+    rejected: AtomicU64,
+    transform: Option<Transform>,
+    filtered: AtomicU64,
+    checksums: bool,
+    signer: Option<sync::Arc<dyn KeyProvider>>,
+    audit: Option<mpsc::Sender<Option<AuditRecord>>>,
+    throughput: metrics::Window,
+    clock: sync::Arc<dyn Clock>,
+    /// An instant of `clock` and the wall-clock time at that instant, which
+    /// map the times of the clock to the times in the write-ahead log.
+    wall: (Instant, SystemTime),
+    faults: Option<Faults>,
+    idle_timeout: Option<Duration>,
+    consume_timeout: Option<(Duration, TimeoutAction)>,
+    panic_policy: Option<PanicPolicy>,
+    drop_policy: DropPolicy,
+    on_shutdown: Option<ShutdownHandler>,
+    started: Instant,
+    /// The ids of the consumers handed each message first, in order, see
+    /// `Builder::dispatch_order`.
+    dispatch_order: Vec<usize>,
+    primary: Option<usize>,
+    /// The shutdown phases of the consumers, see `Builder::shutdown_phase`.
+    shutdown_phases: HashMap<usize, u32>,
+    /// Whether the primary only hands on the messages it consumed
+    /// successfully, see `Builder::durable_writer`.
+    persist_first: bool,
+    /// Whether the messages still arriving are discarded, see
+    /// `DropPolicy::Discard`.
+    discarding: AtomicBool,
+    discarded: AtomicU64,
+    /// The messages sent to the multipliers but not yet dispatched.
+    in_transit: AtomicUsize,
+    /// The name of the `Carousel` in the lineage of its messages, see
+    /// `Builder::lineage`.
+    stage: Option<sync::Arc<str>>,
+    /// The receivers of `Carousel::errors`.
+    errors: errors::Listeners,
+    /// Whether new messages are refused, see `Carousel::quiesce`.
+    quiesced: AtomicBool,
+    /// Whether a consumer panic shut the `Carousel` down, see
+    /// `PanicPolicy::ShutdownCarousel`. A closed `Carousel` is quiesced too.
+    closed: AtomicBool,
+    /// The puts in progress.
+    putting: AtomicUsize,
+    /// Whether the multipliers wait for `Carousel::start`, see
+    /// `Builder::start_paused`.
+    held: sync::Mutex<bool>,
+    released: sync::Condvar,
+    /// Wakes `Carousel::quiesce` and the feeders as the messages move on.
+    progress: sync::Arc<progress::Progress>,
+}
+
+/// Wakes the threads still waiting for progress of the dropped `Carousel`.
+impl Drop for Shared {
+    fn drop(&mut self) {
+        self.progress.notify();
+    }
+}
+
+impl Shared {
+    /// Starts a poller for `subscription` and adds its route. In retained mode
+    /// the consumer first receives the retained messages it subscribed to.
+    fn subscribe(self: &sync::Arc<Self>, subscription: Subscription, replay: &[wal::Record]) {
+        let (route, poller) = PollerThread::spawn(subscription, self, replay);
+
+        let now = self.clock.now();
+        let retained = self.retained.lock().unwrap();
+        retained
+            .values()
+            .filter(|envelope| route.accepts(envelope) && !envelope.is_expired(now))
+            .for_each(|envelope| route.forward(envelope.clone(), self));
+        drop(retained);
+
+        let mut routes = self.routes.write().unwrap();
+        routes.push(route);
+        if !self.dispatch_order.is_empty() {
+            let order = &self.dispatch_order;
+            let rank = |id| order.iter().position(|&o| o == id).unwrap_or(order.len());
+            routes.sort_by_key(|route| rank(route.slot.id));
+        }
+        drop(routes);
+        self.pollers.lock().unwrap().push(poller);
+    }
+
+    /// Hands `envelope`, which the primary consumer `primary` returned from,
+    /// to the other consumers, see `Builder::primary`.
+    fn fan_out(&self, mut envelope: Envelope, primary: usize) {
+        envelope.fan_out = false;
+        let routes = self.routes.read().unwrap();
+        multiplier::broadcast(
+            routes.iter(),
+            envelope,
+            |route, envelope| route.slot.id != primary && route.accepts(envelope),
+            |route, envelope| route.forward(envelope, self),
+        );
+    }
+
+    /// Hands a copy of `envelope` to the audit consumer, if any.
+    fn audit(&self, reason: AuditReason, envelope: &Envelope) {
+        let audit = match &self.audit {
+            Some(audit) => audit,
+            None => return,
+        };
+        let data = match &envelope.spilled {
+            Some(spilled) => spilled.read().unwrap_or_else(|e| {
+                println!("Carousel failed to read a spilled message to audit: {}", e);
+                Vec::new()
+            }),
+            None => envelope.data.to_vec(),
+        };
+        let record = AuditRecord {
+            reason,
+            topic: envelope.topic.as_deref().map(String::from),
+            namespace: envelope.namespace().map(String::from),
+            data,
+        };
+        let _ = audit.send(Some(record));
+    }
+
+    /// Hands `error` to the error handler, or prints it without one.
+    fn report(&self, error: ConsumeError) {
+        // Timeouts are sent by the watchdog as `CarouselError::TimedOut`.
+        if !error.error.is::<ConsumeTimeout>() {
+            self.errors.emit(CarouselError::ConsumeFailed {
+                consumer: error.consumer,
+                attempts: error.attempts,
+                error: error.error.to_string(),
+            });
+        }
+        match &self.error_handler {
+            Some(handler) => handler(error),
+            None => println!("Consumer {}", error),
+        }
+    }
+
+    /// Returns `data`, unless it doesn't match the checksum `crc` it was
+    /// stamped with. A corrupted message is reported as a `ChecksumError`
+    /// without being handed to the consumer.
+    fn verify(&self, crc: Option<u32>, data: Vec<u8>, slot: &Slot) -> Option<Vec<u8>> {
+        let crc = match crc {
+            Some(crc) => crc,
+            None => return Some(data),
+        };
+        match checksum::verify(crc, &data) {
+            Ok(()) => Some(data),
+            Err(e) => {
+                slot.corrupted.fetch_add(1, Ordering::SeqCst);
+                self.report(ConsumeError {
+                    consumer: slot.id,
+                    attempts: 0,
+                    data,
+                    error: Box::new(e),
+                });
+                None
+            }
+        }
+    }
+
+    /// Returns the producer `name`, which is created without a quota on first
+    /// use.
+    fn producer(&self, name: &str) -> sync::Arc<producer::Producer> {
+        let mut producers = self.producers.lock().unwrap();
+        let producer = producers.entry(String::from(name)).or_insert_with(|| {
+            sync::Arc::new(producer::Producer::new(name, ProducerQuota::default()))
+        });
+        sync::Arc::clone(producer)
+    }
+
+    /// Sends a `CarouselError::Dropped` unless `count` is `0`.
+    fn dropped(&self, consumer: Option<usize>, reason: DropReason, count: u64) {
+        if count > 0 {
+            self.errors.emit(CarouselError::Dropped {
+                consumer,
+                reason,
+                count,
+            });
+        }
+    }
+
+    /// Returns whether no message is being put, on its way to the consumer
+    /// queues, or waiting in one.
+    fn is_drained(&self) -> bool {
+        // The order matters: a put counts towards `in_transit` before it is
+        // done, and a message is queued before it leaves `in_transit`.
+        self.putting.load(Ordering::SeqCst) == 0
+            && self.in_transit.load(Ordering::SeqCst) == 0
+            && self
+                .routes
+                .read()
+                .unwrap()
+                .iter()
+                .all(|route| route.slot.depth.load(Ordering::SeqCst) == 0)
+    }
+
+    /// Returns the wall-clock time at the instant `at` of the clock, see
+    /// `Builder::clock`.
+    fn wall_time(&self, at: Instant) -> SystemTime {
+        let (instant, time) = self.wall;
+        time + at.saturating_duration_since(instant)
+    }
+
+    /// Returns the namespace `name`, which is created on first use.
+    fn tenant(&self, name: &str) -> sync::Arc<namespace::Tenant> {
+        let mut namespaces = self.namespaces.lock().unwrap();
+        let tenant = namespaces
+            .entry(String::from(name))
+            .or_insert_with(|| sync::Arc::new(namespace::Tenant::new(name, None)));
+        sync::Arc::clone(tenant)
+    }
+
+    /// Returns the shutdown phase of the consumer `id`, see
+    /// `Builder::shutdown_phase`.
+    fn shutdown_phase(&self, id: usize) -> u32 {
+        self.shutdown_phases.get(&id).copied().unwrap_or(0)
+    }
+
+    /// Spawns a thread of the `Carousel`, named `<prefix>-<role>` if a prefix
+    /// was set with `Builder::thread_name`.
+    fn spawn<F, T>(&self, role: String, f: F) -> thread::JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut builder = thread::Builder::new();
+        if let Some(prefix) = &self.thread_name {
+            builder = builder.name(format!("{}-{}", prefix, role));
+        }
+        builder.spawn(f).expect("failed to spawn a carousel thread")
+    }
+
+    /// Applies `control` to the consumer `id`, unless it was removed.
+    ///
+    /// Only the multipliers apply controls. Other threads submit them, see
+    /// `Shared::submit`, as a multiplier waiting on the memory limit of a
+    /// consumer holds the routes until that consumer catches up.
+    fn control(&self, id: usize, control: Control) {
+        let mut routes = self.routes.write().unwrap();
+        let i = match routes.iter().position(|route| route.slot.id == id) {
+            Some(i) => i,
+            None => return,
+        };
+        match control {
+            Control::Pause => routes[i].slot.queue.set_paused(true),
+            Control::Resume => routes[i].slot.queue.set_paused(false),
+            Control::Filter(filter) => {
+                let change = routing::GateChange::Filter(filter);
+                routes[i].slot.push(Event::Gate(change));
+            }
+            Control::Remove => {
+                let route = routes.remove(i);
+                route.slot.queue.set_paused(false);
+                route.slot.push(Event::Terminate);
+                self.progress.notify();
+            }
+        }
+    }
+
+    /// Applies all entries of `table` while holding the routes, so that the
+    /// multipliers see either none or all of them.
+    fn apply_routing(&self, table: RoutingTable) {
+        let mut routes = self.routes.write().unwrap();
+        for (id, entry) in table.entries {
+            let route = match routes.iter_mut().find(|route| route.slot.id == id.0) {
+                Some(route) => route,
+                None => continue,
+            };
+            if let Some(topic) = entry.topic {
+                route.topic = topic;
+            }
+            if let Some(filter) = entry.filter {
+                let change = routing::GateChange::Filter(filter);
+                route.slot.push(Event::Gate(change));
+            }
+            if let Some(per_second) = entry.rate_limit {
+                let change = routing::GateChange::RateLimit(per_second);
+                route.slot.push(Event::Gate(change));
+            }
+            if let Some(sample) = entry.sample {
+                route.sample = sample;
+            }
+        }
+    }
+
+    /// Applies `delta` to the budget and to the gates of the consumers.
+    fn reconfigure(&self, delta: ConfigDelta) {
+        if let Some(budget) = &self.budget {
+            if let Some(capacity) = delta.capacity {
+                budget.set_limit(capacity);
+            }
+            if let Some(overflow) = delta.overflow {
+                budget.set_overflow(overflow);
+            }
+        }
+        let table = delta
+            .rate_limits
+            .into_iter()
+            .fold(RoutingTable::new(), |table, (id, per_second)| {
+                table.rate_limit(SubscriptionId(id), per_second)
+            });
+        self.apply_routing(table);
+    }
+
+    /// Hands a change to the consumers to the first multiplier, which applies
+    /// it in order with the messages. Once the multipliers stopped the
+    /// `Carousel` is shutting down and the change is ignored.
+    fn submit(&self, event: Event) {
+        let _ = self.shards[0].send(event);
+    }
+
+    /// Reacts to a panic of the consumer `id` as directed by `policy`.
+    fn panicked(&self, id: usize, policy: &PanicPolicy, error: &ConsumerPanic) {
+        match policy {
+            PanicPolicy::Restart => {}
+            PanicPolicy::RemoveConsumer => self.submit(Event::Control(id, Control::Remove)),
+            PanicPolicy::ShutdownCarousel => {
+                println!("Consumer {} panicked, shutting the carousel down.", id);
+                self.closed.store(true, Ordering::SeqCst);
+                self.quiesced.store(true, Ordering::SeqCst);
+                let ids: Vec<usize> = self
+                    .routes
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .map(|route| route.slot.id)
+                    .collect();
+                // The multipliers apply the removals and stop after the
+                // messages put before, without this poller taking the routes.
+                for id in ids {
+                    self.submit(Event::Control(id, Control::Remove));
+                }
+                for shard in &self.shards {
+                    let _ = shard.send(Event::Terminate);
+                }
+            }
+            PanicPolicy::Callback(handler) => handler(SubscriptionId(id), error),
+        }
+    }
+
+    /// Reports the consumers whose current call started `consume_timeout`
+    /// or longer before `now`.
+    fn watch(&self, now: Instant) {
+        let timeout = match self.consume_timeout {
+            Some((timeout, _)) => timeout,
+            None => return,
+        };
+        let timed_out: Vec<(usize, usize)> = self
+            .routes
+            .read()
+            .unwrap()
+            .iter()
+            .filter_map(|route| {
+                let slot = &route.slot;
+                let mut call = slot.call.lock().unwrap();
+                match &mut *call {
+                    Some(call) if !call.timed_out && now >= call.started + timeout => {
+                        call.timed_out = true;
+                        slot.timed_out.fetch_add(1, Ordering::SeqCst);
+                        Some((slot.id, call.attempt))
+                    }
+                    _ => None,
+                }
+            })
+            .collect();
+        for (consumer, attempts) in timed_out {
+            self.errors
+                .emit(CarouselError::TimedOut { consumer, timeout });
+            self.report(ConsumeError {
+                consumer,
+                attempts,
+                data: Vec::new(),
+                error: Box::new(ConsumeTimeout { timeout }),
+            });
+        }
+    }
+
+    /// Removes messages that expired before `now` from all queues and from
+    /// the retained messages.
+    fn sweep(&self, now: Instant) {
+        for route in self.routes.read().unwrap().iter() {
+            route.slot.sweep(now, self);
+        }
+
+        let mut retained = self.retained.lock().unwrap();
+        let len = retained.len();
+        retained.retain(|_, envelope| !envelope.is_expired(now));
+        let swept = len - retained.len();
+        self.expired_retained
+            .fetch_add(swept as u64, Ordering::SeqCst);
+    }
+
+    /// Compacts the write-ahead log, see `Builder::compact_log`.
+    fn compact(&self, now: Instant) {
+        let (wal, horizon) = match (&self.wal, self.compaction) {
+            (Some(wal), Some(horizon)) => (wal, horizon),
+            _ => return,
+        };
+        let horizon = self
+            .wall_time(now)
+            .checked_sub(horizon)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        if let Err(e) = wal.compact(horizon) {
+            println!("Carousel error compacting the write-ahead log: {}", e);
+            self.errors.emit(CarouselError::WalFailed {
+                error: e.to_string(),
+            });
+        }
+    }
+}
+
+/// `Sweeper` is a housekeeping thread that periodically sweeps expired
+/// messages out of the queues and retained messages of a `Carousel`, or
+/// watches consumers for calls that take too long.
+struct Sweeper {
+    stop: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Sweeper {
+    fn new(
+        role: &str,
+        interval: Duration,
+        shared: sync::Arc<Shared>,
+        task: fn(&Shared, Instant),
+    ) -> Sweeper {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = sync::Arc::clone(&shared).spawn(String::from(role), move || {
+            while let Err(mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                task(&shared, shared.clock.now());
+            }
+        });
+
+        Sweeper {
+            stop: Some(stop),
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Sweeper {
+    fn drop(&mut self) {
+        self.stop.take();
+        if let Some(thread) = self.thread.take() {
+            join(thread, "housekeeping");
+        }
+    }
+}
+
+/// Waits for a thread of the `Carousel` to exit. A thread that panicked, such
+/// as the poller of a consumer that panicked without a `PanicPolicy`, is
+/// reported instead of panicking the thread that joins it, which is often
+/// dropping the `Carousel`.
+fn join(thread: thread::JoinHandle<()>, role: &str) {
+    if thread.join().is_err() {
+        println!("A {} thread of the carousel panicked.", role);
+    }
+}
+
+impl Carousel {
+    /// Creates a new `Carousel` for a vector of consumers.
+    pub fn new<T>(consumers: Vec<Box<T>>) -> Carousel
+    where
+        T: Consumer + Send + ?Sized + 'static,
+    {
+        consumers
+            .into_iter()
+            .fold(Builder::new(), |builder, consumer| {
+                builder.consumer(consumer)
+            })
+            .build()
+    }
+
+    /// Creates a `Carousel` with `consumers` and the settings in `config`,
+    /// including its rate limits.
+    pub fn from_config<T>(config: &CarouselConfig, consumers: Vec<T>) -> Carousel
+    where
+        T: CtxConsumer + Send + 'static,
+    {
+        let carousel = consumers
+            .into_iter()
+            .fold(Builder::new(), |builder, consumer| {
+                builder.consumer(consumer)
+            })
+            .config(config)
+            .build();
+        let table = config
+            .rate_limits
+            .iter()
+            .fold(RoutingTable::new(), |table, (&id, &per_second)| {
+                table.rate_limit(SubscriptionId(id), Some(per_second))
+            });
+        carousel.apply_routing(table);
+        carousel
+    }
+
+    /// Returns a `Builder` to configure a `Carousel` beyond its consumers.
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    /// Puts a message on the `Carousel` which will be asynchronously
+    /// sent to all it's consumers.
+    pub fn put(&self, data: Vec<u8>) {
+        self.send(self.envelope(data));
+    }
+
+    /// Puts a message on `topic`. It is sent to all consumers subscribed to
+    /// `topic` and to all consumers without a topic.
+    pub fn put_to(&self, topic: &str, data: Vec<u8>) {
+        let envelope = Envelope {
+            topic: Some(sync::Arc::from(topic)),
+            ..self.envelope(data)
+        };
+        self.send(envelope);
+    }
+
+    /// Puts a message like `Carousel::put`, but returns the error of the
+    /// validator registered with `Builder::validator` if it rejects the
+    /// message, `PutError::Quiesced` once the `Carousel` was quiesced, or
+    /// `PutError::Closed` once a consumer panic shut it down.
+    /// `Carousel::put` prints the error instead.
+    pub fn try_put(&self, data: Vec<u8>) -> Result<(), PutError> {
+        self.try_send(self.envelope(data))
+    }
+
+    /// Puts a message on `topic` like `Carousel::put_to`, but returns the
+    /// error of the validator if it rejects the message.
+    pub fn try_put_to(&self, topic: &str, data: Vec<u8>) -> Result<(), PutError> {
+        let envelope = Envelope {
+            topic: Some(sync::Arc::from(topic)),
+            ..self.envelope(data)
+        };
+        self.try_send(envelope)
+    }
+
+    /// Puts a message forwarded by a `Bridge` on `topic`.
+    pub(crate) fn put_hop(
+        &self,
+        topic: &str,
+        data: Vec<u8>,
+        hop: bridge::Hop,
+    ) -> Result<(), PutError> {
+        let envelope = Envelope {
+            topic: Some(sync::Arc::from(topic)),
+            hop: Some(hop),
+            ..self.envelope(data)
+        };
+        self.try_send(envelope)
+    }
+
+    /// Puts a message like `Carousel::put` and returns a `Receipt` that is
+    /// done once every consumer is done with the message.
+    pub fn put_tracked(&self, data: Vec<u8>) -> Receipt {
+        let (receipt, ticket) = Receipt::new();
+        let mut envelope = self.envelope(data);
+        envelope.receipt = Some(ticket);
+        self.send(envelope);
+        receipt
+    }
+
+    /// Puts a message with a producer assigned `id` on the `Carousel`.
+    ///
+    /// With `Builder::exactly_once` each consumer consumes at most one message
+    /// per id, so producers can safely re-put messages, e.g. after a restart.
+    pub fn put_with_id(&self, id: u64, data: Vec<u8>) {
+        let envelope = Envelope {
+            id: Some(id),
+            ..self.envelope(data)
+        };
+        self.send(envelope);
+    }
+
+    /// Puts the concatenation of `slices` as a single message, e.g. a header
+    /// and a body, allocating and copying the payload only once.
+    pub fn put_vectored(&self, slices: &[&[u8]]) {
+        self.put(slices.concat());
+    }
+
+    /// Returns a receiver of the errors of the `Carousel` from now on, such as
+    /// panicking consumers, failed and dropped messages, and timed out calls,
+    /// see `CarouselError`. Every receiver gets every error; a receiver that
+    /// isn't read keeps them all in memory.
+    pub fn errors(&self) -> mpsc::Receiver<CarouselError> {
+        self.shared.errors.listen()
+    }
+
+    /// Lets a `Carousel` built with `Builder::start_paused` forward the
+    /// messages put so far and from now on. Starting a running `Carousel`
+    /// does nothing.
+    pub fn start(&self) {
+        *self.shared.held.lock().unwrap() = false;
+        self.shared.released.notify_all();
+    }
+
+    /// Stops the `Carousel` from taking new messages and returns a `Receipt`
+    /// that is done once the consumers consumed every message put before,
+    /// e.g. to hand over to a new instance without losing messages in a
+    /// rolling restart.
+    ///
+    /// From the call on every put by any handle, including the puts of
+    /// consumers through their `Ctx`, is refused: `Carousel::try_put` returns
+    /// `PutError::Quiesced` and `Carousel::put` prints it. A paused consumer
+    /// holds back the receipt until it is resumed. Quiescing a `Carousel`
+    /// again returns another receipt for the same messages.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::{Carousel, PutError};
+    /// use std::sync::mpsc;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let c = Carousel::builder().consumer(tx).build();
+    /// c.put(String::from("before").into_bytes());
+    ///
+    /// c.quiesce().wait();
+    /// assert_eq!(rx.try_recv().unwrap(), b"before".to_vec());
+    /// assert_eq!(c.try_put(Vec::new()), Err(PutError::Quiesced));
+    /// ```
+    pub fn quiesce(&self) -> Receipt {
+        self.shared.quiesced.store(true, Ordering::SeqCst);
+        let (receipt, ticket) = Receipt::new();
+        let shared = sync::Arc::downgrade(&self.shared);
+        let progress = sync::Arc::clone(&self.shared.progress);
+        self.shared.spawn(String::from("quiesce"), move || {
+            // The receipt is done as well when the `Carousel` is gone.
+            progress.wait_until(|| shared.upgrade().is_none_or(|shared| shared.is_drained()));
+            drop(ticket);
+        });
+        receipt
+    }
+
+    /// Hands the messages persisted within `range` to the consumer `id`
+    /// again, e.g. to rebuild an index it maintains, and returns how many
+    /// there were. Like the live messages, only those put on the topics and
+    /// into the namespace of the consumer are replayed. The other consumers
+    /// don't see them, and the offset of the consumer isn't changed by them.
+    /// The replayed messages are queued at once, in between the live ones.
+    ///
+    /// Fails unless the `Carousel` has a write-ahead log, see
+    /// `Builder::persistence`, and `id` is a running consumer.
+    ///
+    /// # Example
+    /// ```no_run
+    /// use mp2c::asynch::{Carousel, SubscriptionId};
+    /// use std::sync::mpsc;
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let c = Carousel::builder()
+    ///   .consumer(tx)
+    ///   .persistence("/var/lib/app/wal")
+    ///   .unwrap()
+    ///   .build();
+    /// let hour_ago = SystemTime::now() - Duration::from_secs(3600);
+    /// let replayed = c.replay(hour_ago.., SubscriptionId(0)).unwrap();
+    /// println!("replaying {} messages", replayed);
+    /// ```
+    pub fn replay<R>(&self, range: R, id: SubscriptionId) -> io::Result<usize>
+    where
+        R: RangeBounds<SystemTime>,
+    {
+        let wal = self.shared.wal.as_ref().ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::Unsupported,
+                "the carousel has no write-ahead log",
+            )
+        })?;
+        let route = self
+            .shared
+            .routes
+            .read()
+            .unwrap()
+            .iter()
+            .find(|route| route.slot.id == id.0)
+            .cloned()
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("no consumer {}", id.0))
+            })?;
+        let mut replayed = 0;
+        for record in wal.read(range)? {
+            let envelope = Envelope {
+                offset: None,
+                ..Envelope::replayed(&record, &self.shared)
+            };
+            if route.accepts(&envelope) {
+                route.forward(envelope, &self.shared);
+                replayed += 1;
+            }
+        }
+        Ok(replayed)
+    }
+
+    /// Moves the messages waiting in the consumer queues to `writer` and
+    /// returns how many there were, e.g. to hand them to the process taking
+    /// over in a blue/green deployment, see `Carousel::import`. Messages the
+    /// pollers already took off their queues, up to 64 per consumer, and the
+    /// chunks of split messages stay and are consumed as usual. Pause the
+    /// consumers or stop putting messages first to export a consistent
+    /// backlog.
+    ///
+    /// The export starts with the bytes `MP2C` and the format version 1,
+    /// followed by a frame per message and consumer: the id of the consumer
+    /// as a little endian `u32`, the namespace and the topic of the message,
+    /// each as a `u16` length followed by UTF-8 bytes, or `u16::MAX` without
+    /// one, and the payload as a `u32` length followed by its bytes.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::{Carousel, SubscriptionId};
+    /// use std::sync::mpsc;
+    ///
+    /// let (tx, _rx) = mpsc::channel();
+    /// let old = Carousel::builder().consumer(tx).build();
+    /// old.pause(SubscriptionId(0));
+    /// old.put(String::from("test").into_bytes());
+    /// # while old.stats().consumers[0].depth == 0 {
+    /// #   std::thread::sleep(std::time::Duration::from_millis(1));
+    /// # }
+    /// let mut export = Vec::new();
+    /// assert_eq!(old.export_pending(&mut export).unwrap(), 1);
+    /// drop(old);
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let new = Carousel::builder().consumer(tx).build();
+    /// assert_eq!(new.import(&export[..]).unwrap(), 1);
+    /// drop(new);
+    /// assert_eq!(rx.recv().unwrap(), b"test");
+    /// ```
+    pub fn export_pending<W: io::Write>(&self, mut writer: W) -> io::Result<usize> {
+        let mut exported = Vec::new();
+        for route in self.shared.routes.read().unwrap().iter() {
+            let slot = &route.slot;
+            let removed = slot.queue.retain(|event| match event {
+                Event::Message(envelope) if envelope.chunk.is_none() => {
+                    let data = match &envelope.spilled {
+                        Some(spilled) => match spilled.read() {
+                            Ok(data) => data,
+                            Err(_) => return true,
+                        },
+                        None => envelope.data.to_vec(),
+                    };
+                    exported.push(export::Pending {
+                        consumer: slot.id as u32,
+                        namespace: envelope.namespace().map(String::from),
+                        topic: envelope.topic.as_deref().map(String::from),
+                        data,
+                    });
+                    false
+                }
+                _ => true,
+            });
+            for _ in 0..removed {
+                slot.dequeued();
+            }
+        }
+
+        export::write_header(&mut writer)?;
+        for pending in &exported {
+            export::write(&mut writer, pending)?;
+        }
+        writer.flush()?;
+        Ok(exported.len())
+    }
+
+    /// Queues the messages exported by `Carousel::export_pending` for the
+    /// consumers with the same ids and returns how many there were. The
+    /// messages go to their consumer only: they aren't validated, persisted
+    /// or routed again.
+    ///
+    /// Fails if the export is malformed or names a consumer that isn't
+    /// running, in which case the messages before are imported already.
+    pub fn import<R: io::Read>(&self, reader: R) -> io::Result<usize> {
+        let mut reader = io::BufReader::new(reader);
+        export::read_header(&mut reader)?;
+        let mut imported = 0;
+        while let Some(pending) = export::read(&mut reader)? {
+            let route = self
+                .shared
+                .routes
+                .read()
+                .unwrap()
+                .iter()
+                .find(|route| route.slot.id == pending.consumer as usize)
+                .cloned()
+                .ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no consumer {}", pending.consumer),
+                    )
+                })?;
+            let envelope = Envelope {
+                topic: pending.topic.map(sync::Arc::from),
+                namespace: pending.namespace.map(|name| self.shared.tenant(&name)),
+                ..self.envelope(pending.data)
+            };
+            route.forward(envelope, &self.shared);
+            imported += 1;
+        }
+        Ok(imported)
+    }
+
+    /// Subscribes `consumer` to `topic` on the running `Carousel` and returns
+    /// the id identifying the consumer. In retained mode the consumer first
+    /// receives the last message put on `topic`, if any.
+    pub fn subscribe<T>(&self, topic: &str, consumer: T) -> SubscriptionId
+    where
+        T: CtxConsumer + Send + 'static,
+    {
+        self.subscription(None, Some(Selector::Topic(String::from(topic))), consumer)
+    }
+
+    /// Subscribes `consumer` to all topics matching `pattern` on the running
+    /// `Carousel`, like `Carousel::subscribe`.
+    pub fn subscribe_pattern<T>(&self, pattern: TopicPattern, consumer: T) -> SubscriptionId
+    where
+        T: CtxConsumer + Send + 'static,
+    {
+        self.subscription(None, Some(Selector::Pattern(pattern)), consumer)
+    }
+
+    /// Subscribes `consumer` to every message put from now on.
+    #[cfg(feature = "ffi")]
+    pub(crate) fn subscribe_all<T>(&self, consumer: T) -> SubscriptionId
+    where
+        T: CtxConsumer + Send + 'static,
+    {
+        self.subscription(None, None, consumer)
+    }
+
+    fn subscription<T>(
+        &self,
+        namespace: Option<&sync::Arc<namespace::Tenant>>,
+        topic: Option<Selector>,
+        consumer: T,
+    ) -> SubscriptionId
+    where
+        T: CtxConsumer + Send + 'static,
+    {
+        let id = self.shared.next_id.fetch_add(1, Ordering::SeqCst);
+        let subscription = Subscription {
+            id,
+            namespace: namespace.cloned(),
+            topic,
+            sample: None,
+            factory: Box::new(move || Sink::Mobile(Box::new(consumer))),
+        };
+        self.submit(Event::Subscribe(subscription));
+        SubscriptionId(id)
+    }
+
+    /// Returns a handle to the namespace `name`, which is created on first
+    /// use. See `Namespace`.
+    pub fn namespace(&self, name: &str) -> Namespace {
+        Namespace {
+            carousel: self.clone(),
+            tenant: self.shared.tenant(name),
+        }
+    }
+
+    /// Returns a handle to the `Carousel`, like a clone, that puts messages as
+    /// the producer `name`. Messages put by all handles of a producer, and
+    /// their clones, count against the producer's quota, see
+    /// `Builder::producer_quota`, and are attributed to it in
+    /// `CarouselStats::producers`.
+    pub fn clone_as(&self, name: &str) -> Carousel {
+        let producer = self.shared.producer(name);
+        let mut carousel = Carousel::handle(&self.shared);
+        carousel.producer = Some(producer);
+        carousel
+    }
+
+    /// Returns a handle to the `Carousel`, like a clone, that buffers up to
+    /// `capacity` messages while the `Carousel` is paused, see
+    /// `Builder::start_paused`, quiesced or backpressured, see
+    /// `Builder::depth_alert`, and sends them in order once it is writable
+    /// again. Messages are validated when they are sent. Clones of the handle
+    /// share its buffer; a full buffer refuses messages with
+    /// `PutError::BufferFull`.
+    ///
+    /// A thread of its own flushes the buffer, until the handles are dropped
+    /// and the buffer is empty. Messages still buffered when the `Carousel`
+    /// stops, or stays quiesced once the handles are dropped, are lost.
+    pub fn buffered(&self, capacity: usize) -> Carousel {
+        assert!(capacity > 0);
+        let mut carousel = self.clone();
+        carousel.buffer = Some(buffer::Buffer::spawn(self, capacity));
+        carousel
+    }
+
+    /// Changes the quota of the producer `name`, e.g. to throttle a noisy
+    /// producer found in `CarouselStats::producers`.
+    pub fn set_producer_quota(&self, name: &str, quota: ProducerQuota) {
+        self.shared.producer(name).set_quota(quota);
+    }
+
+    /// Pauses the consumer `id`. Messages keep being forwarded to a paused
+    /// consumer but wait in its queue until it is resumed, except for the
+    /// messages the consumer already took off its queue.
+    ///
+    /// Like all changes to a consumer, pausing it applies once the messages
+    /// put before have been forwarded. Unknown consumers are ignored.
+    pub fn pause(&self, id: SubscriptionId) {
+        self.control(id, Control::Pause);
+    }
+
+    /// Resumes the consumer `id` after `Carousel::pause`.
+    pub fn resume(&self, id: SubscriptionId) {
+        self.control(id, Control::Resume);
+    }
+
+    /// Lets the consumer `id` consume only the messages put from now on for
+    /// which `filter` returns `true`, replacing any previous filter. Filtered
+    /// messages count as neither consumed nor expired. Filters don't apply to
+    /// streaming consumers.
+    pub fn set_filter<F>(&self, id: SubscriptionId, filter: F)
+    where
+        F: FnMut(&[u8]) -> bool + Send + 'static,
+    {
+        self.control(id, Control::Filter(Box::new(filter)));
+    }
+
+    /// Removes the consumer `id` from the `Carousel`. It consumes the messages
+    /// put before it was removed and is then dropped on its poller thread.
+    pub fn remove(&self, id: SubscriptionId) {
+        self.control(id, Control::Remove);
+    }
+
+    /// Applies all changes in `table` at once, without restarting consumers.
+    /// Like all changes to consumers, the table applies to the messages put
+    /// after it. Unknown consumers are ignored.
+    pub fn apply_routing(&self, table: RoutingTable) {
+        self.submit(Event::Routing(table));
+    }
+
+    /// Changes the settings of the `Carousel` while it runs. Like all changes
+    /// to consumers, `delta` applies to the messages put after it.
+    ///
+    /// The capacity and overflow policy only apply to a `Carousel` built with
+    /// `Builder::memory_budget`, and are ignored otherwise. Lowering the
+    /// capacity doesn't evict messages already held.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delta` spills messages of a `Carousel` with persistence, see
+    /// `Builder::build`.
+    pub fn reconfigure(&self, delta: ConfigDelta) {
+        assert!(
+            self.shared.wal.is_none() || !matches!(delta.overflow, Some(Overflow::Spill(_))),
+            "spilling messages can't be combined with persistence"
+        );
+        self.submit(Event::Reconfigure(delta));
+    }
+
+    fn control(&self, id: SubscriptionId, control: Control) {
+        self.submit(Event::Control(id.0, control));
+    }
+
+    fn submit(&self, event: Event) {
+        self.shared.submit(event);
+    }
+
+    /// Returns a handle to the `Carousel` sharing `shared`, like a clone.
+    fn handle(shared: &sync::Arc<Shared>) -> Carousel {
+        Carousel {
+            shared: sync::Arc::clone(shared),
+            producer: None,
+            multiplier: None,
+            sweeper: None,
+            watchdog: None,
+            compactor: None,
+            auditor: None,
+            buffer: None,
+        }
+    }
+
+    /// Returns a snapshot of the statistics of the `Carousel`.
+    pub fn stats(&self) -> CarouselStats {
+        let now = self.shared.clock.now();
+        let mut consumers: Vec<ConsumerStats> = self
+            .shared
+            .routes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|route| route.slot.stats(now))
+            .collect();
+        consumers.sort_by_key(|stats| stats.id);
+
+        let mut namespaces: Vec<NamespaceStats> = self
+            .shared
+            .namespaces
+            .lock()
+            .unwrap()
+            .values()
+            .map(|tenant| tenant.stats())
+            .collect();
+        namespaces.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut producers: Vec<ProducerStats> = self
+            .shared
+            .producers
+            .lock()
+            .unwrap()
+            .values()
+            .map(|producer| producer.stats())
+            .collect();
+        producers.sort_by(|a, b| a.name.cmp(&b.name));
+
+        CarouselStats {
+            consumers,
+            expired_retained: self.shared.expired_retained.load(Ordering::SeqCst),
+            queued_bytes: self.shared.budget.as_ref().map_or(0, |b| b.used()),
+            dropped: self.shared.budget.as_ref().map_or(0, |b| b.dropped()),
+            rejected: self.shared.rejected.load(Ordering::SeqCst),
+            discarded: self.shared.discarded.load(Ordering::SeqCst),
+            filtered: self.shared.filtered.load(Ordering::SeqCst),
+            namespaces,
+            producers,
+            throughput: self.shared.throughput.throughput(now),
+        }
+    }
+
+    /// Returns a human readable dump of the configuration of the `Carousel`
+    /// and of the state of its threads and consumers, meant for logs and
+    /// debugging sessions. The format is not stable.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::Carousel;
+    /// use std::sync::mpsc;
+    ///
+    /// let (tx, _rx) = mpsc::channel();
+    /// let c = Carousel::builder().consumer(tx).build();
+    /// println!("{}", c.debug_report());
+    /// ```
+    pub fn debug_report(&self) -> String {
+        use std::fmt::Write;
+
+        let shared = &self.shared;
+        let now = shared.clock.now();
+        let mut report = format!(
+            "Carousel {}, up {:?}\n",
+            if self.multiplier.is_some() {
+                "owner"
+            } else {
+                "handle"
+            },
+            now.saturating_duration_since(shared.started)
+        );
+        let optional = |value: Option<String>| value.unwrap_or_else(|| String::from("-"));
+        let panic_policy = shared.panic_policy.as_ref().map(|policy| match policy {
+            PanicPolicy::Restart => String::from("Restart"),
+            PanicPolicy::RemoveConsumer => String::from("RemoveConsumer"),
+            PanicPolicy::ShutdownCarousel => String::from("ShutdownCarousel"),
+            PanicPolicy::Callback(_) => String::from("Callback"),
+        });
+        let config = vec![
+            ("shards", shared.shards.len().to_string()),
+            ("sharding", format!("{:?}", shared.sharding)),
+            ("wait strategy", format!("{:?}", shared.wait)),
+            ("thread name", optional(shared.thread_name.clone())),
+            (
+                "memory budget",
+                optional(shared.budget.as_ref().map(|b| b.limit().to_string())),
+            ),
+            ("retries", shared.retries.to_string()),
+            ("ttl", optional(shared.ttl.map(|ttl| format!("{:?}", ttl)))),
+            (
+                "chunk size",
+                optional(shared.chunk_size.map(|size| size.to_string())),
+            ),
+            ("retain", shared.retain.to_string()),
+            ("checksums", shared.checksums.to_string()),
+            (
+                "idle timeout",
+                optional(shared.idle_timeout.map(|timeout| format!("{:?}", timeout))),
+            ),
+            (
+                "consume timeout",
+                optional(
+                    shared
+                        .consume_timeout
+                        .map(|(timeout, action)| format!("{:?} {:?}", timeout, action)),
+                ),
+            ),
+            ("panic policy", optional(panic_policy)),
+            ("drop policy", format!("{:?}", shared.drop_policy)),
+        ];
+        report.push_str("config:\n");
+        for (name, value) in config {
+            writeln!(report, "  {}: {}", name, value).unwrap();
+        }
+
+        let multipliers = match &self.multiplier {
+            Some(multiplier) if multiplier.stopped => "stopped",
+            Some(_) => "running",
+            None => "owned by another handle",
+        };
+        writeln!(report, "multipliers: {}", multipliers).unwrap();
+        writeln!(
+            report,
+            "housekeeping: sweeper {}, watchdog {}, compactor {}, auditor {}",
+            if self.sweeper.is_some() {
+                "running"
+            } else {
+                "-"
+            },
+            if self.watchdog.is_some() {
+                "running"
+            } else {
+                "-"
+            },
+            if self.compactor.is_some() {
+                "running"
+            } else {
+                "-"
+            },
+            if self.auditor.is_some() {
+                "running"
+            } else {
+                "-"
+            }
+        )
+        .unwrap();
+
+        let routes = shared.routes.read().unwrap();
+        let pollers = shared.pollers.lock().unwrap();
+        report.push_str("consumers:\n");
+        for poller in pollers.iter() {
+            let slot = &poller.slot;
+            let route = routes.iter().find(|route| route.slot.id == slot.id);
+            let finished = match &*slot.thread.lock().unwrap() {
+                Some(thread) => thread.is_finished(),
+                None => true,
+            };
+            let state = if route.is_none() {
+                "removed"
+            } else if slot.dormant.lock().unwrap().is_some() {
+                "dormant"
+            } else if finished {
+                "stopped"
+            } else if slot.queue.is_paused() {
+                "paused"
+            } else {
+                "running"
+            };
+            let name = match &shared.thread_name {
+                Some(prefix) => format!("{}-poller-{}", prefix, slot.id),
+                None => format!("poller-{}", slot.id),
+            };
+            let stats = slot.stats(now);
+            write!(
+                report,
+                "  {} ({}): {}, depth {}, consumed {}, expired {}, corrupted {}, \
+                 timed out {}, panics {}, discarded {}",
+                slot.id,
+                name,
+                state,
+                stats.depth,
+                stats.consumed,
+                stats.expired,
+                stats.corrupted,
+                stats.timed_out,
+                stats.panics,
+                stats.discarded
+            )
+            .unwrap();
+            if let Some(namespace) = route.and_then(Route::namespace) {
+                write!(report, ", namespace {}", namespace).unwrap();
+            }
+            match route.and_then(|route| route.topic.as_ref()) {
+                Some(Selector::Topic(topic)) => write!(report, ", topic {}", topic).unwrap(),
+                Some(Selector::Pattern(pattern)) => {
+                    write!(report, ", pattern {}", pattern).unwrap()
+                }
+                None => {}
+            }
+            report.push('\n');
+        }
+        report
+    }
+
+    fn envelope(&self, data: Vec<u8>) -> Envelope {
+        let now = self.shared.clock.now();
+        Envelope {
+            data: sync::Arc::new(data),
+            id: None,
+            offset: None,
+            topic: None,
+            namespace: None,
+            expires: self.shared.ttl.map(|ttl| now + ttl),
+            context: self.shared.propagator.as_ref().map(|p| p.capture()),
+            charge: None,
+            quota: None,
+            backlog: None,
+            spilled: None,
+            chunk: None,
+            crc: None,
+            put: now,
+            receipt: None,
+            lineage: self
+                .shared
+                .stage
+                .as_ref()
+                .map(|stage| lineage::extend(stage, now)),
+            hop: None,
+            fan_out: false,
+        }
+    }
+
+    fn send(&self, envelope: Envelope) {
+        if let Err(e) = self.try_send(envelope) {
+            println!("Carousel rejected a message: {}", e);
+        }
+    }
+
+    /// Sends `envelope`, or buffers it for a handle of `Carousel::buffered`.
+    fn try_send(&self, envelope: Envelope) -> Result<(), PutError> {
+        match &self.buffer {
+            Some(buffer) => buffer.put(self, envelope),
+            None => self.try_send_now(envelope),
+        }
+    }
+
+    /// Returns whether a message put now would be forwarded right away: the
+    /// `Carousel` isn't quiesced, paused or backpressured.
+    fn is_writable(&self) -> bool {
+        !self.shared.quiesced.load(Ordering::SeqCst)
+            && !*self.shared.held.lock().unwrap()
+            && !self.is_backpressured()
+    }
+
+    /// Validates `envelope` and sends it to a multiplier, unless the
+    /// `Carousel` was quiesced.
+    fn try_send_now(&self, envelope: Envelope) -> Result<(), PutError> {
+        // Counted before the check, so that `Carousel::quiesce` waits for the
+        // puts that found the `Carousel` still open.
+        self.shared.putting.fetch_add(1, Ordering::SeqCst);
+        let closed = self.shared.closed.load(Ordering::SeqCst);
+        let sent = match (closed, self.shared.quiesced.load(Ordering::SeqCst)) {
+            (true, _) => Err(PutError::Closed),
+            (false, true) => Err(PutError::Quiesced),
+            (false, false) => self.validate_and_send(envelope),
+        };
+        if self.shared.putting.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shared.progress.notify();
+        }
+        sent
+    }
+
+    fn validate_and_send(&self, mut envelope: Envelope) -> Result<(), PutError> {
+        if let Some(validator) = &self.shared.validator {
+            if let Err(e) = validator(&envelope.data) {
+                self.shared.rejected.fetch_add(1, Ordering::SeqCst);
+                self.shared.audit(AuditReason::Rejected, &envelope);
+                return Err(e.into());
+            }
+        }
+        self.shared.audit(AuditReason::Accepted, &envelope);
+        self.shared
+            .throughput
+            .record(envelope.put, envelope.data.len());
+        if let Some(keys) = &self.shared.signer {
+            envelope.data = sync::Arc::new(sign::sign(keys.as_ref(), &envelope.data));
+        }
+        if self.shared.checksums {
+            envelope.crc = Some(checksum::crc32(&envelope.data));
+        }
+        if let Some(producer) = &self.producer {
+            producer.admit(envelope.data.len(), self.shared.clock.as_ref());
+        }
+        let shard = match (self.shared.sharding, &envelope.topic) {
+            _ if self.shared.shards.len() == 1 => 0,
+            (Sharding::RoundRobin, _) => self.shared.next_shard.fetch_add(1, Ordering::SeqCst),
+            (Sharding::ByTopic, Some(topic)) => {
+                let mut hasher = DefaultHasher::new();
+                topic.hash(&mut hasher);
+                hasher.finish() as usize
+            }
+            (Sharding::ByTopic, None) => 0,
+        };
+        let index = shard % self.shared.shards.len();
+        let shard = &self.shared.shards[index];
+
+        match self.shared.chunk_size {
+            Some(size) if envelope.data.len() > size => {
+                let mut next = self.shared.next_message.lock().unwrap();
+                let message = *next;
+                *next += 1;
+                for chunk in chunk::split(envelope, size, message, index) {
+                    self.send_to(shard, chunk);
+                }
+            }
+            _ => self.send_to(shard, envelope),
+        }
+        Ok(())
+    }
+
+    fn send_to(&self, shard: &mpsc::Sender<Event>, mut envelope: Envelope) {
+        let quota = envelope
+            .namespace
+            .as_ref()
+            .and_then(|tenant| tenant.quota.clone());
+        if let Some(quota) = quota {
+            if !quota.admit_quota(&mut envelope) {
+                self.shared.audit(AuditReason::Overflow, &envelope);
+                self.shared.dropped(None, DropReason::Overflow, 1);
+                return;
+            }
+        }
+        // A payload spilled by the quota is no longer held in memory.
+        if let (Some(budget), None) = (&self.shared.budget, &envelope.spilled) {
+            if !budget.admit(&mut envelope) {
+                self.shared.audit(AuditReason::Overflow, &envelope);
+                self.shared.dropped(None, DropReason::Overflow, 1);
+                return;
+            }
+        }
+        // A message put after the multipliers stopped is discarded like the
+        // messages arriving during a `DropPolicy::Discard` shutdown.
+        self.shared.in_transit.fetch_add(1, Ordering::SeqCst);
+        if shard.send(Event::Message(envelope)).is_err() {
+            self.shared.in_transit.fetch_sub(1, Ordering::SeqCst);
+            self.shared.progress.notify();
+            self.shared.discarded.fetch_add(1, Ordering::SeqCst);
+            self.shared.errors.emit(CarouselError::ChannelClosed);
+        }
+    }
+
+    /// Starts a `StatusServer` listening on `addr` that serves the statistics
+    /// of the `Carousel` over HTTP, available with the `http` feature.
+    #[cfg(feature = "http")]
+    pub fn serve_status<A: std::net::ToSocketAddrs>(&self, addr: A) -> io::Result<StatusServer> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        StatusServer::start(self.clone(), listener)
+    }
+
+    /// Puts the synthetic load of `config` on the `Carousel` and reports how
+    /// fast its consumers took it, available with the `bench` feature. Run it
+    /// on a fresh `Carousel` built like the one to tune, since the latencies
+    /// cover the whole life of each consumer.
+    ///
+    /// # Example
+    /// ```
+    /// # #[cfg(feature = "bench")]
+    /// # {
+    /// use mp2c::asynch::{from_fn, BenchConfig, Carousel, WaitStrategy};
+    ///
+    /// let c = Carousel::builder()
+    ///   .consumer(from_fn(|_| {}))
+    ///   .wait_strategy(WaitStrategy::SpinThenPark { spins: 100 })
+    ///   .build();
+    /// let report = c.bench(BenchConfig { messages: 1000, ..BenchConfig::default() });
+    /// println!("{} msg/s, p99 {:?}", report.throughput.messages_per_second, report.latency.p99);
+    /// # }
+    /// ```
+    #[cfg(feature = "bench")]
+    pub fn bench(&self, config: BenchConfig) -> BenchReport {
+        bench::run(self, config)
+    }
+
+    /// Spawns a feeder thread that puts every message yielded by `iter` on the
+    /// `Carousel`, which is handy for replaying files or test fixtures.
+    ///
+    /// The feeder is backpressure aware: while any consumer has a raised
+    /// `DepthAlert`, or the messages on their way to the consumer queues
+    /// would raise one, it waits before taking the next message from `iter`.
+    /// Without `Builder::depth_alert` the feeder never waits.
+    pub fn feed_from_iter<I>(&self, iter: I) -> Feed
+    where
+        I: IntoIterator<Item = Vec<u8>> + Send + 'static,
+    {
+        let carousel = self.clone();
+        let thread = self.shared.spawn(String::from("feeder"), move || {
+            let mut iter = iter.into_iter();
+            let mut fed = 0;
+            loop {
+                carousel.wait_for_capacity();
+                match iter.next() {
+                    Some(data) => carousel.put(data),
+                    None => return fed,
+                }
+                fed += 1;
+            }
+        });
+
+        Feed { thread }
+    }
+
+    /// Spawns a feeder thread that reads `reader`, such as a file or a
+    /// socket, and puts every chunk terminated by `delimiter` on the
+    /// `Carousel` as one message, without the delimiter, like a delimited
+    /// `Writer`. A trailing chunk without a delimiter is put at the end of
+    /// the input. The feeder stops at the end of the input or at the first
+    /// read error.
+    ///
+    /// Like `Carousel::feed_from_iter` the feeder stops reading while any
+    /// consumer has a raised `DepthAlert`, so a slow consumer holds back the
+    /// source, e.g. through TCP flow control, instead of the messages piling
+    /// up in memory.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::Carousel;
+    /// use std::sync::mpsc;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let c = Carousel::builder()
+    ///   .consumer(tx)
+    ///   .depth_alert(1000, 100, |_| {})
+    ///   .build();
+    /// let feed = c.feed_from_reader(&b"first\nsecond\n"[..], b'\n');
+    /// assert_eq!(feed.join(), 2);
+    /// drop(c);
+    /// assert_eq!(rx.iter().count(), 2);
+    /// ```
+    pub fn feed_from_reader<R>(&self, reader: R, delimiter: u8) -> Feed
+    where
+        R: io::Read + Send + 'static,
+    {
+        let carousel = self.clone();
+        let thread = self.shared.spawn(String::from("feeder"), move || {
+            let mut reader = io::BufReader::new(reader);
+            let mut fed = 0;
+            loop {
+                carousel.wait_for_capacity();
+                let mut data = Vec::new();
+                match io::BufRead::read_until(&mut reader, delimiter, &mut data) {
+                    Ok(0) => return fed,
+                    Ok(_) => {
+                        if data.last() == Some(&delimiter) {
+                            data.pop();
+                        }
+                        carousel.put(data);
+                        fed += 1;
+                    }
+                    Err(e) => {
+                        println!("Feeder error reading a message: {}", e);
+                        return fed;
+                    }
+                }
+            }
+        });
+
+        Feed { thread }
+    }
+
+    /// Waits while any consumer has a raised `DepthAlert`.
+    fn wait_for_capacity(&self) {
+        self.shared.progress.wait_until(|| !self.is_backpressured());
+    }
+
+    /// Leaves the threads of the `Carousel` running in the background until
+    /// the process exits, like `DropPolicy::Detach`, and returns a handle to
+    /// it. Dropping the handle, or any clone, doesn't stop the `Carousel`,
+    /// which suits fire-and-forget pipelines such as telemetry.
+    pub fn detach(mut self) -> Carousel {
+        let handle = self.clone();
+        self.leak_threads();
+        handle
+    }
+
+    /// Splits the `Carousel` into a handle to put messages and subscribe
+    /// consumers, which doesn't stop the `Carousel` when dropped, and its
+    /// `Threads`, which the application stops and joins when it chooses.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::{from_fn, Carousel};
+    ///
+    /// let (c, mut threads) = Carousel::builder()
+    ///   .consumer(from_fn(|_| {}))
+    ///   .build()
+    ///   .into_parts();
+    /// c.put(String::from("test").into_bytes());
+    ///
+    /// // later, as part of the shutdown of the application
+    /// for thread in threads.stop_multipliers() {
+    ///   thread.join().unwrap();
+    /// }
+    /// for thread in threads.stop_pollers() {
+    ///   thread.join().unwrap();
+    /// }
+    /// ```
+    pub fn into_parts(self) -> (Carousel, Threads) {
+        (self.clone(), Threads { carousel: self })
+    }
+
+    /// Stops the `Carousel` like dropping it and returns what became of its
+    /// messages. Called on a handle, it returns the report of the running
+    /// `Carousel` without stopping it.
+    pub fn shutdown(mut self) -> ShutdownReport {
+        self.shut_down().unwrap_or_else(|| self.report())
+    }
+
+    /// Stops and joins the threads of the `Carousel`, unless it is a handle,
+    /// and returns the report of the shutdown.
+    fn shut_down(&mut self) -> Option<ShutdownReport> {
+        self.multiplier.as_ref()?;
+        for multiplier_thread in self.stop_multipliers() {
+            join(multiplier_thread, "multiplier");
+        }
+        for poller_thread in self.stop_pollers() {
+            join(poller_thread, "poller");
+        }
+        self.multiplier = None;
+        self.auditor.take();
+        Some(self.report())
+    }
+
+    fn report(&self) -> ShutdownReport {
+        let now = self.shared.clock.now();
+        let mut consumers: Vec<ConsumerReport> = self
+            .shared
+            .pollers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|poller| {
+                let stats = poller.slot.stats(now);
+                ConsumerReport {
+                    id: stats.id,
+                    consumed: stats.consumed,
+                    discarded: stats.discarded,
+                    panics: stats.panics,
+                }
+            })
+            .collect();
+        consumers.sort_by_key(|report| report.id);
+
+        ShutdownReport {
+            uptime: now.saturating_duration_since(self.shared.started),
+            consumers,
+            discarded: self.shared.discarded.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Stops the housekeeping threads and tells the multiplier threads to
+    /// stop once they forwarded the messages put before. Returns the
+    /// multiplier threads, none if they were told to stop before.
+    fn stop_multipliers(&mut self) -> Vec<thread::JoinHandle<()>> {
+        if self.multiplier.is_some() {
+            self.start();
+        }
+        let multiplier = match &mut self.multiplier {
+            Some(multiplier) if !multiplier.stopped => multiplier,
+            _ => return Vec::new(),
+        };
+        multiplier.stopped = true;
+        self.sweeper.take();
+        self.watchdog.take();
+        self.compactor.take();
+
+        let discard = self.shared.drop_policy == DropPolicy::Discard;
+        self.shared.discarding.store(discard, Ordering::SeqCst);
+        println!("Sending terminate message to all pollers.");
+
+        for shard in &self.shared.shards {
+            // A multiplier that already exited needs no telling.
+            let _ = shard.send(Event::Terminate);
+        }
+        multiplier.threads.drain(..).collect()
+    }
+
+    /// Tells the poller threads to stop once they consumed the messages
+    /// forwarded to them and returns them. The multiplier threads must have
+    /// finished forwarding.
+    fn stop_pollers(&self) -> Vec<thread::JoinHandle<()>> {
+        println!("Shutting down all pollers.");
+
+        let discard = self.shared.drop_policy == DropPolicy::Discard;
+        // The primary consumer hands its last messages on to the others, so
+        // it stops first.
+        let primary = self.shared.primary.and_then(|id| {
+            let routes = self.shared.routes.read().unwrap();
+            let route = routes.iter().find(|route| route.slot.id == id)?;
+            Some(sync::Arc::clone(&route.slot))
+        });
+        let terminate = |slot: &Slot| {
+            if discard {
+                slot.discard(&self.shared);
+            }
+            slot.queue.set_paused(false);
+            slot.push(Event::Terminate);
+        };
+        if let Some(slot) = primary {
+            terminate(&slot);
+            let thread = slot.thread.lock().unwrap().take();
+            if let Some(thread) = thread {
+                join(thread, "poller");
+            }
+        }
+        let slots: Vec<sync::Arc<Slot>> = self
+            .shared
+            .routes
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|route| Some(route.slot.id) != self.shared.primary)
+            .map(|route| sync::Arc::clone(&route.slot))
+            .collect();
+        let mut phases: Vec<u32> = slots
+            .iter()
+            .map(|slot| self.shared.shutdown_phase(slot.id))
+            .collect();
+        phases.sort_unstable();
+        phases.dedup();
+        // The pollers of every phase but the last are joined before the next
+        // phase is terminated.
+        for (i, &phase) in phases.iter().enumerate() {
+            let stopping: Vec<&sync::Arc<Slot>> = slots
+                .iter()
+                .filter(|slot| self.shared.shutdown_phase(slot.id) == phase)
+                .collect();
+            for slot in &stopping {
+                terminate(slot);
+            }
+            if i + 1 == phases.len() {
+                break;
+            }
+            for slot in stopping {
+                let thread = slot.thread.lock().unwrap().take();
+                if let Some(thread) = thread {
+                    join(thread, "poller");
+                }
+            }
+        }
+        // Terminating wakes up idle pollers, so their last thread is stored.
+        self.shared
+            .pollers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|poller| poller.slot.thread.lock().unwrap().take())
+            .collect()
+    }
+
+    /// Lets go of the threads of the `Carousel` without stopping them.
+    fn leak_threads(&mut self) {
+        // The housekeeping threads stop once their handles are dropped.
+        std::mem::forget(self.sweeper.take());
+        std::mem::forget(self.watchdog.take());
+        std::mem::forget(self.compactor.take());
+        std::mem::forget(self.auditor.take());
+        self.multiplier.take();
+    }
+
+    /// Returns an `io::Write` adapter that puts what is written to it on the
+    /// `Carousel`. See `Writer` for how writes are split into messages.
+    pub fn writer(&self) -> Writer {
+        Writer::new(self.clone())
+    }
+
+    /// Returns whether a consumer has a raised `DepthAlert`, or the messages
+    /// not yet dispatched by the multipliers would raise one.
+    fn is_backpressured(&self) -> bool {
+        let routes = self.shared.routes.read().unwrap();
+        let in_transit = self.shared.in_transit.load(Ordering::SeqCst);
+        routes.iter().any(|route| {
+            route.slot.is_alerted()
+                || route
+                    .slot
+                    .alerts
+                    .as_ref()
+                    .is_some_and(|alerts| in_transit >= alerts.high)
+        })
+    }
+}
+
+/// `Threads` are the threads of a `Carousel` taken apart with
+/// `Carousel::into_parts`, to be stopped and joined by the application. The
+/// handles must not put messages once the multiplier threads were told to
+/// stop. Dropping `Threads` stops and joins the threads that are left, like
+/// dropping the `Carousel`, but doesn't wait for threads handed out.
+pub struct Threads {
+    carousel: Carousel,
+}
+
+impl Threads {
+    /// Tells the multiplier threads to stop once they forwarded the messages
+    /// put so far and returns them, none if they were told to stop before.
+    pub fn stop_multipliers(&mut self) -> Vec<thread::JoinHandle<()>> {
+        self.carousel.stop_multipliers()
+    }
+
+    /// Tells the poller threads to stop once they consumed the messages
+    /// forwarded to them and returns them. Join the multiplier threads
+    /// first, or messages they still forward are lost. If the multipliers
+    /// weren't told to stop yet, they are stopped and joined first.
+    pub fn stop_pollers(&mut self) -> Vec<thread::JoinHandle<()>> {
+        for multiplier_thread in self.carousel.stop_multipliers() {
+            join(multiplier_thread, "multiplier");
+        }
+        self.carousel.stop_pollers()
+    }
+}
+
+/// `Feed` is a handle to a feeder thread started by `Carousel::feed_from_iter`
+/// or `Carousel::feed_from_reader`.
+pub struct Feed {
+    thread: thread::JoinHandle<usize>,
+}
+
+impl Feed {
+    /// Waits until the feeder has drained its source and returns the number
+    /// of messages it put on the `Carousel`.
+    ///
+    /// # Panics
+    ///
+    /// Panics with the panic of the feeder thread, e.g. if the iterator of
+    /// `Carousel::feed_from_iter` panicked.
+    pub fn join(self) -> usize {
+        self.thread.join().unwrap()
+    }
+}
+
+/// `Builder` configures and creates a `Carousel`.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{Carousel, Consumer};
+///
+/// struct TestConsumer;
+///
+/// impl Consumer for TestConsumer {
+///   fn consume(&mut self, data: Vec<u8>) {
+///     // do something with data
+///   }
+/// }
+///
+/// let c = Carousel::builder()
+///   .consumer(TestConsumer)
+///   .depth_alert(1000, 100, |alert| println!("{:?}", alert))
+///   .build();
+///
+/// c.put(String::from("test").into_bytes());
+/// ```
+#[derive(Default)]
+pub struct Builder {
+    consumers: Vec<Subscription>,
+    depth_alerts: Option<DepthAlerts>,
+    propagator: Option<sync::Arc<dyn Propagator>>,
+    error_handler: Option<ErrorHandler>,
+    retries: usize,
+    dedup: Option<Box<dyn DedupStore>>,
+    wal: Option<(wal::Wal, Vec<wal::Record>)>,
+    retain: bool,
+    ttl: Option<Duration>,
+    sweep_interval: Option<Duration>,
+    compaction: Option<(Duration, Duration)>,
+    shards: Option<(usize, Sharding)>,
+    strict_ordering: bool,
+    wait: WaitStrategy,
+    budget: Option<budget::Budget>,
+    consumer_budgets: HashMap<usize, budget::Budget>,
+    chunk_size: Option<usize>,
+    thread_name: Option<String>,
+    namespaces: HashMap<String, sync::Arc<namespace::Tenant>>,
+    producers: HashMap<String, sync::Arc<producer::Producer>>,
+    validator: Option<validate::Validator>,
+    transform: Option<Transform>,
+    checksums: bool,
+    signer: Option<sync::Arc<dyn KeyProvider>>,
+    audit: Option<Box<dyn AuditConsumer + Send>>,
+    clock: Option<sync::Arc<dyn Clock>>,
+    faults: Option<Faults>,
+    idle_timeout: Option<Duration>,
+    consume_timeout: Option<(Duration, TimeoutAction)>,
+    panic_policy: Option<PanicPolicy>,
+    drop_policy: DropPolicy,
+    on_shutdown: Option<ShutdownHandler>,
+    dispatch_order: Vec<SubscriptionId>,
+    primary: Option<SubscriptionId>,
+    shutdown_phases: HashMap<usize, u32>,
+    persist_first: bool,
+    start_paused: bool,
+    stage: Option<sync::Arc<str>>,
+}
+
+impl Builder {
+    /// Creates a new `Builder` without any consumers.
+    pub fn new() -> Builder {
+        Builder::default()
+    }
+
+    /// Registers a consumer, which may be any `Consumer`, `TryConsumer` or
+    /// `CtxConsumer`.
+    /// Consumers are identified by their position in registration order,
+    /// starting at 0.
+    pub fn consumer<T>(self, consumer: T) -> Builder
+    where
+        T: CtxConsumer + Send + 'static,
+    {
+        self.subscription(None, Box::new(move || Sink::Mobile(Box::new(consumer))))
+    }
+
+    /// Registers `consumer` to receive the messages in batches, flushed as
+    /// selected by `triggers`, e.g. after 500 messages, 200 milliseconds or
+    /// 4 MiB, whichever comes first. The last batch is flushed when the
+    /// `Carousel` shuts down.
+    ///
+    /// A message counts as consumed once it is added to the batch, so
+    /// retries, timeouts and the write-ahead log apply to adding it rather
+    /// than to the flush.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::{Carousel, FlushTriggers};
+    /// use std::sync::mpsc;
+    /// use std::time::Duration;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let c = Carousel::builder()
+    ///   .batch_consumer(tx, FlushTriggers { messages: 2, ..FlushTriggers::default() })
+    ///   .build();
+    /// for i in 0..3u8 {
+    ///   c.put(vec![i]);
+    /// }
+    /// drop(c);
+    /// assert_eq!(rx.recv().unwrap(), vec![vec![0], vec![1]]);
+    /// assert_eq!(rx.recv().unwrap(), vec![vec![2]]);
+    /// ```
+    pub fn batch_consumer<T>(self, consumer: T, triggers: FlushTriggers) -> Builder
+    where
+        T: BatchConsumer + Send + 'static,
+    {
+        assert!(triggers.messages > 0);
+
+        self.consumer(batch::Batching::new(consumer, triggers))
+    }
+
+    /// Registers `consumer` with a transform of its own, which runs on its
+    /// poller thread, so that projecting or re-encoding messages for one
+    /// consumer doesn't slow the multiplier. Messages for which `transform`
+    /// returns `None` count as consumed without reaching `consumer`. See
+    /// `Builder::transform` for a transform shared by all consumers.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::Carousel;
+    /// use std::sync::mpsc;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let c = Carousel::builder()
+    ///   .transformed_consumer(tx, |data: Vec<u8>| Some(data[..2].to_vec()))
+    ///   .build();
+    /// c.put(String::from("test").into_bytes());
+    /// drop(c);
+    /// assert_eq!(rx.recv().unwrap(), b"te");
+    /// ```
+    pub fn transformed_consumer<T, F>(self, consumer: T, transform: F) -> Builder
+    where
+        T: CtxConsumer + Send + 'static,
+        F: FnMut(Vec<u8>) -> Option<Vec<u8>> + Send + 'static,
+    {
+        self.consumer(ctx::Transformed {
+            consumer,
+            transform,
+        })
+    }
+
+    /// Registers a consumer that only receives messages put on `topic`.
+    pub fn subscriber<T>(self, topic: &str, consumer: T) -> Builder
+    where
+        T: CtxConsumer + Send + 'static,
+    {
+        self.subscription(
+            Some(Selector::Topic(String::from(topic))),
+            Box::new(move || Sink::Mobile(Box::new(consumer))),
+        )
+    }
+
+    /// Registers a consumer that only receives messages put on topics matching
+    /// `pattern`.
+    pub fn pattern_subscriber<T>(self, pattern: TopicPattern, consumer: T) -> Builder
+    where
+        T: CtxConsumer + Send + 'static,
+    {
+        self.subscription(
+            Some(Selector::Pattern(pattern)),
+            Box::new(move || Sink::Mobile(Box::new(consumer))),
+        )
+    }
+
+    /// Registers a consumer created by `factory` on its poller thread when the
+    /// `Carousel` starts. The consumer itself doesn't need to be `Send`, so it
+    /// may hold resources that are bound to the thread they were created on.
+    pub fn consumer_factory<F, T>(self, factory: F) -> Builder
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: CtxConsumer + 'static,
+    {
+        self.subscription(None, Box::new(move || Sink::Whole(Box::new(factory()))))
+    }
+
+    /// Registers a consumer created by `factory` on its poller thread, like
+    /// `Builder::consumer_factory`, that receives no messages until it is
+    /// ready, see `Readiness`, e.g. until its caches are loaded. Messages put
+    /// in the meantime wait in its queue rather than reaching a consumer
+    /// that can't handle them yet.
+    ///
+    /// A consumer that never gets ready holds back the shutdown of the
+    /// `Carousel` if it received a message.
+    pub fn warming_consumer<F, T>(self, factory: F) -> Builder
+    where
+        F: FnOnce(Readiness) -> T + Send + 'static,
+        T: CtxConsumer + 'static,
+    {
+        self.subscription(
+            None,
+            Box::new(move || {
+                let (readiness, gate) = Readiness::new();
+                let consumer = factory(readiness);
+                Sink::Whole(Box::new(readiness::Warming { consumer, gate }))
+            }),
+        )
+    }
+
+    /// Registers a consumer created by `factory` on its poller thread, like
+    /// `Builder::consumer_factory`, and replaced by a new one from `factory`
+    /// whenever it panics, as directed by `supervisor`. A message during
+    /// which the consumer panicked is handed to the new consumer as
+    /// configured with `Supervisor::redeliver`, then fails with a
+    /// `ConsumerPanic` error and is retried as configured with
+    /// `Builder::retries`.
+    pub fn supervised<F, T>(self, supervisor: Supervisor, factory: F) -> Builder
+    where
+        F: FnMut() -> T + Send + 'static,
+        T: CtxConsumer + 'static,
+    {
+        self.subscription(
+            None,
+            Box::new(move || {
+                Sink::Whole(Box::new(supervisor::Supervised::new(supervisor, factory)))
+            }),
+        )
+    }
+
+    /// Registers a consumer that only receives a random share of `rate` of
+    /// the messages, e.g. `0.01` for 1%, which suits debug and audit
+    /// consumers. Messages left out of the sample aren't forwarded to the
+    /// consumer at all. The rate can be changed with `RoutingTable::sample`.
+    pub fn sampled_consumer<T>(self, rate: f64, consumer: T) -> Builder
+    where
+        T: CtxConsumer + Send + 'static,
+    {
+        let mut builder = self.consumer(consumer);
+        if let Some(subscription) = builder.consumers.last_mut() {
+            subscription.sample = Some(routing::Sample::new(rate));
+        }
+        builder
+    }
+
+    /// Registers a consumer that reads each message as a stream. Combined with
+    /// `Builder::chunk_size` it reads large messages chunk by chunk as they
+    /// arrive, instead of waiting for them to be put back together.
+    pub fn streaming_consumer<T>(self, consumer: T) -> Builder
+    where
+        T: StreamingConsumer + Send + 'static,
+    {
+        self.subscription(None, Box::new(move || Sink::Stream(Box::new(consumer))))
+    }
+
+    fn subscription(mut self, topic: Option<Selector>, factory: Factory) -> Builder {
+        self.consumers.push(Subscription {
+            id: self.consumers.len(),
+            namespace: None,
+            topic,
+            sample: None,
+            factory,
+        });
+        self
+    }
+
+    /// Invokes `handler` with `DepthAlert::Raised` when a consumer's queue depth
+    /// reaches `high`, and with `DepthAlert::Cleared` once it drops back to `low`.
+    ///
+    /// The handler runs on the multiplier thread for raised alerts and on the
+    /// consumer's poller thread for cleared alerts, so it should return quickly.
+    pub fn depth_alert<F>(mut self, high: usize, low: usize, handler: F) -> Builder
+    where
+        F: Fn(DepthAlert) + Send + Sync + 'static,
+    {
+        assert!(low < high);
+
+        self.depth_alerts = Some(DepthAlerts {
+            high,
+            low,
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    /// Propagates context captured by `propagator` at `Carousel::put` to every
+    /// `Consumer::consume` call for that message.
+    pub fn propagator<P>(mut self, propagator: P) -> Builder
+    where
+        P: Propagator + 'static,
+    {
+        self.propagator = Some(sync::Arc::new(propagator));
+        self
+    }
+
+    /// Hands messages a `TryConsumer` failed to consume to `handler`, which runs
+    /// on the consumer's poller thread. Without a handler failures are printed.
+    ///
+    /// While a handler or retries are configured each message is copied before
+    /// it is consumed, so that it can be retried or handed to the handler.
+    pub fn error_handler<F>(mut self, handler: F) -> Builder
+    where
+        F: Fn(ConsumeError) + Send + Sync + 'static,
+    {
+        self.error_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Retries a failed `TryConsumer::try_consume` up to `retries` times before
+    /// the message is handed to the error handler.
+    pub fn retries(mut self, retries: usize) -> Builder {
+        self.retries = retries;
+        self
+    }
+
+    /// Consumes every message put with `Carousel::put_with_id` at most once per
+    /// consumer, as recorded in `store`. Messages without an id are unaffected.
+    ///
+    /// A message is recorded after it has been consumed successfully, so a
+    /// crash in between consuming and recording can still repeat it.
+    pub fn exactly_once<S>(mut self, store: S) -> Builder
+    where
+        S: DedupStore + 'static,
+    {
+        self.dedup = Some(Box::new(store));
+        self
+    }
+
+    /// Persists every message in a write-ahead log in `dir` before it is
+    /// forwarded to the consumers, along with the offset of the last message
+    /// each consumer has handled.
+    ///
+    /// When a `Carousel` is built on an existing log, each consumer first
+    /// receives the persisted messages it hasn't handled yet, so it resumes
+    /// exactly where it left off. Consumers are identified by their position in
+    /// registration order; a consumer without a recorded offset receives the
+    /// entire log.
+    ///
+    /// The log is flushed to the operating system after every message, which
+    /// survives a crash of the process but not necessarily of the machine.
+    pub fn persistence<P: AsRef<Path>>(mut self, dir: P) -> io::Result<Builder> {
+        self.wal = Some(wal::Wal::open(dir)?);
+        Ok(self)
+    }
+
+    /// Compacts the write-ahead log of `Builder::persistence` every
+    /// `interval` on a housekeeping thread: of the messages persisted more
+    /// than `horizon` ago, only the last one put on each topic of each
+    /// namespace is kept, which bounds the log of state topics. Messages
+    /// without a topic aren't compacted.
+    ///
+    /// A consumer resuming from a compacted log skips the superseded messages
+    /// it hasn't handled, and `Carousel::replay` no longer has them.
+    ///
+    /// The age of a message follows the clock of `Builder::clock`: messages
+    /// are persisted with its time, mapped to the wall-clock time once when
+    /// the `Carousel` is built.
+    pub fn compact_log(mut self, horizon: Duration, interval: Duration) -> Builder {
+        self.compaction = Some((horizon, interval));
+        self
+    }
+
+    /// Remembers the last message put on each topic and sends it to consumers
+    /// that subscribe to the topic with `Carousel::subscribe`, which suits
+    /// state topics such as configuration.
+    pub fn retain(mut self) -> Builder {
+        self.retain = true;
+        self
+    }
+
+    /// Expires messages `ttl` after they were put. Expired messages are
+    /// discarded instead of being consumed, and expired retained messages are
+    /// no longer sent to new subscribers.
+    pub fn ttl(mut self, ttl: Duration) -> Builder {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Starts a housekeeping thread that sweeps expired messages out of all
+    /// queues and retained messages every `interval`, so that memory is
+    /// reclaimed even while consumers are busy or stalled. Swept messages are
+    /// counted in `Carousel::stats`.
+    ///
+    /// Pollers take up to 64 messages off their queue at once. Those messages
+    /// are out of reach of the sweeper but are still discarded once expired.
+    pub fn sweep_every(mut self, interval: Duration) -> Builder {
+        self.sweep_interval = Some(interval);
+        self
+    }
+
+    /// Starts `shards` multiplier threads instead of one, spreading messages
+    /// over them as selected by `sharding`, to scale fan-out throughput on
+    /// machines with many cores.
+    ///
+    /// Ordering is relaxed with more than one shard. With `Sharding::ByTopic`
+    /// messages on the same topic keep their order; with `Sharding::RoundRobin`
+    /// messages may be consumed in any order. Sharding can't be combined with
+    /// `Builder::persistence`, which relies on messages being consumed in the
+    /// order they were persisted.
+    pub fn multiplier_shards(mut self, shards: usize, sharding: Sharding) -> Builder {
+        assert!(shards > 0);
+
+        self.shards = Some((shards, sharding));
+        self
+    }
+
+    /// Requires the strict ordering described on `Carousel`, which
+    /// `Builder::build` then refuses to combine with more than one multiplier
+    /// shard or with a primary consumer, see `Builder::primary`.
+    pub fn strict_ordering(mut self) -> Builder {
+        self.strict_ordering = true;
+        self
+    }
+
+    /// Limits the payload bytes held in memory by the `Carousel` to `bytes`.
+    /// A message is charged from `put` until every consumer is done with it;
+    /// `overflow` selects what happens to messages that don't fit. Retained
+    /// messages aren't charged, and a message larger than the whole budget is
+    /// let through once nothing else is held.
+    ///
+    /// With `Overflow::Block` a consumer must not put messages on its own
+    /// `Carousel`, as it would wait for itself. `Overflow::Spill` can't be
+    /// combined with `Builder::persistence`, whose log keeps payloads on disk
+    /// already.
+    pub fn memory_budget(mut self, bytes: usize, overflow: Overflow) -> Builder {
+        self.budget = Some(budget::Budget::new(bytes, overflow));
+        self
+    }
+
+    /// Limits the payload bytes queued for the consumer `id` to `bytes`,
+    /// applying `overflow` to the messages that don't fit in its queue only,
+    /// so that a slow consumer's backlog is bounded without affecting the
+    /// others. A message is charged to the consumer from when it is queued
+    /// until the consumer is done with it, independently of
+    /// `Builder::memory_budget`.
+    ///
+    /// With `Overflow::Block` the multiplier waits for the consumer to catch
+    /// up, which holds back the other consumers too. With `Overflow::Drop`
+    /// the message is dropped for this consumer only and counted in
+    /// `ConsumerStats::dropped`. `Overflow::Spill` can't be combined with
+    /// `Builder::persistence`.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::{from_fn, Carousel, Overflow, SubscriptionId};
+    ///
+    /// let c = Carousel::builder()
+    ///   .consumer(from_fn(|_data| { /* fast */ }))
+    ///   .consumer(from_fn(|_data| { /* slow */ }))
+    ///   .consumer_memory_limit(SubscriptionId(1), 1 << 20, Overflow::Drop)
+    ///   .build();
+    /// c.put(String::from("test").into_bytes());
+    /// ```
+    pub fn consumer_memory_limit(
+        mut self,
+        id: SubscriptionId,
+        bytes: usize,
+        overflow: Overflow,
+    ) -> Builder {
+        self.consumer_budgets
+            .insert(id.0, budget::Budget::new(bytes, overflow));
+        self
+    }
+
+    /// Splits messages larger than `size` bytes into chunks of at most `size`
+    /// bytes, which travel to the consumers separately and are put back
+    /// together before they are consumed. This keeps the events moving
+    /// through the `Carousel` small.
+    ///
+    /// Chunked messages aren't retained, and chunking can't be combined with
+    /// `Builder::persistence`, which persists the events moving through the
+    /// `Carousel`.
+    pub fn chunk_size(mut self, size: usize) -> Builder {
+        assert!(size > 0);
+
+        self.chunk_size = Some(size);
+        self
+    }
+
+    /// Limits the payload bytes held in memory for messages put into the
+    /// namespace `name` to `bytes`, applying `overflow` to messages that don't
+    /// fit, like `Builder::memory_budget` does for the whole `Carousel`. A
+    /// namespace that exceeds its quota doesn't take memory from the others.
+    ///
+    /// `Overflow::Spill` can't be combined with `Builder::persistence`.
+    pub fn namespace_quota(mut self, name: &str, bytes: usize, overflow: Overflow) -> Builder {
+        let quota = budget::Budget::new(bytes, overflow);
+        let tenant = namespace::Tenant::new(name, Some(quota));
+        self.namespaces
+            .insert(String::from(name), sync::Arc::new(tenant));
+        self
+    }
+
+    /// Limits how fast the producer `name` may put messages, see
+    /// `Carousel::clone_as`. Handles of other producers aren't affected.
+    pub fn producer_quota(mut self, name: &str, quota: ProducerQuota) -> Builder {
+        let producer = producer::Producer::new(name, quota);
+        self.producers
+            .insert(String::from(name), sync::Arc::new(producer));
+        self
+    }
+
+    /// Runs `validator` on every message when it is put, before any work is
+    /// done to forward it. Rejected messages never reach a consumer; they are
+    /// returned to the producer by `Carousel::try_put`, and counted in
+    /// `CarouselStats::rejected`. The validator runs on the producer's thread.
+    pub fn validator<F>(mut self, validator: F) -> Builder
+    where
+        F: Fn(&[u8]) -> Result<(), ValidationError> + Send + Sync + 'static,
+    {
+        self.validator = Some(Box::new(validator));
+        self
+    }
+
+    /// Runs `transform` on every message in the multiplier, before it is
+    /// persisted, retained and forwarded, so that normalizing or enriching a
+    /// message happens once rather than once per consumer. Messages for which
+    /// `transform` returns `None` are dropped and counted in
+    /// `CarouselStats::filtered`.
+    ///
+    /// The transform sees the payload as put, signed if `Builder::signer` is
+    /// set, and checksums are stamped again afterwards. Chunks of messages
+    /// split by `Builder::chunk_size` and messages spilled to disk pass
+    /// through untransformed. With `Builder::multiplier_shards` the transform
+    /// runs on several threads at once.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::Carousel;
+    /// use std::sync::mpsc;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let c = Carousel::builder()
+    ///   .consumer(tx)
+    ///   .transform(|data: Vec<u8>| match data.is_empty() {
+    ///     true => None,
+    ///     false => Some(data.to_ascii_lowercase()),
+    ///   })
+    ///   .build();
+    /// c.put(Vec::new());
+    /// c.put(String::from("TEST").into_bytes());
+    /// drop(c);
+    /// assert_eq!(rx.iter().collect::<Vec<_>>(), vec![b"test".to_vec()]);
+    /// ```
+    pub fn transform<F>(mut self, transform: F) -> Builder
+    where
+        F: Fn(Vec<u8>) -> Option<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.transform = Some(Box::new(transform));
+        self
+    }
+
+    /// Stamps every message with a CRC-32 of its payload at `put` and verifies
+    /// it on the poller thread before the message is consumed, e.g. after it
+    /// was read back from a spill file. A corrupted message is handed to the
+    /// error handler with a `ChecksumError` instead of being consumed, and
+    /// counted in `ConsumerStats::corrupted`.
+    ///
+    /// Streaming consumers only verify messages that weren't split into
+    /// chunks, as they read the others before all chunks have arrived.
+    pub fn checksums(mut self) -> Builder {
+        self.checksums = true;
+        self
+    }
+
+    /// Signs every message put on the `Carousel` with an HMAC-SHA256 under the
+    /// signing key of `keys`, after it passed the validator. Consumers behind
+    /// a trust boundary, such as a bridge to another process, check and strip
+    /// the signature with `verify`.
+    ///
+    /// A signed message is the little endian `u32` id of the signing key, the
+    /// payload and the HMAC-SHA256 of both.
+    pub fn signer<K>(mut self, keys: K) -> Builder
+    where
+        K: KeyProvider + 'static,
+    {
+        self.signer = Some(sync::Arc::new(keys));
+        self
+    }
+
+    /// Mirrors every message to `consumer` with the reason it is handed over:
+    /// each message that is put, and again each time it is rejected, dropped,
+    /// filtered out or expires before a consumer got to it. This gives a
+    /// complete record of what happened to every message, e.g. for compliance.
+    ///
+    /// The audit consumer runs on its own thread and receives a copy of each
+    /// message, in the order the records were made.
+    pub fn audit<T>(mut self, consumer: T) -> Builder
+    where
+        T: AuditConsumer + Send + 'static,
+    {
+        self.audit = Some(Box::new(consumer));
+        self
+    }
+
+    /// Sets the clock the `Carousel` timestamps messages with, expires them by
+    /// and paces rate limits and producer quotas with. Defaults to the
+    /// `SystemClock`; tests pass a `MockClock` to control time.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::{from_fn, Carousel, MockClock};
+    /// use std::time::Duration;
+    ///
+    /// let clock = MockClock::new();
+    /// let c = Carousel::builder()
+    ///   .consumer(from_fn(|_| {}))
+    ///   .ttl(Duration::from_secs(1))
+    ///   .clock(clock.clone())
+    ///   .build();
+    ///
+    /// c.put(vec![0]);
+    /// clock.advance(Duration::from_secs(1));
+    /// ```
+    pub fn clock<C>(mut self, clock: C) -> Builder
+    where
+        C: Clock + 'static,
+    {
+        self.clock = Some(sync::Arc::new(clock));
+        self
+    }
+
+    /// Injects `faults` into the deliveries to consumers, to test how an
+    /// application copes with delayed, lost, duplicated and failing
+    /// deliveries. Faults are drawn per consumer from `Faults::seed`, so a run
+    /// can be reproduced. Streaming consumers are not affected.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a rate of `faults` isn't between `0.0` and `1.0`.
+    pub fn faults(mut self, faults: Faults) -> Builder {
+        let rates = [
+            ("delay_rate", faults.delay_rate),
+            ("drop_rate", faults.drop_rate),
+            ("duplicate_rate", faults.duplicate_rate),
+            ("panic_rate", faults.panic_rate),
+        ];
+        for (name, rate) in &rates {
+            assert!(
+                (0.0..=1.0).contains(rate),
+                "the fault rate {} must be between 0.0 and 1.0",
+                name
+            );
+        }
+
+        self.faults = Some(faults);
+        self
+    }
+
+    /// Lets the poller thread of a consumer exit once no message arrived for
+    /// `timeout`, and starts a new one when the next message arrives, so
+    /// that many mostly idle consumers don't keep as many threads alive.
+    /// Consumers created with `Builder::consumer_factory` and streaming
+    /// consumers are bound to their thread and keep it.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Builder {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Watches every call to a consumer and reports those that take `timeout`
+    /// or longer, so that a hung downstream doesn't silently stall its
+    /// consumer. The call can't be interrupted; once `timeout` passed it is
+    /// counted in `ConsumerStats::timed_out` and handed to the error handler
+    /// as a `ConsumeTimeout`, from a watchdog thread and without the
+    /// message. `action` selects what becomes of the message once the call
+    /// returns; the messages of streaming consumers can't be read again and
+    /// are never retried.
+    pub fn consume_timeout(mut self, timeout: Duration, action: TimeoutAction) -> Builder {
+        assert!(timeout > Duration::ZERO);
+
+        self.consume_timeout = Some((timeout, action));
+        self
+    }
+
+    /// Catches consumers that panic instead of letting the panic end their
+    /// poller thread, and reacts as directed by `policy`. Consumers
+    /// registered with `Builder::supervised` are restarted by their
+    /// supervisor.
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Builder {
+        self.panic_policy = Some(policy);
+        self
+    }
+
+    /// Selects what dropping the `Carousel` does with the messages that
+    /// weren't consumed yet. The default, `DropPolicy::Drain`, consumes them
+    /// all first. Dropping clones of the `Carousel` never stops it.
+    pub fn drop_policy(mut self, policy: DropPolicy) -> Builder {
+        self.drop_policy = policy;
+        self
+    }
+
+    /// Builds a `Carousel` that forwards no messages until
+    /// `Carousel::start` is called, so that an application can set up all
+    /// its consumers and producers, check them, and then let the messages
+    /// flow at one point. Messages put and changes made in the meantime,
+    /// such as subscriptions, wait and apply in order once started.
+    ///
+    /// Stopping a `Carousel` starts it first.
+    pub fn start_paused(mut self) -> Builder {
+        self.start_paused = true;
+        self
+    }
+
+    /// Tracks the path of every message through chained carousels, with
+    /// `stage` naming this `Carousel`. A message put by a consumer of another
+    /// `Carousel` that tracks lineage, while it consumes a message, carries
+    /// the lineage of that message extended by `stage`, so a consumer at the
+    /// end of a pipeline sees every stage and how long the message spent in
+    /// it, see `Ctx::lineage`.
+    ///
+    /// Lineage is carried in the process only and doesn't reach streaming
+    /// consumers.
+    pub fn lineage(mut self, stage: &str) -> Builder {
+        self.stage = Some(sync::Arc::from(stage));
+        self
+    }
+
+    /// Hands each message to the consumers `order` first, in that order, and
+    /// then to the others in registration order. Consumers subscribed later
+    /// can be listed too.
+    ///
+    /// The order is the one in which messages are queued for the pollers;
+    /// the pollers still consume them independently. To let a consumer
+    /// finish with a message before the others see it, see
+    /// `Builder::primary`.
+    pub fn dispatch_order(mut self, order: Vec<SubscriptionId>) -> Builder {
+        self.dispatch_order = order;
+        self
+    }
+
+    /// Hands each message to the consumer `id` first, and to the other
+    /// consumers only once `id` returned from it, whatever the outcome, for a
+    /// consumer that validates or persists messages before others react.
+    ///
+    /// The consumers receiving a message are the ones subscribed when the
+    /// primary returned from it. The other consumers wait while the primary
+    /// is paused. Messages the primary doesn't receive, and the chunks of
+    /// messages split by `Builder::chunk_size`, go to the other consumers
+    /// right away, possibly before earlier messages the primary still holds,
+    /// so a primary can't be combined with `Builder::strict_ordering`.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::{from_fn, Carousel, SubscriptionId};
+    /// use std::sync::mpsc;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let c = Carousel::builder()
+    ///   .consumer(tx)
+    ///   .consumer(from_fn(|_data| { /* validate or persist the data */ }))
+    ///   .primary(SubscriptionId(1))
+    ///   .build();
+    /// c.put(String::from("test").into_bytes());
+    /// drop(c);
+    /// assert_eq!(rx.recv().unwrap(), b"test");
+    /// ```
+    pub fn primary(mut self, id: SubscriptionId) -> Builder {
+        self.primary = Some(id);
+        self.persist_first = false;
+        self
+    }
+
+    /// Puts the consumer `id` in the shutdown phase `phase`, 0 by default.
+    /// At shutdown the consumers of a phase consume their last messages and
+    /// stop before the consumers of the next phase are told to, e.g. so that
+    /// ingest consumers have handed everything on before the consumers
+    /// flushing it stop. The primary consumer, see `Builder::primary`, stops
+    /// first.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::{from_fn, Carousel, SubscriptionId};
+    ///
+    /// let c = Carousel::builder()
+    ///   .consumer(from_fn(|_data| { /* flush the data */ }))
+    ///   .consumer(from_fn(|_data| { /* ingest the data */ }))
+    ///   .shutdown_phase(SubscriptionId(0), 1)
+    ///   .build();
+    /// c.put(String::from("test").into_bytes());
+    /// c.shutdown();
+    /// ```
+    pub fn shutdown_phase(mut self, id: SubscriptionId, phase: u32) -> Builder {
+        self.shutdown_phases.insert(id.0, phase);
+        self
+    }
+
+    /// Registers `writer` as the primary consumer, see `Builder::primary`,
+    /// which hands a message on to the other consumers only once it consumed
+    /// it successfully. Messages the writer fails on, after
+    /// `Builder::retries`, filters or lets expire never reach the others, so
+    /// they never see a message that wasn't persisted.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::{Carousel, Error, TryConsumer};
+    /// use std::sync::mpsc;
+    ///
+    /// struct Journal;
+    ///
+    /// impl TryConsumer for Journal {
+    ///   fn try_consume(&mut self, data: Vec<u8>) -> Result<(), Error> {
+    ///     // append data to a journal
+    ///     Ok(())
+    ///   }
+    /// }
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let c = Carousel::builder()
+    ///   .consumer(tx)
+    ///   .durable_writer(Journal)
+    ///   .build();
+    /// c.put(String::from("test").into_bytes());
+    /// drop(c);
+    /// assert_eq!(rx.recv().unwrap(), b"test");
+    /// ```
+    pub fn durable_writer<T>(self, writer: T) -> Builder
+    where
+        T: CtxConsumer + Send + 'static,
+    {
+        let id = SubscriptionId(self.consumers.len());
+        let mut builder = self.consumer(writer);
+        builder.primary = Some(id);
+        builder.persist_first = true;
+        builder
+    }
+
+    /// Calls `handler` with the `ShutdownReport` once dropping the `Carousel`
+    /// stopped it. `Carousel::shutdown` returns the report instead.
+    pub fn on_shutdown<F>(mut self, handler: F) -> Builder
+    where
+        F: Fn(ShutdownReport) + Send + Sync + 'static,
+    {
+        self.on_shutdown = Some(Box::new(handler));
+        self
+    }
+
+    /// Names the threads of the `Carousel` `<prefix>-multiplier-<shard>`,
+    /// `<prefix>-poller-<consumer>` and so on, so they can be told apart in
+    /// debuggers and profilers.
+    pub fn thread_name(mut self, prefix: &str) -> Builder {
+        self.thread_name = Some(String::from(prefix));
+        self
+    }
+
+    /// Applies the settings in `config`, see `CarouselConfig`.
+    pub fn config(self, config: &CarouselConfig) -> Builder {
+        let mut builder = self
+            .retries(config.retries)
+            .wait_strategy(config.wait_strategy);
+        if let Some(bytes) = config.capacity {
+            builder = builder.memory_budget(bytes, Overflow::Block);
+        }
+        if config.shards > 1 {
+            builder = builder.multiplier_shards(config.shards, config.sharding);
+        }
+        if let Some(ttl) = config.ttl {
+            builder = builder.ttl(ttl);
+        }
+        if let Some(size) = config.chunk_size {
+            builder = builder.chunk_size(size);
+        }
+        if let Some(prefix) = &config.thread_name {
+            builder = builder.thread_name(prefix);
+        }
+        builder
+    }
+
+    /// Selects how pollers and multiplier threads wait for work. The default,
+    /// `WaitStrategy::Block`, uses no CPU while idle; spinning trades CPU for
+    /// lower delivery latency.
+    pub fn wait_strategy(mut self, wait: WaitStrategy) -> Builder {
+        self.wait = wait;
+        self
+    }
+
+    /// Creates the `Carousel` and starts its multiplier and poller threads.
+    /// A `Carousel` built without consumers drops the messages put until
+    /// consumers subscribe, e.g. with `Carousel::subscribe`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the options contradict each other:
+    /// - `Builder::multiplier_shards` together with `Builder::persistence`,
+    /// - `Builder::strict_ordering` together with more than one multiplier
+    ///   shard, `Builder::primary` or `Builder::durable_writer`,
+    /// - `Overflow::Spill` for `Builder::memory_budget`,
+    ///   `Builder::namespace_quota` or `Builder::consumer_memory_limit`
+    ///   together with `Builder::persistence`,
+    /// - `Builder::chunk_size` together with `Builder::persistence`,
+    /// - `Builder::compact_log` without `Builder::persistence`.
+    pub fn build(self) -> Carousel {
+        assert!(
+            self.shards.is_none() || self.wal.is_none(),
+            "multiplier shards can't be combined with persistence"
+        );
+
+        assert!(
+            !self.strict_ordering || self.shards.is_none_or(|(shards, _)| shards == 1),
+            "strict ordering can't be combined with more than one multiplier shard"
+        );
+
+        assert!(
+            !self.strict_ordering || self.primary.is_none(),
+            "strict ordering can't be combined with a primary consumer"
+        );
+
+        assert!(
+            self.wal.is_none() || !self.budget.as_ref().is_some_and(|b| b.spills()),
+            "spilling messages can't be combined with persistence"
+        );
+
+        assert!(
+            self.wal.is_none()
+                || !self
+                    .namespaces
+                    .values()
+                    .any(|tenant| tenant.quota.as_ref().is_some_and(|q| q.spills())),
+            "spilling the messages of a namespace can't be combined with persistence"
+        );
+
+        assert!(
+            self.wal.is_none() || !self.consumer_budgets.values().any(|b| b.spills()),
+            "spilling the messages of a consumer can't be combined with persistence"
+        );
+
+        assert!(
+            self.wal.is_none() || self.chunk_size.is_none(),
+            "chunking messages can't be combined with persistence"
+        );
+
+        assert!(
+            self.compaction.is_none() || self.wal.is_some(),
+            "compacting the log needs persistence"
+        );
+
+        let (shards, sharding) = self.shards.unwrap_or((1, Sharding::RoundRobin));
+        let (txs, rxs): (Vec<_>, Vec<_>) = (0..shards).map(|_| mpsc::channel::<Event>()).unzip();
+
+        let (wal, replay) = match self.wal {
+            Some((wal, replay)) => (Some(wal), replay),
+            None => (None, Vec::new()),
+        };
+        let (audit_tx, audit_rx) = mpsc::channel();
+        let clock = self.clock.unwrap_or_else(|| sync::Arc::new(SystemClock));
+        let started = clock.now();
+        let shared = sync::Arc::new(Shared {
+            shards: txs,
+            propagator: self.propagator,
+            error_handler: self.error_handler,
+            retries: self.retries,
+            dedup: self.dedup,
+            wal,
+            compaction: self.compaction.map(|(horizon, _)| horizon),
+            alerts: self.depth_alerts.map(sync::Arc::new),
+            retain: self.retain,
+            retained: sync::Mutex::new(HashMap::new()),
+            expired_retained: AtomicU64::new(0),
+            ttl: self.ttl,
+            next_id: AtomicUsize::new(self.consumers.len()),
+            sharding,
+            next_shard: AtomicUsize::new(0),
+            wait: self.wait,
+            budget: self.budget.map(sync::Arc::new),
+            consumer_budgets: self
+                .consumer_budgets
+                .into_iter()
+                .map(|(id, budget)| (id, sync::Arc::new(budget)))
+                .collect(),
+            chunk_size: self.chunk_size,
+            next_message: sync::Mutex::new(0),
+            thread_name: self.thread_name,
+            routes: sync::RwLock::new(Vec::with_capacity(self.consumers.len())),
+            pollers: sync::Mutex::new(Vec::with_capacity(self.consumers.len())),
+            namespaces: sync::Mutex::new(self.namespaces),
+            producers: sync::Mutex::new(self.producers),
+            validator: self.validator,
+            rejected: AtomicU64::new(0),
+            transform: self.transform,
+            filtered: AtomicU64::new(0),
+            checksums: self.checksums,
+            signer: self.signer,
+            audit: self.audit.as_ref().map(|_| audit_tx.clone()),
+            throughput: metrics::Window::new(clock.now()),
+            wall: (started, SystemTime::now()),
+            clock,
+            faults: self.faults,
+            idle_timeout: self.idle_timeout,
+            consume_timeout: self.consume_timeout,
+            panic_policy: self.panic_policy,
+            drop_policy: self.drop_policy,
+            on_shutdown: self.on_shutdown,
+            started,
+            dispatch_order: self.dispatch_order.iter().map(|id| id.0).collect(),
+            primary: self.primary.map(|id| id.0),
+            shutdown_phases: self.shutdown_phases,
+            persist_first: self.persist_first,
+            discarding: AtomicBool::new(false),
+            in_transit: AtomicUsize::new(0),
+            stage: self.stage,
+            errors: errors::Listeners::default(),
+            quiesced: AtomicBool::new(false),
+            closed: AtomicBool::new(false),
+            putting: AtomicUsize::new(0),
+            held: sync::Mutex::new(self.start_paused),
+            released: sync::Condvar::new(),
+            progress: sync::Arc::default(),
+            discarded: AtomicU64::new(0),
+        });
+
+        let auditor = self.audit.map(|consumer| {
+            let thread = shared.spawn(String::from("auditor"), move || {
+                audit::Auditor::run(consumer, audit_rx)
+            });
+            audit::Auditor::new(audit_tx, thread)
+        });
+        let multiplier = Multipier::new(self.consumers, &shared, replay, rxs);
+        let sweeper = self.sweep_interval.map(|interval| {
+            Sweeper::new(
+                "sweeper",
+                interval,
+                sync::Arc::clone(&shared),
+                Shared::sweep,
+            )
+        });
+        let watchdog = self.consume_timeout.map(|(timeout, _)| {
+            let interval = (timeout / 4).max(Duration::from_millis(1));
+            Sweeper::new(
+                "watchdog",
+                interval,
+                sync::Arc::clone(&shared),
+                Shared::watch,
+            )
+        });
+        let compactor = self.compaction.map(|(_, interval)| {
+            Sweeper::new(
+                "compactor",
+                interval,
+                sync::Arc::clone(&shared),
+                Shared::compact,
+            )
+        });
+
+        Carousel {
+            shared,
+            producer: None,
+            multiplier: Some(multiplier),
+            sweeper,
+            watchdog,
+            compactor,
+            auditor,
+            buffer: None,
+        }
+    }
+}
+
+impl Clone for Carousel {
+    fn clone(&self) -> Self {
+        let mut carousel = Carousel::handle(&self.shared);
+        carousel.producer = self.producer.clone();
+        carousel.buffer = self.buffer.clone();
+        carousel
+    }
+}
+
+impl fmt::Debug for Carousel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut consumers: Vec<usize> = self
+            .shared
+            .routes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|route| route.slot.id)
+            .collect();
+        consumers.sort_unstable();
+        f.debug_struct("Carousel")
+            .field("owner", &self.multiplier.is_some())
+            .field("consumers", &consumers)
+            .field("shards", &self.shared.shards.len())
+            .field("drop_policy", &self.shared.drop_policy)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for Carousel {
+    fn drop(&mut self) {
+        if self.multiplier.is_some() && self.shared.drop_policy == DropPolicy::Detach {
+            self.leak_threads();
+            return;
+        }
+        if let (Some(report), Some(handler)) = (self.shut_down(), &self.shared.on_shutdown) {
+            handler(report);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asynch::{
+        block_on, from_fn, AsyncConsumer, AuditReason, AuditRecord, Carousel, CarouselConfig,
+        CarouselError, ChecksumError, ConfigDelta, ConsumeError, ConsumeFuture, ConsumeTimeout,
+        Consumer, ConsumerPanic, Context, Ctx, CtxConsumer, DepthAlert, DropPolicy, DropReason,
+        Error, Faults, FlushTriggers, InjectedPanic, MockClock, Overflow, PanicPolicy,
+        ProducerQuota, Propagator, PutError, RoutingTable, Sharding, SpillFile, StreamingConsumer,
+        SubscriptionId, Supervisor, TimeoutAction, TryConsumer, ValidationError, WaitStrategy,
+    };
+    use std::cell::Cell;
+    use std::io::Read;
+    use std::rc::Rc;
+    use std::sync::{self, mpsc};
+    use std::time::{Duration, Instant};
+
+    struct Counter(sync::Arc<sync::atomic::AtomicUsize>);
+
+    impl Consumer for Counter {
+        fn consume(&mut self, _data: Vec<u8>) {
+            self.0.fetch_add(1, sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    #[allow(clippy::vec_init_then_push)]
+    fn basic() {
+        struct TestConsumer1;
+
+        impl Consumer for TestConsumer1 {
+            fn consume(&mut self, data: Vec<u8>) {
+                assert_eq!(String::from_utf8(data).unwrap(), String::from("test"));
+            }
+        }
+
+        struct TestConsumer2;
+
+        impl Consumer for TestConsumer2 {
+            fn consume(&mut self, data: Vec<u8>) {
+                assert_eq!(String::from_utf8(data).unwrap(), String::from("test"));
+            }
+        }
+
+        let mut v: Vec<Box<dyn Consumer + Send + 'static>> = Vec::new();
+        v.push(Box::new(TestConsumer1));
+        v.push(Box::new(TestConsumer2));
+        let c = Carousel::new(v);
+
+        c.put(String::from("test").into_bytes());
+        c.put(String::from("test").into_bytes());
+        c.put(String::from("test").into_bytes());
+        c.put(String::from("test").into_bytes());
+
+        std::thread::sleep(std::time::Duration::from_secs(2));
+    }
+
+    #[test]
+    #[allow(clippy::vec_init_then_push)]
+    fn multi_producer() {
+        struct TestConsumer1;
+
+        impl Consumer for TestConsumer1 {
+            fn consume(&mut self, data: Vec<u8>) {
+                assert_eq!(String::from_utf8(data).unwrap(), String::from("test"));
+            }
+        }
+
+        struct TestConsumer2;
+
+        impl Consumer for TestConsumer2 {
+            fn consume(&mut self, data: Vec<u8>) {
+                assert_eq!(String::from_utf8(data).unwrap(), String::from("test"));
+            }
+        }
+
+        let mut v: Vec<Box<dyn Consumer + Send + 'static>> = Vec::new();
+        v.push(Box::new(TestConsumer1));
+        v.push(Box::new(TestConsumer2));
+        let c = Carousel::new(v);
+
+        for _ in 1..10 {
+            let cloned_c = c.clone();
+            let t = std::thread::spawn(move || {
+                cloned_c.put(String::from("test").into_bytes());
+            });
+
+            t.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn depth_alert() {
+        struct GatedConsumer(mpsc::Receiver<()>);
+
+        impl Consumer for GatedConsumer {
+            fn consume(&mut self, _data: Vec<u8>) {
+                self.0.recv().unwrap();
+            }
+        }
+
+        let (gate_tx, gate_rx) = mpsc::channel();
+        let (alert_tx, alert_rx) = mpsc::channel();
+
+        let c = Carousel::builder()
+            .consumer(GatedConsumer(gate_rx))
+            .depth_alert(3, 1, move |alert| alert_tx.send(alert).unwrap())
+            .build();
+
+        for _ in 0..3 {
+            c.put(String::from("test").into_bytes());
+        }
+
+        assert_eq!(
+            alert_rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            DepthAlert::Raised {
+                consumer: 0,
+                depth: 3
+            }
+        );
+
+        for _ in 0..3 {
+            gate_tx.send(()).unwrap();
+        }
+
+        assert_eq!(
+            alert_rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            DepthAlert::Cleared {
+                consumer: 0,
+                depth: 1
+            }
+        );
+    }
+
+    #[test]
+    fn propagator() {
+        thread_local! {
+            static TRACE_ID: Cell<u32> = const { Cell::new(0) };
+        }
+
+        struct TracePropagator;
+
+        impl Propagator for TracePropagator {
+            fn capture(&self) -> Context {
+                sync::Arc::new(TRACE_ID.with(|id| id.get()))
+            }
+
+            fn scope(&self, context: &Context, consume: &mut dyn FnMut()) {
+                let id = *context.downcast_ref::<u32>().unwrap();
+                TRACE_ID.with(|current| current.set(id));
+                consume();
+                TRACE_ID.with(|current| current.set(0));
+            }
+        }
+
+        struct TraceConsumer(mpsc::Sender<u32>);
+
+        impl Consumer for TraceConsumer {
+            fn consume(&mut self, _data: Vec<u8>) {
+                self.0.send(TRACE_ID.with(|id| id.get())).unwrap();
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+
+        let c = Carousel::builder()
+            .consumer(TraceConsumer(tx))
+            .propagator(TracePropagator)
+            .build();
+
+        TRACE_ID.with(|id| id.set(42));
+        c.put(String::from("test").into_bytes());
+        TRACE_ID.with(|id| id.set(7));
+        c.put(String::from("test").into_bytes());
+
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 42);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), 7);
+    }
+
+    #[test]
+    fn feed_from_iter() {
+        let count = sync::Arc::new(sync::atomic::AtomicUsize::new(0));
+
+        let c = Carousel::builder()
+            .consumer(Counter(sync::Arc::clone(&count)))
+            .depth_alert(8, 2, |_| {})
+            .build();
+
+        let feed = c.feed_from_iter((0..100).map(|i: u32| i.to_be_bytes().to_vec()));
+
+        assert_eq!(feed.join(), 100);
+        drop(c);
+        assert_eq!(count.load(sync::atomic::Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn feed_from_reader() {
+        // Hands out one line per read, counting the reads.
+        struct Lines(sync::Arc<sync::atomic::AtomicUsize>);
+
+        impl std::io::Read for Lines {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let read = self.0.fetch_add(1, sync::atomic::Ordering::SeqCst);
+                if read >= 100 {
+                    return Ok(0);
+                }
+                buf[..2].copy_from_slice(&[b' ' + read as u8, b'\n']);
+                Ok(2)
+            }
+        }
+
+        let open = sync::Arc::new(sync::atomic::AtomicBool::new(false));
+        let gate = sync::Arc::clone(&open);
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(from_fn(move |data| {
+                while !gate.load(sync::atomic::Ordering::SeqCst) {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                tx.send(data).unwrap();
+            }))
+            .depth_alert(4, 1, |_| {})
+            .build();
+
+        let reads = sync::Arc::new(sync::atomic::AtomicUsize::new(0));
+        let feed = c.feed_from_reader(Lines(sync::Arc::clone(&reads)), b'\n');
+        std::thread::sleep(Duration::from_millis(100));
+        // The feeder stopped reading while the consumer is behind.
+        assert!(reads.load(sync::atomic::Ordering::SeqCst) < 20);
+
+        open.store(true, sync::atomic::Ordering::SeqCst);
+        assert_eq!(feed.join(), 100);
+        drop(c);
+        let messages: Vec<Vec<u8>> = rx.try_iter().collect();
+        assert_eq!(messages.len(), 100);
+        assert_eq!(messages[42], vec![b' ' + 42]);
+    }
+
+    #[test]
+    fn error_handler() {
+        struct Flaky(usize);
+
+        impl TryConsumer for Flaky {
+            fn try_consume(&mut self, data: Vec<u8>) -> Result<(), Error> {
+                self.0 += 1;
+                if data == b"poison" || self.0 % 2 == 1 {
+                    return Err("flaky".into());
+                }
+                Ok(())
+            }
+        }
+
+        let (tx, rx) = mpsc::channel::<ConsumeError>();
+
+        let c = Carousel::builder()
+            .consumer(Flaky(0))
+            .retries(1)
+            .error_handler(move |e| tx.send(e).unwrap())
+            .build();
+
+        c.put(String::from("test").into_bytes());
+        c.put(String::from("poison").into_bytes());
+        drop(c);
+
+        let errors: Vec<ConsumeError> = rx.iter().collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].data, b"poison".to_vec());
+        assert_eq!(errors[0].attempts, 2);
+    }
+
+    #[test]
+    fn consumer_factory() {
+        struct Local(Rc<mpsc::Sender<Vec<u8>>>);
+
+        impl Consumer for Local {
+            fn consume(&mut self, data: Vec<u8>) {
+                self.0.send(data).unwrap();
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+
+        let c = Carousel::builder()
+            .consumer_factory(move || Local(Rc::new(tx)))
+            .build();
+
+        c.put(String::from("test").into_bytes());
+        drop(c);
+
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![b"test".to_vec()]);
+    }
+
+    #[test]
+    fn warming_consumer() {
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .warming_consumer(move |readiness| {
+                ready_tx.send(readiness).unwrap();
+                from_fn(move |data| tx.send(data).unwrap())
+            })
+            .build();
+        c.put(vec![1]);
+        c.put(vec![2]);
+
+        let readiness = ready_rx.recv().unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(c.stats().consumers[0].depth, 2);
+
+        readiness.ready();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![1]);
+        drop(c);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![vec![2]]);
+    }
+
+    #[test]
+    fn batch_consumer() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .batch_consumer(
+                tx,
+                FlushTriggers {
+                    messages: 100,
+                    delay: Duration::from_millis(200),
+                    bytes: 8,
+                },
+            )
+            .build();
+
+        // The bytes trigger flushes the first batch, the delay the second.
+        c.put(vec![1; 5]);
+        c.put(vec![2; 4]);
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            vec![vec![1; 5], vec![2; 4]]
+        );
+        c.put(vec![3]);
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            vec![vec![3]]
+        );
+        c.put(vec![4]);
+        drop(c);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![vec![vec![4]]]);
+    }
+
+    #[test]
+    fn start_paused() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder().start_paused().build();
+        c.put(vec![1]);
+        c.subscribe("late", tx.clone());
+        c.put_to("late", vec![2]);
+        let id = c.subscribe("topic", tx);
+        c.put_to("topic", vec![3]);
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(rx.try_recv().is_err());
+        assert_eq!(c.stats().consumers.len(), 0);
+
+        c.start();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![2]);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![3]);
+        c.remove(id);
+        drop(c);
+        assert!(rx.try_recv().is_err());
+
+        // Stopping a carousel that was never started forwards its messages.
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder().consumer(tx).start_paused().build();
+        c.put(vec![4]);
+        drop(c);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![vec![4]]);
+    }
+
+    #[test]
+    fn buffered() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder().consumer(tx).start_paused().build();
+        let buffered = c.buffered(2);
+        assert_eq!(buffered.try_put(vec![1]), Ok(()));
+        assert_eq!(buffered.clone().try_put(vec![2]), Ok(()));
+        assert_eq!(buffered.try_put(vec![3]), Err(PutError::BufferFull));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(rx.try_recv().is_err());
+
+        // The buffer is flushed in order once the carousel is writable.
+        c.start();
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![1]);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![2]);
+        assert_eq!(buffered.try_put(vec![4]), Ok(()));
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![4]);
+        drop(buffered);
+        drop(c);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn shutdown_phases() {
+        // Records when each consumer stopped.
+        struct Stop(usize, mpsc::Sender<usize>);
+
+        impl Consumer for Stop {
+            fn consume(&mut self, _data: Vec<u8>) {
+                std::thread::sleep(Duration::from_millis(10 * self.0 as u64));
+            }
+        }
+
+        impl Drop for Stop {
+            fn drop(&mut self) {
+                self.1.send(self.0).unwrap();
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(Stop(0, tx.clone()))
+            .consumer(Stop(1, tx.clone()))
+            .consumer(Stop(2, tx))
+            .shutdown_phase(SubscriptionId(0), 2)
+            .shutdown_phase(SubscriptionId(2), 1)
+            .build();
+        c.put(vec![1]);
+        c.shutdown();
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![1, 2, 0]);
+    }
+
+    #[test]
+    fn lineage() {
+        struct Path(mpsc::Sender<Vec<String>>);
+
+        impl CtxConsumer for Path {
+            fn consume_with(&mut self, ctx: &Ctx<'_>, _data: Vec<u8>) -> Result<(), Error> {
+                let lineage = ctx.lineage().unwrap();
+                assert_eq!(lineage.latencies().len(), lineage.stages().len() - 1);
+                let stages = lineage.stages().iter().map(|s| s.name.to_string());
+                self.0.send(stages.collect()).unwrap();
+                Ok(())
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let store = Carousel::builder()
+            .lineage("store")
+            .consumer(Path(tx))
+            .build();
+        let forward = store.clone();
+        let enrich = Carousel::builder()
+            .lineage("enrich")
+            .consumer(from_fn(move |data| forward.put(data)))
+            .build();
+        let forward = enrich.clone();
+        let ingest = Carousel::builder()
+            .lineage("ingest")
+            .consumer(from_fn(move |data| forward.put(data)))
+            .build();
+
+        ingest.put(vec![1]);
+        store.put(vec![2]);
+        let mut paths: Vec<Vec<String>> = (0..2)
+            .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        paths.sort_by_key(|path| path.len());
+        assert_eq!(
+            paths,
+            vec![vec!["store"], vec!["ingest", "enrich", "store"]]
+        );
+        drop(ingest);
+        drop(enrich);
+    }
+
+    #[test]
+    fn errors() {
+        let c = Carousel::builder()
+            .consumer(from_fn(|data: Vec<u8>| assert_ne!(data, vec![0], "boom")))
+            .panic_policy(PanicPolicy::Restart)
+            .build();
+        let errors = c.errors();
+        c.put(vec![0]);
+        let panic = errors.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            panic,
+            CarouselError::ConsumerPanicked { consumer: 0, .. }
+        ));
+        assert!(matches!(
+            errors.recv_timeout(Duration::from_secs(5)).unwrap(),
+            CarouselError::ConsumeFailed {
+                consumer: 0,
+                attempts: 1,
+                ..
+            }
+        ));
+        drop(c);
+
+        let (tx, _rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .drop_policy(DropPolicy::Discard)
+            .build();
+        let errors = c.errors();
+        c.pause(SubscriptionId(0));
+        c.put(vec![1]);
+        while c.stats().consumers[0].depth == 0 {
+            std::thread::yield_now();
+        }
+        drop(c);
+        assert_eq!(
+            errors.try_iter().collect::<Vec<_>>(),
+            vec![CarouselError::Dropped {
+                consumer: Some(0),
+                reason: DropReason::Discarded,
+                count: 1,
+            }]
+        );
+    }
+
+    #[test]
+    fn strict_ordering() {
+        let (tx1, rx1) = mpsc::channel();
+        let (tx2, rx2) = mpsc::channel();
+
+        let c = Carousel::builder().consumer(tx1).consumer(tx2).build();
+
+        let producers: Vec<_> = (0..4u8)
+            .map(|producer| {
+                let c = c.clone();
+                std::thread::spawn(move || {
+                    for i in 0..100u8 {
+                        c.put(vec![producer, i]);
+                    }
+                })
+            })
+            .collect();
+        producers.into_iter().for_each(|p| p.join().unwrap());
+        drop(c);
+
+        let seen1: Vec<Vec<u8>> = rx1.iter().collect();
+        let seen2: Vec<Vec<u8>> = rx2.iter().collect();
+        assert_eq!(seen1.len(), 400);
+        assert_eq!(seen1, seen2);
+
+        for producer in 0..4u8 {
+            let own: Vec<u8> = seen1
+                .iter()
+                .filter(|m| m[0] == producer)
+                .map(|m| m[1])
+                .collect();
+            assert_eq!(own, (0..100u8).collect::<Vec<_>>());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "strict ordering")]
+    fn strict_ordering_rules_out_shards() {
+        Carousel::builder()
+            .strict_ordering()
+            .multiplier_shards(2, Sharding::ByTopic)
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "primary consumer")]
+    fn strict_ordering_rules_out_a_primary() {
+        Carousel::builder()
+            .consumer(from_fn(|_| {}))
+            .consumer(from_fn(|_| {}))
+            .primary(SubscriptionId(1))
+            .strict_ordering()
+            .build();
+    }
+
+    #[test]
+    #[should_panic(expected = "compacting the log needs persistence")]
+    fn compaction_needs_persistence() {
+        Carousel::builder()
+            .compact_log(Duration::from_secs(60), Duration::from_secs(1))
+            .build();
+    }
+
+    #[test]
+    fn topics_and_retained_messages() {
+        let (config_tx, config_rx) = mpsc::channel();
+        let (all_tx, all_rx) = mpsc::channel();
+        let (late_tx, late_rx) = mpsc::channel();
+
+        let c = Carousel::builder()
+            .subscriber("config", config_tx)
+            .consumer(all_tx)
+            .retain()
+            .build();
+
+        c.put_to("config", String::from("v1").into_bytes());
+        c.put_to("other", String::from("other").into_bytes());
+        c.put(String::from("plain").into_bytes());
+        c.subscribe("config", late_tx);
+        c.put_to("config", String::from("v2").into_bytes());
+        drop(c);
+
+        let received = |rx: mpsc::Receiver<Vec<u8>>| {
+            rx.iter()
+                .map(|data| String::from_utf8(data).unwrap())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(received(config_rx), vec!["v1", "v2"]);
+        assert_eq!(received(all_rx), vec!["v1", "other", "plain", "v2"]);
+        assert_eq!(received(late_rx), vec!["v1", "v2"]);
+    }
+
+    #[test]
+    fn sweeper_reclaims_expired_messages() {
+        struct GatedConsumer(mpsc::Receiver<()>);
+
+        impl Consumer for GatedConsumer {
+            fn consume(&mut self, _data: Vec<u8>) {
+                let _ = self.0.recv_timeout(Duration::from_secs(5));
+            }
+        }
+
+        let (gate_tx, gate_rx) = mpsc::channel();
+
+        let c = Carousel::builder()
+            .consumer(GatedConsumer(gate_rx))
+            .retain()
+            .ttl(Duration::from_millis(50))
+            .sweep_every(Duration::from_millis(10))
+            .build();
+
+        // Let the poller take the first message, so that the others stay queued.
+        c.put_to("state", String::from("test").into_bytes());
+        std::thread::sleep(Duration::from_millis(20));
+        for _ in 0..2 {
+            c.put_to("state", String::from("test").into_bytes());
+        }
+
+        std::thread::sleep(Duration::from_millis(200));
+
+        let stats = c.stats();
+        assert_eq!(stats.consumers[0].expired, 2);
+        assert_eq!(stats.consumers[0].depth, 1);
+        assert_eq!(stats.expired_retained, 1);
+
+        gate_tx.send(()).unwrap();
+    }
+
+    #[test]
+    fn sharded_multiplier() {
+        let (tx1, rx1) = mpsc::channel();
+        let (tx2, rx2) = mpsc::channel();
+
+        let c = Carousel::builder()
+            .consumer(tx1)
+            .consumer(tx2)
+            .multiplier_shards(4, Sharding::ByTopic)
+            .build();
+
+        for i in 0..100u8 {
+            c.put_to(&format!("topic-{}", i % 3), vec![i % 3, i]);
+        }
+        drop(c);
+
+        for rx in [rx1, rx2] {
+            let received: Vec<Vec<u8>> = rx.iter().collect();
+            assert_eq!(received.len(), 100);
+            for topic in 0..3u8 {
+                let own: Vec<u8> = received
+                    .iter()
+                    .filter(|m| m[0] == topic)
+                    .map(|m| m[1])
+                    .collect();
+                assert_eq!(
+                    own,
+                    (0..100u8).filter(|i| i % 3 == topic).collect::<Vec<_>>()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn wait_strategies() {
+        for wait in [
+            WaitStrategy::Block,
+            WaitStrategy::SpinThenPark { spins: 100 },
+            WaitStrategy::BusySpin,
+        ] {
+            let (tx, rx) = mpsc::channel();
+            let c = Carousel::builder().consumer(tx).wait_strategy(wait).build();
+
+            for i in 0..100u8 {
+                c.put(vec![i]);
+            }
+            assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![0]);
+            drop(c);
+
+            assert_eq!(rx.iter().count(), 99);
+        }
+    }
+
+    #[test]
+    fn memory_budget() {
+        struct GatedConsumer(mpsc::Sender<Vec<u8>>, mpsc::Receiver<()>);
+
+        impl Consumer for GatedConsumer {
+            fn consume(&mut self, data: Vec<u8>) {
+                self.0.send(data).unwrap();
+                let _ = self.1.recv_timeout(Duration::from_secs(5));
+            }
+        }
+
+        let dir = std::env::temp_dir().join(format!("mp2c-spill-{}", std::process::id()));
+        for spill in [false, true] {
+            let overflow = match spill {
+                true => Overflow::Spill(SpillFile::create(&dir).unwrap()),
+                false => Overflow::Drop,
+            };
+            let (tx, rx) = mpsc::channel();
+            let (gate_tx, gate_rx) = mpsc::channel();
+            let c = Carousel::builder()
+                .consumer(GatedConsumer(tx, gate_rx))
+                .memory_budget(10, overflow)
+                .build();
+
+            c.put(b"message0".to_vec());
+            assert_eq!(
+                rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+                b"message0"
+            );
+            c.put(b"message1".to_vec());
+            c.put(b"message2".to_vec());
+
+            let stats = c.stats();
+            assert_eq!(stats.queued_bytes, 8);
+            assert_eq!(stats.dropped, if spill { 0 } else { 2 });
+
+            for _ in 0..3 {
+                gate_tx.send(()).unwrap();
+            }
+            drop(c);
+            let rest: Vec<Vec<u8>> = rx.iter().collect();
+            match spill {
+                true => assert_eq!(rest, vec![b"message1".to_vec(), b"message2".to_vec()]),
+                false => assert!(rest.is_empty()),
+            }
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn consumer_memory_limit() {
+        let (gated_tx, gated_rx) = mpsc::channel();
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let gated = from_fn(move |data: Vec<u8>| {
+            gated_tx.send(data).unwrap();
+            let _ = gate_rx.recv_timeout(Duration::from_secs(5));
+        });
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(gated)
+            .consumer(tx)
+            .consumer_memory_limit(SubscriptionId(0), 10, Overflow::Drop)
+            .build();
+
+        c.put(b"message0".to_vec());
+        assert_eq!(
+            gated_rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            b"message0"
+        );
+        c.put(b"message1".to_vec());
+        c.put(b"message2".to_vec());
+        // The other consumer receives every message, after the gated one was
+        // handed its copies.
+        for _ in 0..3 {
+            rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+
+        let stats = c.stats();
+        assert_eq!(stats.consumers[0].queued_bytes, 8);
+        assert_eq!(stats.consumers[0].dropped, 2);
+        assert_eq!(stats.consumers[1].dropped, 0);
+        assert_eq!(stats.dropped, 0);
+
+        gate_tx.send(()).unwrap();
+        drop(c);
+        assert!(gated_rx.iter().next().is_none());
+    }
+
+    #[test]
+    fn consumer_memory_limit_blocks() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .consumer_memory_limit(SubscriptionId(0), 100, Overflow::Block)
+            .start_paused()
+            .build();
+
+        // The messages reach the multiplier at once and are forwarded in
+        // batches, each of which holds more than the limit.
+        for i in 0..50 {
+            c.put(vec![i; 60]);
+        }
+        c.start();
+        for i in 0..50 {
+            assert_eq!(
+                rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+                vec![i; 60]
+            );
+        }
+        assert_eq!(c.stats().consumers[0].dropped, 0);
+    }
+
+    #[test]
+    fn remove_while_blocked_on_memory_limit() {
+        let (done_tx, done_rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let (tx, rx) = mpsc::channel();
+            let c = Carousel::builder()
+                .supervised(
+                    Supervisor::one_for_one().max_restarts(0, Duration::from_secs(60)),
+                    || {
+                        from_fn(|_| {
+                            // Give the multiplier time to block on the limit.
+                            std::thread::sleep(Duration::from_millis(50));
+                            panic!("poison");
+                        })
+                    },
+                )
+                .consumer(tx)
+                .consumer_memory_limit(SubscriptionId(0), 100, Overflow::Block)
+                .build();
+
+            // The supervisor removes its consumer while the multiplier waits
+            // for that consumer to finish the first message.
+            for i in 0..3 {
+                c.put(vec![i; 60]);
+            }
+            for i in 0..3 {
+                assert_eq!(rx.recv().unwrap(), vec![i; 60]);
+            }
+            while c.stats().consumers.len() > 1 {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            drop(c);
+            done_tx.send(()).unwrap();
+        });
+        done_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+    }
+
+    #[test]
+    fn reconfigure() {
+        let (tx, rx) = mpsc::channel();
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let gated = from_fn(move |data: Vec<u8>| {
+            tx.send(data).unwrap();
+            let _ = gate_rx.recv_timeout(Duration::from_secs(5));
+        });
+        let c = Carousel::builder()
+            .consumer(gated)
+            .memory_budget(10, Overflow::Drop)
+            .build();
+
+        c.put(b"message0".to_vec());
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            b"message0"
+        );
+        c.put(b"message1".to_vec());
+        assert_eq!(c.stats().dropped, 1);
+
+        c.reconfigure(ConfigDelta {
+            capacity: Some(100),
+            ..ConfigDelta::default()
+        });
+        // The multiplier applies the delta shortly after.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while c.stats().queued_bytes == 8 {
+            assert!(Instant::now() < deadline);
+            c.put(b"message2".to_vec());
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(c.stats().queued_bytes, 16);
+
+        drop(gate_tx);
+        drop(c);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![b"message2".to_vec()]);
+    }
+
+    #[test]
+    fn reconfigure_while_blocked() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .memory_budget(10, Overflow::Block)
+            .start_paused()
+            .build();
+        c.reconfigure(ConfigDelta {
+            overflow: Some(Overflow::Drop),
+            ..ConfigDelta::default()
+        });
+
+        c.put(b"message0".to_vec());
+        let blocked = c.clone();
+        let put = std::thread::spawn(move || blocked.put(b"message1".to_vec()));
+        std::thread::sleep(Duration::from_millis(20));
+        // The multiplier changes the policy while the second put blocks.
+        c.start();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            b"message0"
+        );
+        put.join().unwrap();
+
+        // The second message was let in once the first was consumed, or
+        // dropped by the new policy.
+        let dropped = c.stats().dropped;
+        drop(c);
+        assert_eq!(rx.iter().count() as u64 + dropped, 1);
+    }
+
+    #[test]
+    fn put_vectored() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder().consumer(tx).build();
+
+        c.put_vectored(&[b"header:", b"", b"body"]);
+        drop(c);
+
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![b"header:body".to_vec()]);
+    }
+
+    #[test]
+    fn chunking() {
+        let (tx1, rx1) = mpsc::channel();
+        let (tx2, rx2) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx1)
+            .consumer(tx2)
+            .chunk_size(4)
+            .multiplier_shards(2, Sharding::RoundRobin)
+            .build();
+
+        let messages = vec![b"".to_vec(), b"four".to_vec(), b"longer than four".to_vec()];
+        for message in &messages {
+            c.put(message.clone());
+        }
+        drop(c);
+
+        for rx in [rx1, rx2] {
+            let mut received: Vec<Vec<u8>> = rx.iter().collect();
+            received.sort();
+            assert_eq!(received, messages);
+        }
+    }
+
+    #[test]
+    fn streaming_consumer() {
+        struct Reader(mpsc::Sender<Vec<Vec<u8>>>);
+
+        impl StreamingConsumer for Reader {
+            fn consume(&mut self, payload: &mut dyn Read) -> Result<(), Error> {
+                let mut reads = Vec::new();
+                let mut buf = [0; 3];
+                loop {
+                    match payload.read(&mut buf)? {
+                        0 => break,
+                        n => reads.push(buf[..n].to_vec()),
+                    }
+                }
+                self.0.send(reads).unwrap();
+                Ok(())
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .streaming_consumer(Reader(tx))
+            .chunk_size(4)
+            .build();
+
+        c.put(b"abcdefghij".to_vec());
+        c.put(b"xy".to_vec());
+        drop(c);
+
+        let received: Vec<Vec<Vec<u8>>> = rx.iter().collect();
+        assert_eq!(
+            received,
+            vec![
+                vec![
+                    b"abc".to_vec(),
+                    b"d".to_vec(),
+                    b"efg".to_vec(),
+                    b"h".to_vec(),
+                    b"ij".to_vec()
+                ],
+                vec![b"xy".to_vec()],
+            ]
+        );
+    }
+
+    #[test]
+    fn streaming_consumer_panics() {
+        // Panics on empty messages.
+        struct Reader(mpsc::Sender<Vec<u8>>);
+
+        impl StreamingConsumer for Reader {
+            fn consume(&mut self, payload: &mut dyn Read) -> Result<(), Error> {
+                let mut data = Vec::new();
+                payload.read_to_end(&mut data)?;
+                assert!(!data.is_empty());
+                self.0.send(data).unwrap();
+                Ok(())
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .streaming_consumer(Reader(tx))
+            .panic_policy(PanicPolicy::Restart)
+            .build();
+        let errors = c.errors();
+
+        c.put(Vec::new());
+        c.put(b"after".to_vec());
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), b"after");
+        assert_eq!(c.stats().consumers[0].panics, 1);
+        assert!(matches!(
+            errors.try_recv(),
+            Ok(CarouselError::ConsumerPanicked { consumer: 0, .. })
+        ));
+    }
+
+    #[test]
+    fn ctx_consumer() {
+        struct Upper;
+
+        impl CtxConsumer for Upper {
+            fn consume_with(&mut self, ctx: &Ctx<'_>, data: Vec<u8>) -> Result<(), Error> {
+                assert_eq!(
+                    (ctx.consumer(), ctx.topic(), ctx.id()),
+                    (0, Some("in"), None)
+                );
+                ctx.put_to("out", data.to_ascii_uppercase());
+                Ok(())
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .subscriber("in", Upper)
+            .subscriber("out", tx)
+            .build();
+
+        c.put_to("in", b"shout".to_vec());
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), b"SHOUT");
+    }
+
+    #[test]
+    fn subscription_management() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder().consumer(tx).build();
+        let id = SubscriptionId(0);
+
+        c.set_filter(id, |data| data[0] % 2 == 0);
+        for i in 0..4u8 {
+            c.put(vec![i]);
+        }
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![0]);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![2]);
+
+        c.pause(id);
+        c.put(vec![4]);
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        assert_eq!(c.stats().consumers[0].depth, 1);
+        c.resume(id);
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), vec![4]);
+
+        c.remove(id);
+        c.put(vec![6]);
+        drop(c);
+        assert!(rx.iter().next().is_none());
+    }
+
+    #[test]
+    fn pattern_subscribers() {
+        let (tx1, rx1) = mpsc::channel();
+        let (tx2, rx2) = mpsc::channel();
+        let c = Carousel::builder()
+            .pattern_subscriber("logs.*.error".parse().unwrap(), tx1)
+            .retain()
+            .build();
+
+        c.put_to("logs.eu.error", b"1".to_vec());
+        c.put_to("logs.eu.info", b"2".to_vec());
+        c.put_to("logs.us.error", b"3".to_vec());
+        c.subscribe_pattern("logs.us.#".parse().unwrap(), tx2);
+        drop(c);
+
+        assert_eq!(
+            rx1.iter().collect::<Vec<_>>(),
+            vec![b"1".to_vec(), b"3".to_vec()]
+        );
+        assert_eq!(rx2.iter().collect::<Vec<_>>(), vec![b"3".to_vec()]);
+    }
+
+    #[test]
+    fn apply_routing() {
+        let (tx1, rx1) = mpsc::channel();
+        let (tx2, rx2) = mpsc::channel();
+        let c = Carousel::builder()
+            .subscriber("a", tx1)
+            .subscriber("b", tx2)
+            .build();
+
+        c.put_to("a", b"1".to_vec());
+        c.apply_routing(
+            RoutingTable::new()
+                .subscribe(SubscriptionId(0), "b")
+                .subscribe_all(SubscriptionId(1))
+                .filter(SubscriptionId(1), |data| data != b"3")
+                .rate_limit(SubscriptionId(1), Some(100)),
+        );
+        c.put_to("a", b"2".to_vec());
+        c.put_to("b", b"3".to_vec());
+        c.put_to("b", b"4".to_vec());
+        drop(c);
+
+        assert_eq!(
+            rx1.iter().collect::<Vec<_>>(),
+            vec![b"1".to_vec(), b"3".to_vec(), b"4".to_vec()]
+        );
+        assert_eq!(
+            rx2.iter().collect::<Vec<_>>(),
+            vec![b"2".to_vec(), b"4".to_vec()]
+        );
+    }
+
+    #[test]
+    fn from_config() {
+        let mut config = CarouselConfig {
+            shards: 2,
+            thread_name: Some(String::from("test")),
+            ..CarouselConfig::default()
+        };
+        config.rate_limits.insert(0, 1000);
+
+        let (tx, rx) = mpsc::channel();
+        let name = from_fn(move |_: Vec<u8>| {
+            tx.send(std::thread::current().name().map(String::from))
+                .unwrap()
+        });
+        let c = Carousel::from_config(&config, vec![name]);
+        c.put(b"message".to_vec());
+        drop(c);
+
+        assert_eq!(
+            rx.iter().collect::<Vec<_>>(),
+            vec![Some(String::from("test-poller-0"))]
+        );
+    }
+
+    #[test]
+    fn namespaces() {
+        let (tx, rx) = mpsc::channel();
+        let (a_tx, a_rx) = mpsc::channel();
+        let (b_tx, b_rx) = mpsc::channel();
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let gated = from_fn(move |data: Vec<u8>| {
+            b_tx.send(data).unwrap();
+            let _ = gate_rx.recv_timeout(Duration::from_secs(5));
+        });
+        let c = Carousel::builder()
+            .consumer(tx)
+            .namespace_quota("b", 10, Overflow::Drop)
+            .build();
+
+        let a = c.namespace("a");
+        let b = c.namespace("b");
+        a.subscribe_all(a_tx);
+        b.subscribe_all(gated);
+        a.put(b"a1".to_vec());
+        b.put(b"message0".to_vec());
+        assert_eq!(
+            b_rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            b"message0"
+        );
+        b.put(b"message1".to_vec());
+        c.put(b"plain".to_vec());
+
+        let stats = c.stats();
+        assert_eq!(stats.namespaces.len(), 2);
+        assert_eq!(
+            (stats.namespaces[0].name.as_str(), stats.namespaces[0].put),
+            ("a", 1)
+        );
+        assert_eq!(stats.namespaces[1].put, 2);
+        assert_eq!(stats.namespaces[1].dropped, 1);
+        assert_eq!(b.stats().consumers.len(), 1);
+
+        drop(gate_tx);
+        drop((a, b));
+        drop(c);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![b"plain".to_vec()]);
+        assert_eq!(a_rx.iter().collect::<Vec<_>>(), vec![b"a1".to_vec()]);
+        assert!(b_rx.iter().next().is_none());
+    }
+
+    #[test]
+    fn producer_quotas() {
+        let (tx, rx) = mpsc::channel();
+        let quota = ProducerQuota {
+            messages_per_second: Some(100),
+            bytes_per_second: None,
+        };
+        let c = Carousel::builder()
+            .consumer(tx)
+            .producer_quota("noisy", quota)
+            .build();
+
+        let noisy = c.clone_as("noisy").clone();
+        let quiet = c.clone_as("quiet");
+        let start = Instant::now();
+        for _ in 0..11 {
+            noisy.put(b"noise".to_vec());
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        quiet.put(b"q".to_vec());
+
+        let stats = c.stats();
+        assert_eq!(stats.producers.len(), 2);
+        assert_eq!((stats.producers[0].put, stats.producers[0].bytes), (11, 55));
+        assert!(stats.producers[0].throttled >= 10);
+        assert_eq!(
+            (stats.producers[1].put, stats.producers[1].throttled),
+            (1, 0)
+        );
+
+        drop((noisy, quiet));
+        drop(c);
+        assert_eq!(rx.iter().count(), 12);
+    }
+
+    #[test]
+    fn validator() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .validator(|data| match data.first() {
+                Some(b'{') => Ok(()),
+                _ => Err(ValidationError::new("not a JSON object")),
+            })
+            .build();
+
+        assert_eq!(c.try_put(b"{}".to_vec()), Ok(()));
+        assert_eq!(
+            c.try_put_to("topic", b"[]".to_vec()),
+            Err(PutError::Invalid(ValidationError::new("not a JSON object")))
+        );
+        c.put(b"garbage".to_vec());
+        assert_eq!(c.stats().rejected, 2);
+        drop(c);
+
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![b"{}".to_vec()]);
+    }
+
+    #[test]
+    fn checksums() {
+        use std::io::Write;
+
+        let (tx, rx) = mpsc::channel();
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let (error_tx, error_rx) = mpsc::channel::<ConsumeError>();
+        let gated = from_fn(move |data: Vec<u8>| {
+            tx.send(data).unwrap();
+            let _ = gate_rx.recv_timeout(Duration::from_secs(5));
+        });
+        let dir = std::env::temp_dir().join(format!("mp2c-crc-{}", std::process::id()));
+        let c = Carousel::builder()
+            .consumer(gated)
+            .memory_budget(10, Overflow::Spill(SpillFile::create(&dir).unwrap()))
+            .checksums()
+            .error_handler(move |e| error_tx.send(e).unwrap())
+            .build();
+
+        c.put(b"message0".to_vec());
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            b"message0"
+        );
+        c.put(b"message1".to_vec());
+        let mut spill = std::fs::OpenOptions::new()
+            .write(true)
+            .open(dir.join("spill"))
+            .unwrap();
+        spill.write_all(b"garbage!").unwrap();
+        drop(spill);
+
+        drop(gate_tx);
+        drop(c);
+        assert!(rx.iter().next().is_none());
+        let errors: Vec<ConsumeError> = error_rx.iter().collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].data, b"garbage!".to_vec());
+        assert!(errors[0].error.downcast_ref::<ChecksumError>().is_some());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sampled_consumer() {
+        let (tx, rx) = mpsc::channel();
+        let (sampled_tx, sampled_rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .sampled_consumer(0.1, sampled_tx)
+            .build();
+
+        for i in 0..2000u32 {
+            c.put(i.to_be_bytes().to_vec());
+        }
+        c.apply_routing(RoutingTable::new().sample(SubscriptionId(1), None));
+        c.put(b"all".to_vec());
+        drop(c);
+
+        assert_eq!(rx.iter().count(), 2001);
+        let sampled: Vec<Vec<u8>> = sampled_rx.iter().collect();
+        assert!((100..300).contains(&(sampled.len() - 1)));
+        assert_eq!(sampled.last().unwrap(), b"all");
+    }
+
+    #[test]
+    fn audit() {
+        let (tx, rx) = mpsc::channel();
+        let (audit_tx, audit_rx) = mpsc::channel::<AuditRecord>();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .validator(|data| match data {
+                b"invalid" => Err(ValidationError::new("invalid")),
+                _ => Ok(()),
+            })
+            .audit(audit_tx)
+            .build();
+
+        c.set_filter(SubscriptionId(0), |data| data != b"filtered");
+        c.put_to("t", b"valid".to_vec());
+        c.put(b"invalid".to_vec());
+        c.put(b"filtered".to_vec());
+        drop(c);
+
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![b"valid".to_vec()]);
+        let records: Vec<(AuditReason, Vec<u8>)> = audit_rx
+            .iter()
+            .map(|record| (record.reason, record.data))
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                (AuditReason::Accepted, b"valid".to_vec()),
+                (AuditReason::Rejected, b"invalid".to_vec()),
+                (AuditReason::Accepted, b"filtered".to_vec()),
+                (AuditReason::Filtered { consumer: 0 }, b"filtered".to_vec()),
+            ]
+        );
+    }
+
+    #[test]
+    fn latency() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(from_fn(move |data| {
+                std::thread::sleep(Duration::from_millis(2));
+                tx.send(data).unwrap();
+            }))
+            .build();
+
+        for i in 0..5 {
+            c.put(vec![i]);
+        }
+        for _ in 0..5 {
+            rx.recv().unwrap();
+        }
+
+        let stats = c.stats();
+        let latency = stats.consumers[0].latency;
+        assert!(latency.p50 <= latency.p95 && latency.p95 <= latency.p99);
+        // The last message waited for the four before it.
+        assert!(latency.p99 >= Duration::from_millis(8));
+    }
+
+    #[test]
+    fn mock_clock() {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let (tx, rx) = mpsc::channel();
+        let clock = MockClock::new();
+        let quota = ProducerQuota {
+            messages_per_second: Some(1),
+            bytes_per_second: None,
+        };
+        let c = Carousel::builder()
+            .consumer(from_fn(move |data| {
+                started_tx.send(()).unwrap();
+                gate_rx.recv().unwrap();
+                tx.send(data).unwrap();
+            }))
+            .ttl(Duration::from_secs(10))
+            .producer_quota("p", quota)
+            .clock(clock.clone())
+            .build();
+
+        // Throttling advances the mock clock instead of waiting.
+        let start = Instant::now();
+        let p = c.clone_as("p");
+        for i in 0..3 {
+            p.put(vec![i]);
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(c.stats().producers[0].throttled, 2);
+
+        started_rx.recv().unwrap();
+        clock.advance(Duration::from_secs(10));
+        for _ in 0..3 {
+            let _ = gate_tx.send(());
+        }
+        drop((p, c));
+
+        // The first message was being consumed, the others expired.
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![vec![0]]);
+    }
+
+    #[test]
+    fn faults() {
+        fn run(faults: Faults) -> (Vec<Vec<u8>>, usize) {
+            let (tx, rx) = mpsc::channel();
+            let (failed_tx, failed_rx) = mpsc::channel();
+            let c = Carousel::builder()
+                .consumer(tx)
+                .error_handler(move |e| {
+                    assert!(e.error.is::<InjectedPanic>());
+                    failed_tx.send(e.data).unwrap();
+                })
+                .faults(faults)
+                .build();
+            for i in 0..100 {
+                c.put(vec![i]);
+            }
+            drop(c);
+            (rx.iter().collect(), failed_rx.iter().count())
+        }
+
+        let duplicated = run(Faults {
+            duplicate_rate: 1.0,
+            ..Faults::default()
+        });
+        assert_eq!(duplicated.0.len(), 200);
+        assert_eq!(duplicated.0[..2], [vec![0], vec![0]]);
+
+        let chaos = Faults {
+            seed: 42,
+            delay_rate: 0.1,
+            max_delay: Duration::from_millis(1),
+            drop_rate: 0.2,
+            panic_rate: 0.2,
+            ..Faults::default()
+        };
+        let (consumed, failed) = run(chaos);
+        assert!(consumed.len() > 20 && consumed.len() < 100);
+        assert!(failed > 0 && consumed.len() + failed < 100);
+        // The same seed injects the same faults.
+        assert_eq!(run(chaos), (consumed, failed));
+    }
+
+    #[test]
+    #[should_panic(expected = "the fault rate drop_rate")]
+    fn faults_with_invalid_rate() {
+        Carousel::builder().faults(Faults {
+            drop_rate: 1.5,
+            ..Faults::default()
+        });
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .idle_timeout(Duration::from_millis(20))
+            .build();
+
+        let wait_dormant = |dormant: bool| {
+            let deadline = Instant::now() + Duration::from_secs(5);
+            while c.stats().consumers[0].dormant != dormant {
+                assert!(Instant::now() < deadline);
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        };
+
+        c.put(vec![1]);
+        assert_eq!(rx.recv().unwrap(), vec![1]);
+        wait_dormant(true);
+
+        // The next message starts the poller again with the same consumer.
+        c.put(vec![2]);
+        assert_eq!(rx.recv().unwrap(), vec![2]);
+        wait_dormant(true);
+        drop(c);
+
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    fn receipts() {
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .consumer(from_fn(move |_| gate_rx.recv().unwrap()))
+            .build();
+
+        let receipt = c.put_tracked(vec![1]);
+        assert_eq!(rx.recv().unwrap(), vec![1]);
+        // The second consumer is still busy with the message.
+        assert!(!receipt.wait_timeout(Duration::from_millis(20)));
+        gate_tx.send(()).unwrap();
+        receipt.wait();
+        assert!(receipt.is_done());
+    }
+
+    #[test]
+    fn receipt_clones_awaited_together() {
+        use std::future::Future;
+        use std::task::{self, Poll, Wake, Waker};
+
+        struct Woken(mpsc::Sender<usize>, usize);
+
+        impl Wake for Woken {
+            fn wake(self: sync::Arc<Self>) {
+                self.0.send(self.1).unwrap();
+            }
+        }
+
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let c = Carousel::builder()
+            .consumer(from_fn(move |_| gate_rx.recv().unwrap()))
+            .build();
+
+        let mut receipts = vec![c.put_tracked(vec![1])];
+        receipts.push(receipts[0].clone());
+        let (woken_tx, woken_rx) = mpsc::channel();
+        for (task, receipt) in receipts.iter_mut().enumerate() {
+            let waker = Waker::from(sync::Arc::new(Woken(woken_tx.clone(), task)));
+            let mut cx = task::Context::from_waker(&waker);
+            assert_eq!(std::pin::Pin::new(receipt).poll(&mut cx), Poll::Pending);
+        }
+        gate_tx.send(()).unwrap();
+
+        // Both tasks are woken, not only the one that polled last.
+        let mut woken: Vec<usize> = (0..2)
+            .map(|_| woken_rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        woken.sort_unstable();
+        assert_eq!(woken, vec![0, 1]);
+    }
+
+    #[test]
+    fn consume_timeout() {
+        let (failed_tx, failed_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let calls = Cell::new(0);
+        let c = Carousel::builder()
+            .consumer(from_fn(move |data: Vec<u8>| {
+                calls.set(calls.get() + 1);
+                if calls.get() == 1 {
+                    std::thread::sleep(Duration::from_millis(100));
+                }
+                tx.send(data).unwrap();
+            }))
+            .consume_timeout(Duration::from_millis(20), TimeoutAction::Retry)
+            .retries(1)
+            .error_handler(move |e| failed_tx.send(e).unwrap())
+            .build();
+
+        c.put(vec![1]);
+        let e = failed_rx.recv().unwrap();
+        assert_eq!(e.attempts, 1);
+        assert!(e.error.downcast_ref::<ConsumeTimeout>().is_some());
+        // The timed out call counts as failed and the message is retried.
+        assert_eq!(rx.recv().unwrap(), vec![1]);
+        assert_eq!(rx.recv().unwrap(), vec![1]);
+        assert_eq!(c.stats().consumers[0].timed_out, 1);
+        assert!(failed_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn consume_timeout_streaming() {
+        struct Slow(mpsc::Sender<Vec<u8>>);
+
+        impl StreamingConsumer for Slow {
+            fn consume(&mut self, payload: &mut dyn Read) -> Result<(), Error> {
+                std::thread::sleep(Duration::from_millis(100));
+                let mut data = Vec::new();
+                payload.read_to_end(&mut data)?;
+                self.0.send(data).unwrap();
+                Err("too late".into())
+            }
+        }
+
+        let (failed_tx, failed_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .streaming_consumer(Slow(tx))
+            .consume_timeout(Duration::from_millis(20), TimeoutAction::Retry)
+            .error_handler(move |e| failed_tx.send(e).unwrap())
+            .build();
+
+        c.put(vec![1]);
+        let e = failed_rx.recv().unwrap();
+        assert!(e.error.downcast_ref::<ConsumeTimeout>().is_some());
+        // The message isn't retried, and its failure not reported again.
+        assert_eq!(rx.recv().unwrap(), vec![1]);
+        drop(c);
+        assert!(rx.try_recv().is_err());
+        assert!(failed_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn supervisor() {
+        let (failed_tx, failed_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let (started_tx, started_rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .supervised(
+                Supervisor::one_for_one()
+                    .backoff(Duration::from_millis(1), Duration::from_millis(5))
+                    .max_restarts(1, Duration::from_secs(60)),
+                move || {
+                    started_tx.send(()).unwrap();
+                    let tx = tx.clone();
+                    from_fn(move |data: Vec<u8>| {
+                        if data[0] == 0 {
+                            panic!("poison");
+                        }
+                        tx.send(data).unwrap();
+                    })
+                },
+            )
+            .error_handler(move |e| failed_tx.send(e).unwrap())
+            .build();
+
+        c.put(vec![0]);
+        c.put(vec![1]);
+        // The consumer was restarted after the first panic.
+        assert_eq!(rx.recv().unwrap(), vec![1]);
+        let e = failed_rx.recv().unwrap();
+        let panic = e.error.downcast_ref::<ConsumerPanic>().unwrap();
+        assert_eq!(panic.message.as_deref(), Some("poison"));
+
+        // The second panic within a minute is one restart too many.
+        c.put(vec![0]);
+        let e = failed_rx.recv().unwrap();
+        assert!(e.error.downcast_ref::<ConsumerPanic>().is_some());
+        drop(c);
+        assert_eq!(started_rx.try_iter().count(), 2);
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn supervisor_redelivery() {
+        let (failed_tx, failed_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let crashes = sync::Arc::new(sync::atomic::AtomicUsize::new(0));
+        let crashed = sync::Arc::clone(&crashes);
+        let c = Carousel::builder()
+            .supervised(Supervisor::one_for_one().redeliver(2), move || {
+                let tx = tx.clone();
+                let crashed = sync::Arc::clone(&crashed);
+                from_fn(move |data: Vec<u8>| {
+                    // Crashes on the first delivery of 1, and on every one of 0.
+                    if data[0] == 0 || crashed.load(sync::atomic::Ordering::SeqCst) == 0 {
+                        crashed.fetch_add(1, sync::atomic::Ordering::SeqCst);
+                        panic!("crash");
+                    }
+                    tx.send(data).unwrap();
+                })
+            })
+            .error_handler(move |e| failed_tx.send(e).unwrap())
+            .build();
+
+        c.put(vec![1]);
+        assert_eq!(rx.recv().unwrap(), vec![1]);
+        assert!(failed_rx.try_recv().is_err());
+
+        // A poison message is delivered three times before it fails.
+        c.put(vec![0]);
+        let e = failed_rx.recv().unwrap();
+        assert!(e.error.downcast_ref::<ConsumerPanic>().is_some());
+        assert_eq!(e.data, vec![0]);
+        assert_eq!(crashes.load(sync::atomic::Ordering::SeqCst), 4);
+        drop(c);
+    }
+
+    #[test]
+    fn panic_policy() {
+        let (panicked_tx, panicked_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let consumer = move |data: Vec<u8>| {
+            if data[0] == 0 {
+                panic!("poison");
+            }
+            tx.send(data).unwrap();
+        };
+        let c = Carousel::builder()
+            .consumer(from_fn(consumer))
+            .panic_policy(PanicPolicy::Callback(sync::Arc::new(move |id, panic| {
+                panicked_tx.send((id, panic.clone())).unwrap()
+            })))
+            .build();
+
+        c.put(vec![0]);
+        c.put(vec![1]);
+        // The consumer carries on after the panic.
+        assert_eq!(rx.recv().unwrap(), vec![1]);
+        let (id, panic) = panicked_rx.recv().unwrap();
+        assert_eq!(id, SubscriptionId(0));
+        assert_eq!(panic.message.as_deref(), Some("poison"));
+        assert_eq!(c.stats().consumers[0].panics, 1);
+        drop(c);
+
+        let c = Carousel::builder()
+            .consumer(from_fn(|_| panic!("poison")))
+            .consumer(from_fn(|_| {}))
+            .panic_policy(PanicPolicy::ShutdownCarousel)
+            .build();
+        c.put(vec![0]);
+        while !c.stats().consumers.is_empty() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // The carousel refuses new messages instead of dropping them.
+        assert_eq!(c.try_put(vec![1]), Err(PutError::Closed));
+        assert!(c.quiesce().wait_timeout(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn async_consumer() {
+        struct Relay {
+            downstream: Carousel,
+            tx: mpsc::Sender<Vec<u8>>,
+        }
+
+        impl AsyncConsumer for Relay {
+            fn consume(&mut self, data: Vec<u8>) -> ConsumeFuture<'_> {
+                Box::pin(async move {
+                    self.downstream.put_tracked(data.clone()).await;
+                    self.tx.send(data)?;
+                    Ok(())
+                })
+            }
+        }
+
+        let (downstream_tx, downstream_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let relay = Relay {
+            downstream: Carousel::builder()
+                .consumer(from_fn(move |data| {
+                    std::thread::sleep(Duration::from_millis(10));
+                    downstream_tx.send(data).unwrap();
+                }))
+                .build(),
+            tx,
+        };
+        let c = Carousel::builder().consumer(block_on(relay)).build();
+
+        c.put(vec![1]);
+        c.put(vec![2]);
+        // Each message reaches the downstream consumer before it is relayed.
+        assert_eq!(rx.recv().unwrap(), vec![1]);
+        assert_eq!(downstream_rx.try_recv().unwrap(), vec![1]);
+        assert_eq!(rx.recv().unwrap(), vec![2]);
+        assert_eq!(downstream_rx.try_recv().unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn drop_policy() {
+        let (started_tx, started_rx) = mpsc::channel();
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(from_fn(move |data| {
+                started_tx.send(()).unwrap();
+                gate_rx.recv().unwrap();
+                tx.send(data).unwrap();
+            }))
+            .drop_policy(DropPolicy::Discard)
+            .build();
+        let handle = c.clone();
+
+        c.put(vec![1]);
+        started_rx.recv().unwrap();
+        for i in 2..=5 {
+            c.put(vec![i]);
+        }
+        while handle.stats().consumers[0].depth < 5 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let dropped = std::thread::spawn(move || drop(c));
+        while handle.stats().consumers[0].discarded == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        gate_tx.send(()).unwrap();
+        dropped.join().unwrap();
+
+        // Only the message in the hands of the consumer was consumed.
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![vec![1]]);
+        assert_eq!(handle.stats().consumers[0].discarded, 4);
+    }
+
+    #[test]
+    fn detach() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .sweep_every(Duration::from_millis(1))
+            .build()
+            .detach();
+
+        drop(c.clone());
+        c.put(vec![1]);
+        drop(c);
+        // The carousel keeps running without any handle.
+        assert_eq!(rx.recv().unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn into_parts() {
+        let (tx, rx) = mpsc::channel();
+        let (c, mut threads) = Carousel::builder()
+            .consumer(tx)
+            .idle_timeout(Duration::from_millis(5))
+            .build()
+            .into_parts();
+
+        c.put(vec![1]);
+        drop(c);
+        assert_eq!(rx.recv().unwrap(), vec![1]);
+        // Let the poller go idle, so stopping has to start it again.
+        std::thread::sleep(Duration::from_millis(20));
+
+        let multipliers = threads.stop_multipliers();
+        assert_eq!(multipliers.len(), 1);
+        assert!(threads.stop_multipliers().is_empty());
+        for thread in multipliers {
+            thread.join().unwrap();
+        }
+        let pollers = threads.stop_pollers();
+        assert_eq!(pollers.len(), 1);
+        for thread in pollers {
+            thread.join().unwrap();
+        }
+        drop(threads);
+    }
+
+    #[test]
+    fn shutdown_report() {
+        let clock = MockClock::new();
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(from_fn(|data: Vec<u8>| assert_ne!(data[0], 0)))
+            .consumer(tx)
+            .panic_policy(PanicPolicy::Restart)
+            .clock(clock.clone())
+            .build();
+        c.put(vec![0]);
+        c.put(vec![1]);
+        clock.advance(Duration::from_secs(1));
+
+        let report = c.shutdown();
+        assert_eq!(rx.try_iter().count(), 2);
+        assert_eq!(report.uptime, Duration::from_secs(1));
+        assert_eq!(report.panicked(), vec![0]);
+        assert_eq!(report.consumers[1].consumed, 2);
+        assert_eq!(report.discarded, 0);
+
+        let (report_tx, report_rx) = mpsc::channel();
+        let report_tx = sync::Mutex::new(report_tx);
+        let c = Carousel::builder()
+            .consumer(from_fn(|_| {}))
+            .on_shutdown(move |report| report_tx.lock().unwrap().send(report).unwrap())
+            .build();
+        c.put(vec![1]);
+        c.clone().shutdown();
+        assert!(report_rx.try_recv().is_err());
+        drop(c);
+        let report = report_rx.try_recv().unwrap();
+        assert_eq!(report.consumers[0].consumed, 1);
+        assert!(report.panicked().is_empty());
+    }
+
+    #[test]
+    fn debug_report() {
+        let (tx, _rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .thread_name("test")
+            .retries(2)
+            .build();
+        let id = c.subscribe_pattern("logs.*.error".parse().unwrap(), from_fn(|_| {}));
+        c.pause(id);
+        // Subscribing and pausing apply on the multiplier thread.
+        while !c.debug_report().contains("paused") {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        assert_eq!(
+            format!("{:?}", c.clone()),
+            "Carousel { owner: false, consumers: [0, 1], shards: 1, drop_policy: Drain, .. }"
+        );
+        let report = c.debug_report();
+        assert!(report.starts_with("Carousel owner, up "));
+        assert!(report.contains("\n  thread name: test\n  memory budget: -\n  retries: 2\n"));
+        assert!(report.contains("\nmultipliers: running\n"));
+        assert!(report.contains("\n  0 (test-poller-0): running, depth 0, consumed 0,"));
+        assert!(report.contains("\n  1 (test-poller-1): paused, depth 0,"));
+        assert!(report.contains(", pattern logs.*.error\n"));
+    }
+
+    #[test]
+    fn shutdown_races() {
+        let (c, mut threads) = Carousel::builder()
+            .consumer(from_fn(|_| panic!("poison")))
+            .build()
+            .into_parts();
+        c.put(vec![0]);
+        for thread in threads.stop_multipliers() {
+            thread.join().unwrap();
+        }
+        // Changes and messages after the multiplier exited are ignored.
+        c.pause(SubscriptionId(0));
+        c.put(vec![1]);
+        assert_eq!(c.stats().discarded, 1);
+        drop(c);
+        // Joining the poller that died with its consumer doesn't panic.
+        drop(threads);
+    }
+
+    #[test]
+    fn primary() {
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let (primary_tx, primary_rx) = mpsc::channel();
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .consumer(from_fn(move |data| {
+                gate_rx.recv().unwrap();
+                primary_tx.send(data).unwrap();
+            }))
+            .primary(SubscriptionId(1))
+            .dispatch_order(vec![SubscriptionId(1), SubscriptionId(0)])
+            .build();
+
+        c.put(vec![1]);
+        c.put(vec![2]);
+        // The others wait for the primary.
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        gate_tx.send(()).unwrap();
+        assert_eq!(primary_rx.recv().unwrap(), vec![1]);
+        assert_eq!(rx.recv().unwrap(), vec![1]);
+        assert!(rx.recv_timeout(Duration::from_millis(50)).is_err());
+        gate_tx.send(()).unwrap();
+        drop(c);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![vec![2]]);
+    }
+
+    #[test]
+    fn primary_lets_rejected_messages_overtake() {
+        let (gate_tx, gate_rx) = mpsc::channel::<()>();
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .subscriber(
+                "audit",
+                from_fn(move |_| {
+                    gate_rx.recv().unwrap();
+                }),
+            )
+            .primary(SubscriptionId(1))
+            .build();
+
+        // The primary holds the first message while the second, which it
+        // doesn't receive, goes to the others right away.
+        c.put_to("audit", vec![1]);
+        c.put(vec![2]);
+        assert_eq!(rx.recv().unwrap(), vec![2]);
+        gate_tx.send(()).unwrap();
+        drop(c);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![vec![1]]);
+    }
+
+    #[test]
+    fn durable_writer() {
+        struct Journal;
+
+        impl TryConsumer for Journal {
+            fn try_consume(&mut self, data: Vec<u8>) -> Result<(), Error> {
+                match data[0] {
+                    0 => Err("disk full".into()),
+                    _ => Ok(()),
+                }
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let (failed_tx, failed_rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .durable_writer(Journal)
+            .retries(1)
+            .error_handler(move |e| failed_tx.send(e.data).unwrap())
+            .build();
+
+        c.put(vec![0]);
+        c.put(vec![1]);
+        drop(c);
+        // Only the persisted message is handed on.
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![vec![1]]);
+        assert_eq!(failed_rx.iter().collect::<Vec<_>>(), vec![vec![0]]);
+    }
+
+    #[test]
+    fn transform() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .checksums()
+            .transform(|mut data: Vec<u8>| {
+                data.retain(|b| *b != 0);
+                Some(data).filter(|data| !data.is_empty())
+            })
+            .build();
+
+        c.put(vec![0, 1, 0, 2]);
+        c.put(vec![0]);
+        c.put(vec![3]);
+        while c.stats().filtered == 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drop(c);
+        // The checksums still match the transformed messages.
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![vec![1, 2], vec![3]]);
+    }
+
+    #[test]
+    fn transformed_consumer() {
+        let (tx, rx) = mpsc::channel();
+        let (all_tx, all_rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .transformed_consumer(tx, |data: Vec<u8>| match data[0] % 2 {
+                0 => Some(vec![data[0] * 10]),
+                _ => None,
+            })
+            .consumer(all_tx)
+            .build();
+
+        for i in 0..4 {
+            c.put(vec![i]);
+        }
+        drop(c);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![vec![0], vec![20]]);
+        assert_eq!(all_rx.iter().count(), 4);
+    }
+
+    #[test]
+    fn quiesce() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(from_fn(move |data| {
+                std::thread::sleep(Duration::from_millis(1));
+                tx.send(data).unwrap();
+            }))
+            .build();
+        for i in 0..50u8 {
+            c.put(vec![i]);
+        }
+
+        let receipt = c.quiesce();
+        assert_eq!(c.try_put(vec![50]), Err(PutError::Quiesced));
+        c.put(vec![51]);
+        assert!(receipt.wait_timeout(Duration::from_secs(5)));
+        assert_eq!(rx.try_iter().count(), 50);
+        assert!(c.quiesce().wait_timeout(Duration::from_secs(5)));
+
+        drop(c);
+        assert_eq!(rx.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn export_and_import() {
+        let (tx0, rx0) = mpsc::channel();
+        let (tx1, rx1) = mpsc::channel();
+        let old = Carousel::builder().consumer(tx0).build();
+        old.subscribe("a", tx1);
+        old.pause(SubscriptionId(0));
+        old.pause(SubscriptionId(1));
+        old.put(b"all".to_vec());
+        old.put_to("a", b"a".to_vec());
+        while old.stats().consumers.iter().map(|c| c.depth).sum::<usize>() < 3 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        let mut export = Vec::new();
+        assert_eq!(old.export_pending(&mut export).unwrap(), 3);
+        assert!(old.stats().consumers.iter().all(|c| c.depth == 0));
+        drop(old);
+        assert!(rx0.try_recv().is_err() && rx1.try_recv().is_err());
+
+        // The messages go back to the consumers with the same ids.
+        let (tx0, rx0) = mpsc::channel();
+        let (tx1, rx1) = mpsc::channel();
+        let new = Carousel::builder().consumer(tx0).consumer(tx1).build();
+        assert_eq!(new.import(&export[..]).unwrap(), 3);
+        assert!(new.import(&b"MP2C\x02"[..]).is_err());
+        drop(new);
+        assert_eq!(
+            rx0.iter().collect::<Vec<_>>(),
+            vec![b"all".to_vec(), b"a".to_vec()]
+        );
+        assert_eq!(rx1.iter().collect::<Vec<_>>(), vec![b"a".to_vec()]);
+    }
 }
@@ -0,0 +1,82 @@
+use std::sync::mpsc;
+use std::thread;
+
+/// `AuditReason` tells an `AuditConsumer` why it is handed a message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditReason {
+    /// The message was put and is forwarded to the consumers.
+    Accepted,
+    /// The validator rejected the message, see `Builder::validator`.
+    Rejected,
+    /// The message was dropped because it didn't fit in the memory budget or
+    /// the quota of its namespace.
+    Overflow,
+    /// The filter of the consumer `consumer` rejected the message.
+    Filtered { consumer: usize },
+    /// The message expired before the consumer `consumer` got to it.
+    Expired { consumer: usize },
+}
+
+/// `AuditRecord` is a copy of a message handed to the audit consumer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub reason: AuditReason,
+    /// The topic the message was put on, if any.
+    pub topic: Option<String>,
+    /// The namespace the message was put into, if any.
+    pub namespace: Option<String>,
+    /// The payload of the message. Messages dropped for overflow are handed
+    /// over chunk by chunk if they were split with `Builder::chunk_size`.
+    pub data: Vec<u8>,
+}
+
+/// `AuditConsumer` receives a record of every message put on a `Carousel`,
+/// including the messages that never reach a consumer, see `Builder::audit`.
+pub trait AuditConsumer {
+    fn audit(&mut self, record: AuditRecord);
+}
+
+/// Forwards every record to the receiving end of the channel. Records are
+/// discarded once the receiver has been dropped.
+impl AuditConsumer for mpsc::Sender<AuditRecord> {
+    fn audit(&mut self, record: AuditRecord) {
+        let _ = self.send(record);
+    }
+}
+
+/// `Auditor` is the thread handing audit records to the audit consumer.
+pub(crate) struct Auditor {
+    tx: mpsc::Sender<Option<AuditRecord>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Auditor {
+    pub(crate) fn new(
+        tx: mpsc::Sender<Option<AuditRecord>>,
+        thread: thread::JoinHandle<()>,
+    ) -> Auditor {
+        Auditor {
+            tx,
+            thread: Some(thread),
+        }
+    }
+
+    /// Hands every record received on `rx` to `consumer` until `None` arrives.
+    pub(crate) fn run(
+        mut consumer: Box<dyn AuditConsumer + Send>,
+        rx: mpsc::Receiver<Option<AuditRecord>>,
+    ) {
+        while let Ok(Some(record)) = rx.recv() {
+            consumer.audit(record);
+        }
+    }
+}
+
+impl Drop for Auditor {
+    fn drop(&mut self) {
+        let _ = self.tx.send(None);
+        if let Some(thread) = self.thread.take() {
+            crate::asynch::join(thread, "audit");
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use crate::asynch::Consumer;
+use std::sync::{self, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// `BatchConsumer` consumes messages in batches, e.g. to write them to a
+/// database or an object store in one go, see `Builder::batch_consumer`.
+pub trait BatchConsumer {
+    fn consume_batch(&mut self, batch: Vec<Vec<u8>>);
+}
+
+/// Forwards every batch to the receiving end of the channel. Batches are
+/// discarded once the receiver has been dropped.
+impl BatchConsumer for mpsc::Sender<Vec<Vec<u8>>> {
+    fn consume_batch(&mut self, batch: Vec<Vec<u8>>) {
+        let _ = self.send(batch);
+    }
+}
+
+/// `FlushTriggers` selects when the batch of a `BatchConsumer` is flushed:
+/// once it holds `messages` messages, once its first message waited for
+/// `delay`, or once it holds `bytes` bytes of payload, whichever comes first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushTriggers {
+    pub messages: usize,
+    pub delay: Duration,
+    pub bytes: usize,
+}
+
+impl Default for FlushTriggers {
+    fn default() -> Self {
+        FlushTriggers {
+            messages: 100,
+            delay: Duration::from_millis(100),
+            bytes: 1 << 20,
+        }
+    }
+}
+
+struct Batch<T> {
+    consumer: T,
+    messages: Vec<Vec<u8>>,
+    bytes: usize,
+    /// When the first message of the batch arrived.
+    since: Option<Instant>,
+    stopped: bool,
+}
+
+impl<T: BatchConsumer> Batch<T> {
+    fn flush(&mut self) {
+        self.bytes = 0;
+        self.since = None;
+        if !self.messages.is_empty() {
+            let messages = std::mem::take(&mut self.messages);
+            self.consumer.consume_batch(messages);
+        }
+    }
+}
+
+struct State<T> {
+    batch: sync::Mutex<Batch<T>>,
+    changed: sync::Condvar,
+}
+
+/// `Batching` is a consumer registered with `Builder::batch_consumer`. A
+/// thread of its own flushes batches whose delay passed while no message
+/// arrived.
+pub(crate) struct Batching<T> {
+    state: sync::Arc<State<T>>,
+    triggers: FlushTriggers,
+    flusher: Option<thread::JoinHandle<()>>,
+}
+
+impl<T> Batching<T>
+where
+    T: BatchConsumer + Send + 'static,
+{
+    pub(crate) fn new(consumer: T, triggers: FlushTriggers) -> Batching<T> {
+        let state = sync::Arc::new(State {
+            batch: sync::Mutex::new(Batch {
+                consumer,
+                messages: Vec::new(),
+                bytes: 0,
+                since: None,
+                stopped: false,
+            }),
+            changed: sync::Condvar::new(),
+        });
+        let flushed = sync::Arc::clone(&state);
+        let flusher = thread::Builder::new()
+            .name(String::from("batch-flusher"))
+            .spawn(move || flush_when_due(&flushed, triggers.delay))
+            .unwrap();
+
+        Batching {
+            state,
+            triggers,
+            flusher: Some(flusher),
+        }
+    }
+}
+
+/// Flushes the batch of `state` whenever its first message waited for
+/// `delay`, and once more when the consumer is dropped.
+fn flush_when_due<T: BatchConsumer>(state: &State<T>, delay: Duration) {
+    let mut batch = state.batch.lock().unwrap();
+    while !batch.stopped {
+        batch = match batch.since {
+            None => state.changed.wait(batch).unwrap(),
+            Some(since) => {
+                let waited = since.elapsed();
+                if waited >= delay {
+                    batch.flush();
+                    continue;
+                }
+                state.changed.wait_timeout(batch, delay - waited).unwrap().0
+            }
+        };
+    }
+    batch.flush();
+}
+
+impl<T: BatchConsumer> Consumer for Batching<T> {
+    fn consume(&mut self, data: Vec<u8>) {
+        let mut batch = self.state.batch.lock().unwrap();
+        if batch.since.is_none() {
+            batch.since = Some(Instant::now());
+            self.state.changed.notify_one();
+        }
+        batch.bytes += data.len();
+        batch.messages.push(data);
+        if batch.messages.len() >= self.triggers.messages || batch.bytes >= self.triggers.bytes {
+            batch.flush();
+        }
+    }
+}
+
+/// Has the flusher flush the last batch when the poller stops.
+impl<T> Drop for Batching<T> {
+    fn drop(&mut self) {
+        // A consumer that panicked poisons the batch, and the flusher too.
+        let mut batch = self
+            .state
+            .batch
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        batch.stopped = true;
+        self.state.changed.notify_one();
+        drop(batch);
+        if let Some(flusher) = self.flusher.take() {
+            let _ = flusher.join();
+        }
+    }
+}
@@ -0,0 +1,159 @@
+use crate::asynch::{Carousel, ConsumerStats, Latency, Rate};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// `BenchConfig` is the synthetic load of `Carousel::bench`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchConfig {
+    /// The number of messages put, spread over the producers.
+    pub messages: usize,
+    /// The size of each message in bytes.
+    pub size: usize,
+    /// The number of producer threads putting messages concurrently.
+    pub producers: usize,
+    /// How long to wait for the consumers to catch up before giving up.
+    pub timeout: Duration,
+}
+
+impl Default for BenchConfig {
+    fn default() -> Self {
+        BenchConfig {
+            messages: 100_000,
+            size: 64,
+            producers: 1,
+            timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// `BenchReport` is the outcome of `Carousel::bench`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BenchReport {
+    /// The number of messages put.
+    pub messages: usize,
+    /// The time from the first put until every consumer of all messages
+    /// consumed them, or the timeout.
+    pub elapsed: Duration,
+    /// The messages and bytes per second put and consumed over `elapsed`.
+    pub throughput: Rate,
+    /// The highest percentiles among the consumers of all messages, see
+    /// `ConsumerStats::latency`.
+    pub latency: Latency,
+    /// The statistics of each consumer at the end of the run.
+    pub consumers: Vec<ConsumerStats>,
+    /// Whether the consumers didn't catch up within the timeout.
+    pub timed_out: bool,
+}
+
+/// Puts the load of `config` on `carousel` and waits for the consumers that
+/// receive every message, those without a topic, namespace or sample, to
+/// consume it.
+pub(crate) fn run(carousel: &Carousel, config: BenchConfig) -> BenchReport {
+    assert!(config.producers > 0);
+
+    let full: Vec<usize> = carousel
+        .shared
+        .routes
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|route| route.namespace.is_none() && route.topic.is_none())
+        .filter(|route| route.sample.is_none())
+        .map(|route| route.slot.id)
+        .collect();
+    let consumed = |carousel: &Carousel| -> Vec<(usize, u64)> {
+        carousel
+            .stats()
+            .consumers
+            .iter()
+            .filter(|stats| full.contains(&stats.id))
+            .map(|stats| (stats.id, stats.consumed))
+            .collect()
+    };
+    let before = consumed(carousel);
+
+    let started = Instant::now();
+    let producers: Vec<thread::JoinHandle<()>> = (0..config.producers)
+        .map(|i| {
+            let producer = carousel.clone();
+            let messages = config.messages / config.producers
+                + usize::from(i < config.messages % config.producers);
+            let size = config.size;
+            thread::spawn(move || {
+                for _ in 0..messages {
+                    producer.put(vec![0; size]);
+                }
+            })
+        })
+        .collect();
+    for producer in producers {
+        producer.join().unwrap();
+    }
+
+    let caught_up = |carousel: &Carousel| {
+        consumed(carousel).iter().all(|(id, consumed)| {
+            let before = before.iter().find(|(b, _)| b == id).map_or(0, |(_, c)| *c);
+            consumed - before >= config.messages as u64
+        })
+    };
+    let mut timed_out = false;
+    while !caught_up(carousel) {
+        if started.elapsed() >= config.timeout {
+            timed_out = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    let elapsed = started.elapsed();
+
+    let consumers = carousel.stats().consumers;
+    let latency = consumers
+        .iter()
+        .filter(|stats| full.contains(&stats.id))
+        .fold(Latency::default(), |worst, stats| Latency {
+            p50: worst.p50.max(stats.latency.p50),
+            p95: worst.p95.max(stats.latency.p95),
+            p99: worst.p99.max(stats.latency.p99),
+        });
+    let per_second = |n: usize| (n as f64 / elapsed.as_secs_f64().max(f64::EPSILON)) as u64;
+
+    BenchReport {
+        messages: config.messages,
+        elapsed,
+        throughput: Rate {
+            messages_per_second: per_second(config.messages),
+            bytes_per_second: per_second(config.messages * config.size),
+        },
+        latency,
+        consumers,
+        timed_out,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asynch::{from_fn, BenchConfig, Carousel};
+    use std::time::Duration;
+
+    #[test]
+    fn bench() {
+        let c = Carousel::builder()
+            .consumer(from_fn(|_| {}))
+            .consumer(from_fn(|_| {}))
+            .build();
+        let report = c.bench(BenchConfig {
+            messages: 1000,
+            size: 16,
+            producers: 3,
+            timeout: Duration::from_secs(10),
+        });
+        assert!(!report.timed_out);
+        assert_eq!(report.messages, 1000);
+        assert!(report.consumers.iter().all(|stats| stats.consumed == 1000));
+        assert!(report.throughput.messages_per_second > 0);
+        assert_eq!(
+            report.throughput.bytes_per_second / 16,
+            report.throughput.messages_per_second
+        );
+    }
+}
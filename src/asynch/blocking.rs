@@ -0,0 +1,103 @@
+use crate::asynch::{Error, TryConsumer};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync;
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+
+/// The future returned by an `AsyncConsumer`.
+pub type ConsumeFuture<'a> = Pin<Box<dyn Future<Output = Result<(), Error>> + 'a>>;
+
+/// `AsyncConsumer` is a consumer that handles messages asynchronously. It is
+/// registered by wrapping it with `block_on`, which waits for each future on
+/// the poller thread, so a few async consumers can join an otherwise threaded
+/// `Carousel`.
+pub trait AsyncConsumer {
+    fn consume(&mut self, data: Vec<u8>) -> ConsumeFuture<'_>;
+}
+
+/// `Executor` runs the futures of an `AsyncConsumer` to completion on the
+/// poller thread. Implement it for the handle of an async runtime, e.g. with
+/// `tokio::runtime::Handle::block_on`, for futures that need the runtime.
+pub trait Executor {
+    fn block_on(&self, future: ConsumeFuture<'_>) -> Result<(), Error>;
+}
+
+/// `ThreadExecutor` is an `Executor` that parks the poller thread until the
+/// future wakes it. It suits futures that don't depend on a runtime, such as
+/// a `Receipt`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ThreadExecutor;
+
+struct Unpark(thread::Thread);
+
+impl Wake for Unpark {
+    fn wake(self: sync::Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+impl Executor for ThreadExecutor {
+    fn block_on(&self, mut future: ConsumeFuture<'_>) -> Result<(), Error> {
+        let waker = Waker::from(sync::Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(result) => return result,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+}
+
+/// `BlockOn` is a consumer that waits for each message to be consumed by an
+/// `AsyncConsumer`. It is created with `block_on` or `block_on_with`.
+pub struct BlockOn<T, E> {
+    consumer: T,
+    executor: E,
+}
+
+/// Creates a consumer that hands each message to `consumer` and parks the
+/// poller thread until it is consumed.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{block_on, AsyncConsumer, Carousel, ConsumeFuture};
+///
+/// struct Upload;
+///
+/// impl AsyncConsumer for Upload {
+///   fn consume(&mut self, data: Vec<u8>) -> ConsumeFuture<'_> {
+///     Box::pin(async move {
+///       // await the upload of data
+///       Ok(())
+///     })
+///   }
+/// }
+///
+/// let c = Carousel::builder().consumer(block_on(Upload)).build();
+/// c.put(String::from("test").into_bytes());
+/// ```
+pub fn block_on<T: AsyncConsumer>(consumer: T) -> BlockOn<T, ThreadExecutor> {
+    block_on_with(consumer, ThreadExecutor)
+}
+
+/// Creates a consumer that hands each message to `consumer` and runs the
+/// future with `executor`.
+pub fn block_on_with<T, E>(consumer: T, executor: E) -> BlockOn<T, E>
+where
+    T: AsyncConsumer,
+    E: Executor,
+{
+    BlockOn { consumer, executor }
+}
+
+impl<T, E> TryConsumer for BlockOn<T, E>
+where
+    T: AsyncConsumer,
+    E: Executor,
+{
+    fn try_consume(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        self.executor.block_on(self.consumer.consume(data))
+    }
+}
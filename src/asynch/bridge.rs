@@ -0,0 +1,165 @@
+use crate::asynch::{Carousel, Ctx, CtxConsumer, Error, SubscriptionId};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The id of the next `Bridge`, which tells its messages apart.
+static NEXT_BRIDGE: AtomicU64 = AtomicU64::new(1);
+
+/// `Hop` is carried by a message a `Bridge` forwarded: how many bridges it
+/// crossed, and the last one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Hop {
+    pub(crate) count: u32,
+    pub(crate) bridge: u64,
+}
+
+/// `Bridge` forwards the messages put on selected topics between two
+/// carousels of the same process, in both directions. Dropping the bridge
+/// stops it.
+///
+/// A message carries the number of bridges it crossed, see `Ctx::hops`, to
+/// prevent forwarding loops: a bridge doesn't hand a message back to the
+/// carousel it came from, nor forward one that crossed `max_hops` bridges
+/// already, e.g. when bridges form a ring.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{Bridge, Carousel};
+/// use std::sync::mpsc;
+///
+/// let (tx, rx) = mpsc::channel();
+/// let a = Carousel::builder().build();
+/// let b = Carousel::builder().build();
+/// b.subscribe("orders", tx);
+/// let _bridge = Bridge::connect(&a, &b, &["orders"], 1);
+///
+/// a.put_to("orders", String::from("test").into_bytes());
+/// assert_eq!(rx.recv().unwrap(), b"test");
+/// ```
+pub struct Bridge {
+    a: Carousel,
+    b: Carousel,
+    /// The forwarders subscribed on `a` and on `b`.
+    subscriptions: (Vec<SubscriptionId>, Vec<SubscriptionId>),
+}
+
+impl Bridge {
+    /// Starts forwarding the messages put on `topics` on `a` to `b` and the
+    /// other way around, for at most `max_hops` bridges each.
+    pub fn connect(a: &Carousel, b: &Carousel, topics: &[&str], max_hops: u32) -> Bridge {
+        assert!(max_hops > 0);
+
+        let bridge = NEXT_BRIDGE.fetch_add(1, Ordering::Relaxed);
+        let subscribe = |from: &Carousel, to: &Carousel| {
+            topics
+                .iter()
+                .map(|topic| {
+                    let forward = Forward {
+                        to: to.clone(),
+                        bridge,
+                        max_hops,
+                    };
+                    from.subscribe(topic, forward)
+                })
+                .collect()
+        };
+        let subscriptions = (subscribe(a, b), subscribe(b, a));
+
+        Bridge {
+            a: a.clone(),
+            b: b.clone(),
+            subscriptions,
+        }
+    }
+}
+
+impl Drop for Bridge {
+    fn drop(&mut self) {
+        for &id in &self.subscriptions.0 {
+            self.a.remove(id);
+        }
+        for &id in &self.subscriptions.1 {
+            self.b.remove(id);
+        }
+    }
+}
+
+/// `Forward` is the consumer of a `Bridge` on one of its carousels.
+struct Forward {
+    to: Carousel,
+    bridge: u64,
+    max_hops: u32,
+}
+
+impl CtxConsumer for Forward {
+    fn consume_with(&mut self, ctx: &Ctx<'_>, data: Vec<u8>) -> Result<(), Error> {
+        let count = match ctx.hop {
+            Some(hop) if hop.bridge == self.bridge || hop.count >= self.max_hops => return Ok(()),
+            Some(hop) => hop.count + 1,
+            None => 1,
+        };
+        let hop = Hop {
+            count,
+            bridge: self.bridge,
+        };
+        // Subscribed to a topic, the forwarder only sees messages with one.
+        let topic = ctx.topic().unwrap_or_default();
+        self.to.put_hop(topic, data, hop).map_err(Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asynch::{Bridge, Carousel, Ctx, CtxConsumer, Error};
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    /// Sends the payload and the hops of every message.
+    struct Hops(mpsc::Sender<(Vec<u8>, u32)>);
+
+    impl CtxConsumer for Hops {
+        fn consume_with(&mut self, ctx: &Ctx<'_>, data: Vec<u8>) -> Result<(), Error> {
+            self.0.send((data, ctx.hops())).unwrap();
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn bridge() {
+        let carousels: Vec<Carousel> = (0..3).map(|_| Carousel::builder().build()).collect();
+        let receivers: Vec<_> = carousels
+            .iter()
+            .map(|c| {
+                let (tx, rx) = mpsc::channel();
+                c.subscribe("t", Hops(tx));
+                rx
+            })
+            .collect();
+        let ab = Bridge::connect(&carousels[0], &carousels[1], &["t"], 2);
+        let bc = Bridge::connect(&carousels[1], &carousels[2], &["t"], 2);
+
+        carousels[0].put_to("t", b"a".to_vec());
+        carousels[2].put_to("t", b"c".to_vec());
+        carousels[0].put_to("other", b"other".to_vec());
+        let recv = |i: usize| receivers[i].recv_timeout(Duration::from_secs(5)).unwrap();
+        let mut received: Vec<_> = (0..6).map(|i| (i % 3, recv(i % 3))).collect();
+        received.sort();
+        assert_eq!(
+            received,
+            vec![
+                (0, (b"a".to_vec(), 0)),
+                (0, (b"c".to_vec(), 2)),
+                (1, (b"a".to_vec(), 1)),
+                (1, (b"c".to_vec(), 1)),
+                (2, (b"a".to_vec(), 2)),
+                (2, (b"c".to_vec(), 0)),
+            ]
+        );
+
+        // Dropped bridges forward no more.
+        drop((ab, bc));
+        carousels[0].put_to("t", b"late".to_vec());
+        assert_eq!(recv(0), (b"late".to_vec(), 0));
+        drop(carousels);
+        assert!(receivers.iter().all(|rx| rx.try_recv().is_err()));
+    }
+}
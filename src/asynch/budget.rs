@@ -0,0 +1,290 @@
+use crate::asynch::Envelope;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{self, Arc};
+
+/// `Overflow` selects what a `Carousel` does with a message put while its
+/// memory budget is used up, see `Builder::memory_budget`.
+#[derive(Debug)]
+pub enum Overflow {
+    /// The producer blocks until consumers have freed enough memory.
+    Block,
+    /// The message is dropped and counted in `CarouselStats::dropped`.
+    Drop,
+    /// The payload is written to a spill file and read back by each consumer
+    /// when it gets to the message.
+    Spill(SpillFile),
+}
+
+/// `SpillFile` holds payloads that don't fit in the memory budget of a
+/// `Carousel`. The file is emptied whenever no spilled message is waiting.
+#[derive(Debug)]
+pub struct SpillFile {
+    state: sync::Mutex<SpillState>,
+}
+
+#[derive(Debug)]
+struct SpillState {
+    file: fs::File,
+    end: u64,
+    live: usize,
+}
+
+impl SpillFile {
+    /// Creates the spill file in `dir`, replacing the payloads spilled by a
+    /// previous process which are of no use after a restart.
+    pub fn create<P: AsRef<Path>>(dir: P) -> io::Result<SpillFile> {
+        fs::create_dir_all(&dir)?;
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.as_ref().join("spill"))?;
+        Ok(SpillFile {
+            state: sync::Mutex::new(SpillState {
+                file,
+                end: 0,
+                live: 0,
+            }),
+        })
+    }
+
+    fn push(&self, data: &[u8]) -> io::Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        let offset = state.end;
+        state.file.seek(SeekFrom::Start(offset))?;
+        state.file.write_all(data)?;
+        state.end += data.len() as u64;
+        state.live += 1;
+        Ok(offset)
+    }
+
+    fn read(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut state = self.state.lock().unwrap();
+        let mut data = vec![0; len];
+        state.file.seek(SeekFrom::Start(offset))?;
+        state.file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.live -= 1;
+        if state.live == 0 {
+            state.end = 0;
+            if let Err(e) = state.file.set_len(0) {
+                println!("Carousel error truncating the spill file: {}", e);
+            }
+        }
+    }
+}
+
+/// `Policy` is an `Overflow` as applied by a `Budget`. The spill file is
+/// shared with the spilled messages, which may still need it after the
+/// policy changed.
+#[derive(Clone)]
+enum Policy {
+    Block,
+    Drop,
+    Spill(Arc<SpillFile>),
+}
+
+impl From<Overflow> for Policy {
+    fn from(overflow: Overflow) -> Self {
+        match overflow {
+            Overflow::Block => Policy::Block,
+            Overflow::Drop => Policy::Drop,
+            Overflow::Spill(file) => Policy::Spill(Arc::new(file)),
+        }
+    }
+}
+
+/// `Budget` tracks the bytes of payload held in memory by a `Carousel`.
+pub(crate) struct Budget {
+    limit: AtomicUsize,
+    used: AtomicUsize,
+    dropped: AtomicU64,
+    policy: sync::RwLock<Policy>,
+    /// Counts the changes that may let a blocked message in: released
+    /// charges, a raised limit or another policy.
+    changes: sync::Mutex<u64>,
+    changed: sync::Condvar,
+}
+
+impl Budget {
+    pub(crate) fn new(limit: usize, overflow: Overflow) -> Budget {
+        Budget {
+            limit: AtomicUsize::new(limit),
+            used: AtomicUsize::new(0),
+            dropped: AtomicU64::new(0),
+            policy: sync::RwLock::new(Policy::from(overflow)),
+            changes: sync::Mutex::new(0),
+            changed: sync::Condvar::new(),
+        }
+    }
+
+    /// Changes the limit of the budget. Messages already held stay in memory
+    /// even if they exceed the new limit.
+    pub(crate) fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::SeqCst);
+        self.notify();
+    }
+
+    /// Changes the overflow policy for messages put from now on.
+    pub(crate) fn set_overflow(&self, overflow: Overflow) {
+        *self.policy.write().unwrap() = Policy::from(overflow);
+        self.notify();
+    }
+
+    /// Wakes the messages blocked in `charge` to check the budget again.
+    fn notify(&self) {
+        *self.changes.lock().unwrap() += 1;
+        self.changed.notify_all();
+    }
+
+    pub(crate) fn spills(&self) -> bool {
+        matches!(*self.policy.read().unwrap(), Policy::Spill(_))
+    }
+
+    pub(crate) fn limit(&self) -> usize {
+        self.limit.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::SeqCst)
+    }
+
+    /// Charges the payload of `envelope` to the budget, applying the overflow
+    /// policy if it doesn't fit. Returns `false` if the message is dropped.
+    pub(crate) fn admit(self: &Arc<Self>, envelope: &mut Envelope) -> bool {
+        match self.charge(envelope) {
+            Some(charge) => {
+                envelope.charge = charge;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Like `admit`, but charges `envelope` to the quota of its namespace.
+    pub(crate) fn admit_quota(self: &Arc<Self>, envelope: &mut Envelope) -> bool {
+        match self.charge(envelope) {
+            Some(charge) => {
+                envelope.quota = charge;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Like `admit`, but charges this copy of `envelope` to the memory limit
+    /// of its consumer.
+    pub(crate) fn admit_backlog(self: &Arc<Self>, envelope: &mut Envelope) -> bool {
+        match self.charge(envelope) {
+            Some(charge) => {
+                envelope.backlog = charge;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the charge of `envelope`, which is `Some(None)` if its payload
+    /// was spilled instead, or `None` if the message is dropped.
+    fn charge(self: &Arc<Self>, envelope: &mut Envelope) -> Option<Option<Arc<Charge>>> {
+        let bytes = envelope.data.len();
+        let mut changes = *self.changes.lock().unwrap();
+        while !self.reserve(bytes) {
+            // Copied, so that the policy can be changed while a message
+            // blocks.
+            let policy = self.policy.read().unwrap().clone();
+            match policy {
+                Policy::Block => {
+                    let mut current = self.changes.lock().unwrap();
+                    while *current == changes {
+                        current = self.changed.wait(current).unwrap();
+                    }
+                    changes = *current;
+                }
+                Policy::Drop => {
+                    self.dropped.fetch_add(1, Ordering::SeqCst);
+                    return None;
+                }
+                Policy::Spill(file) => match file.push(&envelope.data) {
+                    Ok(offset) => {
+                        envelope.data = Arc::default();
+                        envelope.spilled = Some(Arc::new(Spilled {
+                            file,
+                            offset,
+                            len: bytes,
+                        }));
+                        return Some(None);
+                    }
+                    Err(e) => {
+                        println!("Carousel error spilling a message: {}", e);
+                        self.dropped.fetch_add(1, Ordering::SeqCst);
+                        return None;
+                    }
+                },
+            }
+        }
+        Some(Some(Arc::new(Charge {
+            budget: Arc::clone(self),
+            bytes,
+        })))
+    }
+
+    /// Reserves `bytes` if they fit. A message larger than the whole budget
+    /// fits once nothing else is held.
+    fn reserve(&self, bytes: usize) -> bool {
+        self.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                if used == 0 || used + bytes <= self.limit.load(Ordering::SeqCst) {
+                    Some(used + bytes)
+                } else {
+                    None
+                }
+            })
+            .is_ok()
+    }
+}
+
+/// `Charge` is the share of the budget held by a message. It is shared by all
+/// copies of the message and given back once the last copy is gone.
+pub(crate) struct Charge {
+    budget: Arc<Budget>,
+    bytes: usize,
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.bytes, Ordering::SeqCst);
+        self.budget.notify();
+    }
+}
+
+/// `Spilled` locates the payload of a message in the spill file.
+pub(crate) struct Spilled {
+    file: Arc<SpillFile>,
+    offset: u64,
+    len: usize,
+}
+
+impl Spilled {
+    pub(crate) fn read(&self) -> io::Result<Vec<u8>> {
+        self.file.read(self.offset, self.len)
+    }
+}
+
+impl Drop for Spilled {
+    fn drop(&mut self) {
+        self.file.release();
+    }
+}
@@ -0,0 +1,98 @@
+use crate::asynch::{Carousel, Envelope, PutError, Shared};
+use std::collections::VecDeque;
+use std::sync::{self, atomic::Ordering};
+use std::time::Duration;
+
+/// How often a flusher checks whether its `Carousel` became writable again.
+const RETRY: Duration = Duration::from_millis(1);
+/// How often an idle flusher checks whether its handles were dropped.
+const IDLE: Duration = Duration::from_millis(100);
+
+/// `Buffer` holds the messages put by the handles of `Carousel::buffered`
+/// while the `Carousel` wasn't writable, in the order they were put.
+pub(crate) struct Buffer {
+    messages: sync::Mutex<VecDeque<Envelope>>,
+    added: sync::Condvar,
+    capacity: usize,
+}
+
+impl Buffer {
+    /// Returns a buffer of at most `capacity` messages for the handles of
+    /// `carousel`, and starts the thread flushing it.
+    pub(crate) fn spawn(carousel: &Carousel, capacity: usize) -> sync::Arc<Buffer> {
+        let buffer = sync::Arc::new(Buffer {
+            messages: sync::Mutex::new(VecDeque::new()),
+            added: sync::Condvar::new(),
+            capacity,
+        });
+        let flushed = sync::Arc::clone(&buffer);
+        let shared = sync::Arc::downgrade(&carousel.shared);
+        let producer = carousel.producer.clone();
+        carousel.shared.spawn(String::from("buffer"), move || loop {
+            let messages = flushed.messages.lock().unwrap();
+            if messages.is_empty() {
+                // The flusher holds the last reference once the handles
+                // were dropped.
+                if sync::Arc::strong_count(&flushed) == 1 {
+                    return;
+                }
+                drop(flushed.added.wait_timeout(messages, IDLE).unwrap());
+                continue;
+            }
+            drop(messages);
+            let live: sync::Arc<Shared> = match shared.upgrade() {
+                Some(live) => live,
+                None => return flushed.lose(),
+            };
+            let mut carousel = Carousel::handle(&live);
+            carousel.producer = producer.clone();
+            if !flushed.flush(&carousel) {
+                if sync::Arc::strong_count(&flushed) == 1 && live.quiesced.load(Ordering::SeqCst) {
+                    return flushed.lose();
+                }
+                std::thread::sleep(RETRY);
+            }
+        });
+        buffer
+    }
+
+    /// Sends `envelope` right away if nothing is buffered and `carousel` is
+    /// writable, or buffers it otherwise.
+    pub(crate) fn put(&self, carousel: &Carousel, envelope: Envelope) -> Result<(), PutError> {
+        let mut messages = self.messages.lock().unwrap();
+        if messages.is_empty() && carousel.is_writable() {
+            return carousel.try_send_now(envelope);
+        }
+        if messages.len() >= self.capacity {
+            return Err(PutError::BufferFull);
+        }
+        messages.push_back(envelope);
+        self.added.notify_one();
+        Ok(())
+    }
+
+    /// Sends the buffered messages in order while `carousel` is writable, and
+    /// returns whether the buffer was emptied.
+    fn flush(&self, carousel: &Carousel) -> bool {
+        let mut messages = self.messages.lock().unwrap();
+        while carousel.is_writable() {
+            match messages.pop_front() {
+                Some(envelope) => {
+                    if let Err(e) = carousel.try_send_now(envelope) {
+                        println!("Carousel rejected a buffered message: {}", e);
+                    }
+                }
+                None => return true,
+            }
+        }
+        messages.is_empty()
+    }
+
+    /// Prints how many messages are left, once they no longer can be sent.
+    fn lose(&self) {
+        let lost = self.messages.lock().unwrap().len();
+        if lost > 0 {
+            println!("{} buffered messages were never sent and are lost.", lost);
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use std::error;
+use std::fmt;
+
+/// The table of the reflected CRC-32 polynomial used by zlib, gzip and PNG.
+const TABLE: [u32; 256] = table();
+
+const fn table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Returns the CRC-32 of `data`.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    !data.iter().fold(!0, |crc, &b| {
+        TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Fails if `data` doesn't match the checksum `expected` it was stamped with.
+pub(crate) fn verify(expected: u32, data: &[u8]) -> Result<(), ChecksumError> {
+    let actual = crc32(data);
+    if actual != expected {
+        return Err(ChecksumError { expected, actual });
+    }
+    Ok(())
+}
+
+/// `ChecksumError` reports a message whose payload no longer matches the
+/// CRC-32 it was stamped with at `put`, see `Builder::checksums`. It is handed
+/// to the error handler as the error of a `ConsumeError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChecksumError {
+    /// The checksum stamped at `put`.
+    pub expected: u32,
+    /// The checksum of the payload that arrived at the consumer.
+    pub actual: u32,
+}
+
+impl fmt::Display for ChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "corrupted message: expected checksum {:08x}, got {:08x}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl error::Error for ChecksumError {}
+
+#[cfg(test)]
+mod tests {
+    use super::{crc32, verify};
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b""), 0);
+        assert!(verify(crc32(b"data"), b"date").is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::asynch::{unshare, Envelope};
+use std::collections::HashMap;
+use std::sync;
+
+/// `Chunk` marks an envelope as one part of a message split by `split`.
+#[derive(Clone, Copy)]
+pub(crate) struct Chunk {
+    /// The number shared by all chunks of a message.
+    pub(crate) message: u64,
+    pub(crate) index: usize,
+    pub(crate) last: bool,
+    /// The multiplier shard the chunks of the message are sent through.
+    pub(crate) shard: usize,
+}
+
+/// Splits the payload of `envelope` into chunks of at most `size` bytes, to
+/// be sent through `shard` one after the other.
+pub(crate) fn split(envelope: Envelope, size: usize, message: u64, shard: usize) -> Vec<Envelope> {
+    let n = envelope.data.chunks(size).len();
+    envelope
+        .data
+        .chunks(size)
+        .enumerate()
+        .map(|(index, part)| Envelope {
+            data: sync::Arc::new(part.to_vec()),
+            chunk: Some(Chunk {
+                message,
+                index,
+                last: index + 1 == n,
+                shard,
+            }),
+            ..envelope.clone()
+        })
+        .collect()
+}
+
+/// `Assembler` puts chunked messages back together on a poller thread.
+///
+/// The chunks of a message follow one another on its shard, so there is at
+/// most one partial message per shard: a chunk of a later message, or a gap
+/// in the chunks, means the partial message lost a chunk, e.g. to an overflow
+/// policy or to expiry, and it is discarded.
+#[derive(Default)]
+pub(crate) struct Assembler {
+    partial: HashMap<usize, Partial>,
+}
+
+/// `Partial` is a message whose chunks arrived up to `next`.
+struct Partial {
+    message: u64,
+    next: usize,
+    data: Vec<u8>,
+}
+
+impl Assembler {
+    /// Adds `envelope` to the message it belongs to and returns the whole
+    /// message once its last chunk arrived. Envelopes that aren't chunks are
+    /// returned as they are. A message with a missing chunk, e.g. because the
+    /// consumer subscribed in the middle of it, is discarded.
+    pub(crate) fn assemble(&mut self, consumer: usize, mut envelope: Envelope) -> Option<Envelope> {
+        let chunk = match envelope.chunk {
+            Some(chunk) => chunk,
+            None => return Some(envelope),
+        };
+
+        let mut partial = match self.partial.remove(&chunk.shard) {
+            Some(partial) if partial.message == chunk.message && partial.next == chunk.index => {
+                partial.data
+            }
+            _ if chunk.index == 0 => Vec::new(),
+            _ => return None,
+        };
+        let data = match envelope.spilled.take() {
+            Some(spilled) => spilled.read(),
+            None => Ok(unshare(envelope.data)),
+        };
+        match data {
+            Ok(data) => partial.extend_from_slice(&data),
+            Err(e) => {
+                println!(
+                    "Consumer {} failed to read part of a message: {}",
+                    consumer, e
+                );
+                return None;
+            }
+        }
+        if !chunk.last {
+            let partial = Partial {
+                message: chunk.message,
+                next: chunk.index + 1,
+                data: partial,
+            };
+            self.partial.insert(chunk.shard, partial);
+            return None;
+        }
+
+        Some(Envelope {
+            data: sync::Arc::new(partial),
+            chunk: None,
+            ..envelope
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asynch::chunk::{split, Assembler};
+    use crate::asynch::Carousel;
+
+    #[test]
+    fn message_missing_a_chunk_is_discarded() {
+        let c = Carousel::builder().build();
+        let mut assembler = Assembler::default();
+        let mut chunks = split(c.envelope(b"lost chunk".to_vec()), 4, 0, 0);
+        assert_eq!(chunks.len(), 3);
+        chunks.remove(1);
+        for chunk in chunks {
+            assert!(assembler.assemble(0, chunk).is_none());
+        }
+        assert!(assembler.partial.is_empty());
+
+        // A message that lost its last chunk is superseded by the next one,
+        // while the other shards keep theirs.
+        let mut lost = split(c.envelope(b"lost end".to_vec()), 4, 1, 0);
+        lost.pop();
+        let other = split(c.envelope(b"whole 1".to_vec()), 4, 2, 1);
+        let whole = split(c.envelope(b"whole 0".to_vec()), 4, 3, 0);
+        let interleaved = other.into_iter().zip(whole).flat_map(|(o, w)| [o, w]);
+        let mut assembled = Vec::new();
+        for chunk in lost.into_iter().chain(interleaved) {
+            assembled.extend(assembler.assemble(0, chunk));
+        }
+        let assembled: Vec<_> = assembled.iter().map(|e| e.data.to_vec()).collect();
+        assert_eq!(assembled, vec![b"whole 1".to_vec(), b"whole 0".to_vec()]);
+        assert!(assembler.partial.is_empty());
+    }
+}
@@ -0,0 +1,88 @@
+use std::sync;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// `Clock` is the time source of a `Carousel`, see `Builder::clock`. It
+/// timestamps messages, decides when they expire and paces rate limits and
+/// producer quotas.
+pub trait Clock: Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+
+    /// Waits until `duration` has passed on this clock.
+    fn sleep(&self, duration: Duration);
+}
+
+impl<T: Clock + ?Sized> Clock for sync::Arc<T> {
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        (**self).sleep(duration)
+    }
+}
+
+/// `SystemClock` is the monotonic clock of the operating system, the default
+/// `Clock` of a `Carousel`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
+
+/// `MockClock` is a `Clock` that only moves when told to, for testing time
+/// dependent behaviour deterministically. Sleeping advances it instead of
+/// waiting. Clones share the same time.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{Clock, MockClock};
+/// use std::time::Duration;
+///
+/// let clock = MockClock::new();
+/// let start = clock.now();
+/// clock.advance(Duration::from_secs(60));
+/// assert_eq!(clock.now() - start, Duration::from_secs(60));
+/// ```
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: sync::Arc<sync::Mutex<Instant>>,
+}
+
+impl MockClock {
+    /// Creates a clock standing at the current time.
+    pub fn new() -> MockClock {
+        MockClock {
+            now: sync::Arc::new(sync::Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
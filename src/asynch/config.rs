@@ -0,0 +1,58 @@
+use crate::asynch::{Overflow, Sharding, WaitStrategy};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// `CarouselConfig` holds the settings of a `Carousel` that deployments may
+/// want to tune without recompiling, e.g. after reading them from a file. It
+/// is applied with `Carousel::from_config` or `Builder::config`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CarouselConfig {
+    /// The payload bytes the `Carousel` may hold in memory before producers
+    /// block, see `Builder::memory_budget`. Unlimited if `None`.
+    pub capacity: Option<usize>,
+    /// The number of multiplier threads, see `Builder::multiplier_shards`.
+    pub shards: usize,
+    /// How messages are spread over more than one multiplier thread.
+    pub sharding: Sharding,
+    /// How threads wait for work, see `Builder::wait_strategy`.
+    pub wait_strategy: WaitStrategy,
+    /// The prefix of the names of the threads, see `Builder::thread_name`.
+    pub thread_name: Option<String>,
+    /// The messages per second each consumer may consume, by consumer id.
+    pub rate_limits: HashMap<usize, u32>,
+    /// How often a failed message is retried, see `Builder::retries`.
+    pub retries: usize,
+    /// How long messages live, see `Builder::ttl`.
+    pub ttl: Option<Duration>,
+    /// The size of chunks of large messages, see `Builder::chunk_size`.
+    pub chunk_size: Option<usize>,
+}
+
+impl Default for CarouselConfig {
+    fn default() -> Self {
+        CarouselConfig {
+            capacity: None,
+            shards: 1,
+            sharding: Sharding::RoundRobin,
+            wait_strategy: WaitStrategy::Block,
+            thread_name: None,
+            rate_limits: HashMap::new(),
+            retries: 0,
+            ttl: None,
+            chunk_size: None,
+        }
+    }
+}
+
+/// `ConfigDelta` holds changes to the settings of a running `Carousel`,
+/// applied with `Carousel::reconfigure`. Settings left as `None`, and
+/// consumers left out of `rate_limits`, stay as they are.
+#[derive(Debug, Default)]
+pub struct ConfigDelta {
+    /// The new memory budget in payload bytes, see `Builder::memory_budget`.
+    pub capacity: Option<usize>,
+    /// The new overflow policy of the memory budget.
+    pub overflow: Option<Overflow>,
+    /// The new rate limits by consumer id, where `None` lifts the limit.
+    pub rate_limits: HashMap<usize, Option<u32>>,
+}
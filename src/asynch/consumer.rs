@@ -0,0 +1,474 @@
+use crate::asynch::Consumer;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashSet, VecDeque};
+use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::sync::{self, mpsc};
+
+/// `SharedConsumer` is a consumer that can handle messages through a shared
+/// reference, so that a single instance wrapped in an `Arc` can be registered
+/// with several pollers or carousels.
+///
+/// Any `Consumer` can be shared by wrapping it in an `Arc<Mutex<_>>`.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{Carousel, SharedConsumer};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+///
+/// struct Counter(AtomicUsize);
+///
+/// impl SharedConsumer for Counter {
+///   fn consume(&self, _data: Vec<u8>) {
+///     self.0.fetch_add(1, Ordering::SeqCst);
+///   }
+/// }
+///
+/// let counter = Arc::new(Counter(AtomicUsize::new(0)));
+///
+/// let c1 = Carousel::builder().consumer(Arc::clone(&counter)).build();
+/// let c2 = Carousel::builder().consumer(Arc::clone(&counter)).build();
+/// ```
+pub trait SharedConsumer {
+    fn consume(&self, data: Vec<u8>);
+}
+
+impl<T: Consumer + ?Sized> SharedConsumer for sync::Mutex<T> {
+    fn consume(&self, data: Vec<u8>) {
+        self.lock().unwrap().consume(data);
+    }
+}
+
+impl<T: SharedConsumer + ?Sized> Consumer for sync::Arc<T> {
+    fn consume(&mut self, data: Vec<u8>) {
+        (**self).consume(data);
+    }
+}
+
+/// Forwards every message to the receiving end of the channel. Messages are
+/// discarded once the receiver has been dropped.
+impl Consumer for mpsc::Sender<Vec<u8>> {
+    fn consume(&mut self, data: Vec<u8>) {
+        let _ = self.send(data);
+    }
+}
+
+/// Forwards every message to the receiving end of the channel, blocking the
+/// poller while the channel is full. Messages are discarded once the receiver
+/// has been dropped.
+impl Consumer for mpsc::SyncSender<Vec<u8>> {
+    fn consume(&mut self, data: Vec<u8>) {
+        let _ = self.send(data);
+    }
+}
+
+/// `FromFn` is a `Consumer` calling a closure for each message. It is created
+/// with `from_fn`.
+pub struct FromFn<F> {
+    f: F,
+}
+
+/// Creates a `Consumer` that calls `f` for each message.
+///
+/// This is the shortest way to register channels of other crates as consumers.
+///
+/// # Example
+/// ```ignore
+/// let (tx, rx) = crossbeam_channel::unbounded();
+///
+/// let c = Carousel::builder()
+///   .consumer(mp2c::asynch::from_fn(move |data| {
+///     let _ = tx.send(data);
+///   }))
+///   .build();
+/// ```
+pub fn from_fn<F>(f: F) -> FromFn<F>
+where
+    F: FnMut(Vec<u8>),
+{
+    FromFn { f }
+}
+
+impl<F> Consumer for FromFn<F>
+where
+    F: FnMut(Vec<u8>),
+{
+    fn consume(&mut self, data: Vec<u8>) {
+        (self.f)(data);
+    }
+}
+
+/// `ConsumerExt` offers combinators to assemble consumers from pieces. It is
+/// implemented for every `Consumer`.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{from_fn, Carousel, ConsumerExt};
+///
+/// let upper = from_fn(|data| println!("{}", String::from_utf8(data).unwrap()))
+///   .map(|data: Vec<u8>| data.to_ascii_uppercase())
+///   .filter(|data: &[u8]| !data.is_empty());
+///
+/// let c = Carousel::builder().consumer(upper).build();
+/// c.put(String::from("test").into_bytes());
+/// ```
+pub trait ConsumerExt: Consumer + Sized {
+    /// Transforms every message with `f` before this consumer sees it.
+    fn map<F>(self, f: F) -> Map<Self, F>
+    where
+        F: FnMut(Vec<u8>) -> Vec<u8>,
+    {
+        Map { consumer: self, f }
+    }
+
+    /// Only hands messages to this consumer for which `predicate` returns `true`.
+    fn filter<P>(self, predicate: P) -> Filter<Self, P>
+    where
+        P: FnMut(&[u8]) -> bool,
+    {
+        Filter {
+            consumer: self,
+            predicate,
+        }
+    }
+
+    /// Hands a copy of every message to `other` after this consumer has seen it.
+    fn tee<C>(self, other: C) -> Tee<Self, C>
+    where
+        C: Consumer,
+    {
+        Tee {
+            consumer: self,
+            other,
+        }
+    }
+
+    /// Logs every `n`th message before this consumer sees it, to peek at live
+    /// traffic. Messages are printed to stdout unless `LogEvery::to` sends
+    /// them elsewhere, such as a `log` or `tracing` macro.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::{from_fn, Carousel, ConsumerExt};
+    ///
+    /// let consumer = from_fn(|_| {}).log_every(1000).truncate(64).hex();
+    ///
+    /// let c = Carousel::builder().consumer(consumer).build();
+    /// c.put(String::from("test").into_bytes());
+    /// ```
+    fn log_every(self, n: usize) -> LogEvery<Self> {
+        assert!(n > 0);
+
+        LogEvery {
+            consumer: self,
+            every: n as u64,
+            seen: 0,
+            next: n as u64,
+            truncate: None,
+            hex: false,
+            sink: Box::new(|line: &str| println!("{}", line)),
+        }
+    }
+
+    /// Skips messages whose payload equals one of the last `window` distinct
+    /// payloads this consumer passed on, e.g. to stay idempotent when the
+    /// same messages arrive from several carousels or from a replay. Skipped
+    /// duplicates don't move the window, so a payload repeated often stays in
+    /// it until `window` other payloads arrived. It works without message
+    /// ids, see `Carousel::put_with_id` for deduplicating by id.
+    ///
+    /// Payloads are compared by a 64 bit hash, so a rare collision skips a
+    /// distinct message. To deduplicate across carousels, share a single
+    /// instance, e.g. in an `Arc<Mutex<_>>`, see `SharedConsumer`.
+    ///
+    /// # Example
+    /// ```
+    /// use mp2c::asynch::{Carousel, ConsumerExt};
+    /// use std::sync::mpsc;
+    ///
+    /// let (tx, rx) = mpsc::channel();
+    /// let c = Carousel::builder().consumer(tx.dedup_window(1000)).build();
+    /// c.put(String::from("test").into_bytes());
+    /// c.put(String::from("test").into_bytes());
+    /// drop(c);
+    /// assert_eq!(rx.iter().count(), 1);
+    /// ```
+    fn dedup_window(self, window: usize) -> DedupWindow<Self> {
+        assert!(window > 0);
+
+        DedupWindow {
+            consumer: self,
+            window,
+            order: VecDeque::with_capacity(window),
+            seen: HashSet::with_capacity(window),
+        }
+    }
+}
+
+impl<T: Consumer> ConsumerExt for T {}
+
+/// `Map` is the consumer returned by `ConsumerExt::map`.
+pub struct Map<C, F> {
+    consumer: C,
+    f: F,
+}
+
+impl<C, F> Consumer for Map<C, F>
+where
+    C: Consumer,
+    F: FnMut(Vec<u8>) -> Vec<u8>,
+{
+    fn consume(&mut self, data: Vec<u8>) {
+        self.consumer.consume((self.f)(data));
+    }
+}
+
+/// `Filter` is the consumer returned by `ConsumerExt::filter`.
+pub struct Filter<C, P> {
+    consumer: C,
+    predicate: P,
+}
+
+impl<C, P> Consumer for Filter<C, P>
+where
+    C: Consumer,
+    P: FnMut(&[u8]) -> bool,
+{
+    fn consume(&mut self, data: Vec<u8>) {
+        if (self.predicate)(&data) {
+            self.consumer.consume(data);
+        }
+    }
+}
+
+/// `Tee` is the consumer returned by `ConsumerExt::tee`.
+pub struct Tee<A, B> {
+    consumer: A,
+    other: B,
+}
+
+impl<A, B> Consumer for Tee<A, B>
+where
+    A: Consumer,
+    B: Consumer,
+{
+    fn consume(&mut self, data: Vec<u8>) {
+        self.consumer.consume(data.clone());
+        self.other.consume(data);
+    }
+}
+
+/// `LogEvery` is the consumer returned by `ConsumerExt::log_every`.
+pub struct LogEvery<C> {
+    consumer: C,
+    every: u64,
+    seen: u64,
+    /// The number of the next message to log.
+    next: u64,
+    truncate: Option<usize>,
+    hex: bool,
+    sink: Box<dyn FnMut(&str) + Send>,
+}
+
+impl<C> LogEvery<C> {
+    /// Logs at most the first `bytes` bytes of each message.
+    pub fn truncate(mut self, bytes: usize) -> LogEvery<C> {
+        self.truncate = Some(bytes);
+        self
+    }
+
+    /// Logs the payload as hex bytes instead of as text.
+    pub fn hex(mut self) -> LogEvery<C> {
+        self.hex = true;
+        self
+    }
+
+    /// Hands each log line to `sink` instead of printing it.
+    pub fn to<F>(mut self, sink: F) -> LogEvery<C>
+    where
+        F: FnMut(&str) + Send + 'static,
+    {
+        self.sink = Box::new(sink);
+        self
+    }
+
+    /// Returns the log line of the `n`th message `data`.
+    fn line(&self, n: u64, data: &[u8]) -> String {
+        let shown = &data[..self.truncate.map_or(data.len(), |max| max.min(data.len()))];
+        let mut line = format!("Message {} ({} bytes): ", n, data.len());
+        if self.hex {
+            for (i, byte) in shown.iter().enumerate() {
+                let separator = if i == 0 { "" } else { " " };
+                write!(line, "{}{:02x}", separator, byte).unwrap();
+            }
+        } else {
+            write!(line, "{:?}", String::from_utf8_lossy(shown)).unwrap();
+        }
+        if shown.len() < data.len() {
+            write!(line, " ... {} more bytes", data.len() - shown.len()).unwrap();
+        }
+        line
+    }
+}
+
+impl<C> Consumer for LogEvery<C>
+where
+    C: Consumer,
+{
+    fn consume(&mut self, data: Vec<u8>) {
+        self.seen += 1;
+        if self.seen == self.next {
+            self.next += self.every;
+            let line = self.line(self.seen, &data);
+            (self.sink)(&line);
+        }
+        self.consumer.consume(data);
+    }
+}
+
+/// `DedupWindow` is the consumer returned by `ConsumerExt::dedup_window`.
+pub struct DedupWindow<C> {
+    consumer: C,
+    window: usize,
+    /// The hashes of the last `window` distinct payloads, oldest first.
+    order: VecDeque<u64>,
+    seen: HashSet<u64>,
+}
+
+impl<C> Consumer for DedupWindow<C>
+where
+    C: Consumer,
+{
+    fn consume(&mut self, data: Vec<u8>) {
+        let mut hasher = DefaultHasher::new();
+        data.hash(&mut hasher);
+        let hash = hasher.finish();
+        if !self.seen.insert(hash) {
+            return;
+        }
+        if self.order.len() == self.window {
+            let oldest = self.order.pop_front().unwrap();
+            self.seen.remove(&oldest);
+        }
+        self.order.push_back(hash);
+        self.consumer.consume(data);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asynch::{from_fn, Carousel, ConsumerExt, SharedConsumer};
+    use std::sync::{self, mpsc};
+
+    #[test]
+    fn sender_and_from_fn() {
+        let (tx, rx) = mpsc::channel();
+        let (fn_tx, fn_rx) = mpsc::channel();
+
+        let c = Carousel::builder()
+            .consumer(tx)
+            .consumer(from_fn(move |data| fn_tx.send(data).unwrap()))
+            .build();
+
+        c.put(String::from("test").into_bytes());
+        drop(c);
+
+        assert_eq!(rx.recv().unwrap(), b"test".to_vec());
+        assert_eq!(fn_rx.recv().unwrap(), b"test".to_vec());
+    }
+
+    #[test]
+    fn combinators() {
+        let (tx, rx) = mpsc::channel();
+        let (tee_tx, tee_rx) = mpsc::channel();
+
+        let c = Carousel::builder()
+            .consumer(
+                tx.tee(tee_tx)
+                    .map(|data: Vec<u8>| data.to_ascii_uppercase())
+                    .filter(|data: &[u8]| data != b"skip"),
+            )
+            .build();
+
+        c.put(String::from("skip").into_bytes());
+        c.put(String::from("test").into_bytes());
+        drop(c);
+
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![b"TEST".to_vec()]);
+        assert_eq!(tee_rx.iter().collect::<Vec<_>>(), vec![b"TEST".to_vec()]);
+    }
+
+    #[test]
+    fn log_every() {
+        let (tx, rx) = mpsc::channel();
+        let (log_tx, log_rx) = mpsc::channel();
+        let text_tx = log_tx.clone();
+
+        let c = Carousel::builder()
+            .consumer(
+                tx.log_every(2)
+                    .hex()
+                    .truncate(2)
+                    .to(move |line| log_tx.send(String::from(line)).unwrap()),
+            )
+            .consumer(
+                from_fn(|_| {})
+                    .log_every(3)
+                    .to(move |line| text_tx.send(String::from(line)).unwrap()),
+            )
+            .build();
+        for data in &[&b"one"[..], b"two", b"three"] {
+            c.put(data.to_vec());
+        }
+        drop(c);
+
+        assert_eq!(rx.iter().count(), 3);
+        let mut lines: Vec<String> = log_rx.iter().collect();
+        lines.sort();
+        assert_eq!(
+            lines,
+            vec![
+                "Message 2 (3 bytes): 74 77 ... 1 more bytes",
+                "Message 3 (5 bytes): \"three\"",
+            ]
+        );
+    }
+
+    #[test]
+    fn dedup_window() {
+        let (tx, rx) = mpsc::channel();
+
+        let c = Carousel::builder().consumer(tx.dedup_window(2)).build();
+        for data in &[&b"a"[..], b"b", b"a", b"c", b"b", b"a"] {
+            c.put(data.to_vec());
+        }
+        drop(c);
+
+        // "a" left the window once "b" and "c" were seen after it.
+        assert_eq!(
+            rx.iter().collect::<Vec<_>>(),
+            vec![b"a".to_vec(), b"b".to_vec(), b"c".to_vec(), b"a".to_vec()]
+        );
+    }
+
+    #[test]
+    fn shared() {
+        let (tx, rx) = mpsc::channel();
+        let shared: sync::Arc<dyn SharedConsumer + Send + Sync> =
+            sync::Arc::new(sync::Mutex::new(tx));
+
+        let c1 = Carousel::builder()
+            .consumer(sync::Arc::clone(&shared))
+            .consumer(sync::Arc::clone(&shared))
+            .build();
+        let c2 = Carousel::builder().consumer(shared).build();
+
+        c1.put(String::from("test").into_bytes());
+        c2.put(String::from("test").into_bytes());
+        drop(c1);
+        drop(c2);
+
+        assert_eq!(rx.iter().count(), 3);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::asynch::bridge::Hop;
+use crate::asynch::{Carousel, Error, Lineage, Namespace, TryConsumer};
+
+/// `Ctx` is handed to a `CtxConsumer` along with each message. It describes
+/// the message and lets the consumer put derived messages on the `Carousel`
+/// it consumes from. A consumer subscribed within a `Namespace` puts derived
+/// messages into its namespace.
+pub struct Ctx<'a> {
+    pub(crate) carousel: &'a Carousel,
+    pub(crate) namespace: Option<&'a Namespace>,
+    pub(crate) consumer: usize,
+    pub(crate) id: Option<u64>,
+    pub(crate) offset: Option<u64>,
+    pub(crate) topic: Option<&'a str>,
+    pub(crate) lineage: Option<&'a Lineage>,
+    pub(crate) hop: Option<Hop>,
+}
+
+impl Ctx<'_> {
+    /// Puts a message on the `Carousel`, see `Carousel::put`.
+    pub fn put(&self, data: Vec<u8>) {
+        match self.namespace {
+            Some(namespace) => namespace.put(data),
+            None => self.carousel.put(data),
+        }
+    }
+
+    /// Puts a message on `topic`, see `Carousel::put_to`.
+    pub fn put_to(&self, topic: &str, data: Vec<u8>) {
+        match self.namespace {
+            Some(namespace) => namespace.put_to(topic, data),
+            None => self.carousel.put_to(topic, data),
+        }
+    }
+
+    /// Returns a handle to the `Carousel`, which may be kept beyond `consume`.
+    pub fn carousel(&self) -> Carousel {
+        self.carousel.clone()
+    }
+
+    /// Returns the id of the consuming consumer, its position in registration
+    /// order.
+    pub fn consumer(&self) -> usize {
+        self.consumer
+    }
+
+    /// Returns the producer assigned id of the message, see
+    /// `Carousel::put_with_id`.
+    pub fn id(&self) -> Option<u64> {
+        self.id
+    }
+
+    /// Returns the position of the message in the write-ahead log, see
+    /// `Builder::persistence`.
+    pub fn offset(&self) -> Option<u64> {
+        self.offset
+    }
+
+    /// Returns the namespace the consumer was subscribed within, if any.
+    pub fn namespace(&self) -> Option<&str> {
+        self.namespace.map(Namespace::name)
+    }
+
+    /// Returns the topic the message was put on, if any.
+    pub fn topic(&self) -> Option<&str> {
+        self.topic
+    }
+
+    /// Returns the path of the message through chained carousels, if the
+    /// `Carousel` tracks it, see `Builder::lineage`.
+    pub fn lineage(&self) -> Option<&Lineage> {
+        self.lineage
+    }
+
+    /// Returns the number of carousels the message was forwarded from by a
+    /// `Bridge`, 0 for a message put on this `Carousel`.
+    pub fn hops(&self) -> u32 {
+        self.hop.map_or(0, |hop| hop.count)
+    }
+}
+
+/// `CtxConsumer` is a `TryConsumer` that is handed a `Ctx` with each message.
+///
+/// Every `TryConsumer`, and so every `Consumer`, is a `CtxConsumer` that
+/// ignores the `Ctx`, so all three kinds of consumers are registered with
+/// `Builder::consumer`.
+pub trait CtxConsumer {
+    fn consume_with(&mut self, ctx: &Ctx<'_>, data: Vec<u8>) -> Result<(), Error>;
+}
+
+impl<T: TryConsumer + ?Sized> CtxConsumer for T {
+    fn consume_with(&mut self, _ctx: &Ctx<'_>, data: Vec<u8>) -> Result<(), Error> {
+        self.try_consume(data)
+    }
+}
+
+/// `Transformed` is a consumer registered with `Builder::transformed_consumer`.
+pub(crate) struct Transformed<T, F> {
+    pub(crate) consumer: T,
+    pub(crate) transform: F,
+}
+
+impl<T, F> CtxConsumer for Transformed<T, F>
+where
+    T: CtxConsumer,
+    F: FnMut(Vec<u8>) -> Option<Vec<u8>>,
+{
+    fn consume_with(&mut self, ctx: &Ctx<'_>, data: Vec<u8>) -> Result<(), Error> {
+        match (self.transform)(data) {
+            Some(data) => self.consumer.consume_with(ctx, data),
+            None => Ok(()),
+        }
+    }
+}
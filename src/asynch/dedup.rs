@@ -0,0 +1,144 @@
+use crate::asynch::Error;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use std::sync;
+
+/// `DedupStore` remembers which messages each consumer has processed, keyed by
+/// the consumer's position in registration order and the id a producer passed
+/// to `Carousel::put_with_id`.
+///
+/// A store registered with `Builder::exactly_once` is consulted before every
+/// message with an id is consumed, and a message is marked as processed once
+/// it was consumed successfully.
+pub trait DedupStore: Send + Sync {
+    /// Returns `true` if `consumer` has already processed the message `id`.
+    fn is_processed(&self, consumer: usize, id: u64) -> bool;
+
+    /// Records that `consumer` has processed the message `id`.
+    fn mark_processed(&self, consumer: usize, id: u64) -> Result<(), Error>;
+}
+
+/// `MemoryDedupStore` keeps processed message ids in memory. It protects against
+/// duplicates for the lifetime of the process and grows with every id it sees.
+#[derive(Default)]
+pub struct MemoryDedupStore {
+    processed: sync::Mutex<HashSet<(usize, u64)>>,
+}
+
+impl MemoryDedupStore {
+    /// Creates an empty `MemoryDedupStore`.
+    pub fn new() -> MemoryDedupStore {
+        MemoryDedupStore::default()
+    }
+}
+
+impl DedupStore for MemoryDedupStore {
+    fn is_processed(&self, consumer: usize, id: u64) -> bool {
+        self.processed.lock().unwrap().contains(&(consumer, id))
+    }
+
+    fn mark_processed(&self, consumer: usize, id: u64) -> Result<(), Error> {
+        self.processed.lock().unwrap().insert((consumer, id));
+        Ok(())
+    }
+}
+
+/// `FileDedupStore` appends processed message ids to a file and syncs it after
+/// each message, so that duplicates are recognized across restarts.
+///
+/// The file holds one `<consumer> <id>` line per processed message and is read
+/// back into memory when the store is opened.
+pub struct FileDedupStore {
+    processed: sync::Mutex<HashSet<(usize, u64)>>,
+    file: sync::Mutex<fs::File>,
+}
+
+impl FileDedupStore {
+    /// Opens the store at `path`, creating the file if it doesn't exist.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileDedupStore> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)?;
+
+        let mut processed = HashSet::new();
+        for line in io::BufReader::new(&file).lines() {
+            let line = line?;
+            let mut fields = line.split_whitespace().map(str::parse);
+            match (fields.next(), fields.next()) {
+                (Some(Ok(consumer)), Some(Ok(id))) => {
+                    processed.insert((consumer as usize, id));
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("malformed dedup entry: {}", line),
+                    ))
+                }
+            }
+        }
+
+        Ok(FileDedupStore {
+            processed: sync::Mutex::new(processed),
+            file: sync::Mutex::new(file),
+        })
+    }
+}
+
+impl DedupStore for FileDedupStore {
+    fn is_processed(&self, consumer: usize, id: u64) -> bool {
+        self.processed.lock().unwrap().contains(&(consumer, id))
+    }
+
+    fn mark_processed(&self, consumer: usize, id: u64) -> Result<(), Error> {
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{} {}", consumer, id)?;
+        file.sync_data()?;
+        self.processed.lock().unwrap().insert((consumer, id));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asynch::{Carousel, DedupStore, FileDedupStore, MemoryDedupStore};
+    use std::sync::mpsc;
+
+    #[test]
+    fn exactly_once() {
+        let (tx, rx) = mpsc::channel();
+
+        let c = Carousel::builder()
+            .consumer(tx)
+            .exactly_once(MemoryDedupStore::new())
+            .build();
+
+        c.put_with_id(1, String::from("one").into_bytes());
+        c.put_with_id(1, String::from("one").into_bytes());
+        c.put_with_id(2, String::from("two").into_bytes());
+        c.put(String::from("anonymous").into_bytes());
+        c.put(String::from("anonymous").into_bytes());
+        drop(c);
+
+        assert_eq!(rx.iter().count(), 4);
+    }
+
+    #[test]
+    fn file_store_survives_reopen() {
+        let path = std::env::temp_dir().join(format!("mp2c-dedup-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let store = FileDedupStore::open(&path).unwrap();
+        store.mark_processed(0, 7).unwrap();
+        drop(store);
+
+        let store = FileDedupStore::open(&path).unwrap();
+        assert!(store.is_processed(0, 7));
+        assert!(!store.is_processed(1, 7));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
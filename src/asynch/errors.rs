@@ -0,0 +1,111 @@
+use crate::asynch::ConsumerPanic;
+use std::error;
+use std::fmt;
+use std::sync::{self, mpsc};
+use std::time::Duration;
+
+/// `CarouselError` is an error event of a running `Carousel`, handed to the
+/// receivers of `Carousel::errors` to centralize error handling. The events
+/// are sent in addition to what the `Carousel` prints or hands to the error
+/// handler of `Builder::error_handler`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CarouselError {
+    /// The consumer `consumer` panicked while consuming a message, see
+    /// `Builder::panic_policy` and `Builder::supervised`.
+    ConsumerPanicked {
+        consumer: usize,
+        panic: ConsumerPanic,
+    },
+    /// The consumer `consumer` failed to consume a message after `attempts`
+    /// attempts, with the error `error`.
+    ConsumeFailed {
+        consumer: usize,
+        attempts: usize,
+        error: String,
+    },
+    /// A call to the consumer `consumer` took longer than `timeout`, see
+    /// `Builder::consume_timeout`.
+    TimedOut { consumer: usize, timeout: Duration },
+    /// `count` messages were dropped, for the consumer `consumer` only or,
+    /// without a consumer, for all of them.
+    Dropped {
+        consumer: Option<usize>,
+        reason: DropReason,
+        count: u64,
+    },
+    /// A message was put after the multipliers stopped and is lost.
+    ChannelClosed,
+    /// The write-ahead log failed to persist a message or a committed offset,
+    /// see `Builder::persistence`.
+    WalFailed { error: String },
+}
+
+/// `DropReason` tells why messages were dropped, see
+/// `CarouselError::Dropped`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropReason {
+    /// The message didn't fit in the memory budget, the quota of its
+    /// namespace or the memory limit of the consumer.
+    Overflow,
+    /// The message expired before the consumer got to it.
+    Expired,
+    /// The message was discarded at shutdown, see `DropPolicy::Discard`.
+    Discarded,
+}
+
+impl fmt::Display for CarouselError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CarouselError::ConsumerPanicked { consumer, panic } => {
+                write!(f, "consumer {}: {}", consumer, panic)
+            }
+            CarouselError::ConsumeFailed {
+                consumer,
+                attempts,
+                error,
+            } => write!(
+                f,
+                "consumer {} failed to consume a message after {} attempts: {}",
+                consumer, attempts, error
+            ),
+            CarouselError::TimedOut { consumer, timeout } => {
+                write!(f, "consumer {} took longer than {:?}", consumer, timeout)
+            }
+            CarouselError::Dropped {
+                consumer,
+                reason,
+                count,
+            } => {
+                write!(f, "{} messages dropped ({:?})", count, reason)?;
+                match consumer {
+                    Some(consumer) => write!(f, " for consumer {}", consumer),
+                    None => Ok(()),
+                }
+            }
+            CarouselError::ChannelClosed => write!(f, "message put after the carousel stopped"),
+            CarouselError::WalFailed { error } => write!(f, "write-ahead log failed: {}", error),
+        }
+    }
+}
+
+impl error::Error for CarouselError {}
+
+/// `Listeners` are the receivers of `Carousel::errors`.
+#[derive(Default)]
+pub(crate) struct Listeners {
+    senders: sync::Mutex<Vec<mpsc::Sender<CarouselError>>>,
+}
+
+impl Listeners {
+    pub(crate) fn listen(&self) -> mpsc::Receiver<CarouselError> {
+        let (tx, rx) = mpsc::channel();
+        self.senders.lock().unwrap().push(tx);
+        rx
+    }
+
+    /// Sends `error` to every receiver, forgetting the dropped ones.
+    pub(crate) fn emit(&self, error: CarouselError) {
+        let mut senders = self.senders.lock().unwrap();
+        senders.retain(|tx| tx.send(error.clone()).is_ok());
+    }
+}
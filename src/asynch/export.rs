@@ -0,0 +1,97 @@
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+/// The first bytes of an export, followed by the version of its format.
+const MAGIC: &[u8; 4] = b"MP2C";
+const VERSION: u8 = 1;
+
+/// `Pending` is a message exported from, or imported into, the queue of a
+/// consumer, see `Carousel::export_pending`.
+pub(crate) struct Pending {
+    pub(crate) consumer: u32,
+    pub(crate) namespace: Option<String>,
+    pub(crate) topic: Option<String>,
+    pub(crate) data: Vec<u8>,
+}
+
+pub(crate) fn write_header<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION])
+}
+
+pub(crate) fn write<W: Write>(writer: &mut W, pending: &Pending) -> io::Result<()> {
+    writer.write_all(&pending.consumer.to_le_bytes())?;
+    write_name(writer, pending.namespace.as_deref())?;
+    write_name(writer, pending.topic.as_deref())?;
+    writer.write_all(&(pending.data.len() as u32).to_le_bytes())?;
+    writer.write_all(&pending.data)
+}
+
+/// Writes `name` with its length, or `u16::MAX` without a name.
+fn write_name<W: Write>(writer: &mut W, name: Option<&str>) -> io::Result<()> {
+    match name {
+        Some(name) => {
+            let len: u16 = name
+                .len()
+                .try_into()
+                .ok()
+                .filter(|&len| len != u16::MAX)
+                .ok_or_else(|| invalid("name too long to export"))?;
+            writer.write_all(&len.to_le_bytes())?;
+            writer.write_all(name.as_bytes())
+        }
+        None => writer.write_all(&u16::MAX.to_le_bytes()),
+    }
+}
+
+pub(crate) fn read_header<R: Read>(reader: &mut R) -> io::Result<()> {
+    let mut header = [0; 5];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != MAGIC {
+        return Err(invalid("not an export of a carousel"));
+    }
+    if header[4] != VERSION {
+        return Err(invalid(&format!("unknown export version {}", header[4])));
+    }
+    Ok(())
+}
+
+/// Reads the next message, or returns `None` at the end of the export.
+pub(crate) fn read<R: Read>(reader: &mut R) -> io::Result<Option<Pending>> {
+    let mut consumer = [0; 4];
+    match reader.read_exact(&mut consumer) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let namespace = read_name(reader)?;
+    let topic = read_name(reader)?;
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let mut data = vec![0; u32::from_le_bytes(len) as usize];
+    reader.read_exact(&mut data)?;
+    Ok(Some(Pending {
+        consumer: u32::from_le_bytes(consumer),
+        namespace,
+        topic,
+        data,
+    }))
+}
+
+fn read_name<R: Read>(reader: &mut R) -> io::Result<Option<String>> {
+    let mut len = [0; 2];
+    reader.read_exact(&mut len)?;
+    let len = u16::from_le_bytes(len);
+    if len == u16::MAX {
+        return Ok(None);
+    }
+    let mut name = vec![0; len as usize];
+    reader.read_exact(&mut name)?;
+    String::from_utf8(name)
+        .map(Some)
+        .map_err(|_| invalid("name is not UTF-8"))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,105 @@
+use crate::asynch::Clock;
+use std::error;
+use std::fmt;
+use std::time::Duration;
+
+/// `Faults` configures the faults injected into the deliveries of a
+/// `Carousel` for chaos testing, see `Builder::faults`. Each rate is the
+/// share of deliveries, from `0.0` to `1.0`, that suffer the fault.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::Faults;
+/// use std::time::Duration;
+///
+/// let faults = Faults {
+///   seed: 7,
+///   delay_rate: 0.1,
+///   max_delay: Duration::from_millis(5),
+///   drop_rate: 0.01,
+///   ..Faults::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Faults {
+    /// The seed of the random draws. The same seed injects the same faults
+    /// into the same sequence of messages of a consumer.
+    pub seed: u64,
+    /// The share of deliveries delayed by up to `max_delay`.
+    pub delay_rate: f64,
+    pub max_delay: Duration,
+    /// The share of deliveries silently lost.
+    pub drop_rate: f64,
+    /// The share of deliveries handed to the consumer twice.
+    pub duplicate_rate: f64,
+    /// The share of deliveries failing as if the consumer panicked. They are
+    /// routed to the error handler with an `InjectedPanic` error.
+    pub panic_rate: f64,
+}
+
+/// `InjectedPanic` is the error of a delivery that failed by a simulated
+/// consumer panic, see `Faults::panic_rate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InjectedPanic;
+
+impl fmt::Display for InjectedPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "injected consumer panic")
+    }
+}
+
+impl error::Error for InjectedPanic {}
+
+/// `Fault` is what happens to a single delivery.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Fault {
+    None,
+    Drop,
+    Duplicate,
+    Panic,
+}
+
+/// `Injector` draws the faults of the deliveries to one consumer.
+pub(crate) struct Injector {
+    faults: Faults,
+    state: u64,
+}
+
+impl Injector {
+    /// Returns the injector of the consumer `consumer`. The rates of `faults`
+    /// were checked by `Builder::faults`.
+    pub(crate) fn new(faults: Faults, consumer: usize) -> Injector {
+        Injector {
+            faults,
+            state: faults.seed ^ (consumer as u64).wrapping_mul(0xa076_1d64_78bd_642f),
+        }
+    }
+
+    /// Returns a random number in `[0, 1)`, see SplitMix64.
+    fn draw(&mut self) -> f64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        (z ^ (z >> 31)) as f64 / (u64::MAX as f64 + 1.0)
+    }
+
+    /// Draws the fault of the next delivery, first waiting on `clock` if the
+    /// delivery is delayed.
+    pub(crate) fn inject(&mut self, clock: &dyn Clock) -> Fault {
+        let faults = self.faults;
+        if self.draw() < faults.delay_rate {
+            let delay = faults.max_delay.mul_f64(self.draw());
+            clock.sleep(delay);
+        }
+        if self.draw() < faults.drop_rate {
+            Fault::Drop
+        } else if self.draw() < faults.panic_rate {
+            Fault::Panic
+        } else if self.draw() < faults.duplicate_rate {
+            Fault::Duplicate
+        } else {
+            Fault::None
+        }
+    }
+}
@@ -0,0 +1,386 @@
+use crate::asynch::{
+    Carousel, CarouselStats, ConsumerStats, Latency, NamespaceStats, ProducerStats, Rate,
+    Throughput,
+};
+use std::fmt::Write;
+use std::io::{self, BufRead, BufReader};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// `StatusServer` is a tiny HTTP server reporting on a running `Carousel`,
+/// started with `Carousel::serve_status` and available with the `http`
+/// feature. It answers `GET` requests for
+///
+/// - `/stats` with the `CarouselStats` as JSON,
+/// - `/health` with `ok`,
+/// - `/metrics` with the statistics in the Prometheus text format.
+///
+/// Requests are served one at a time. Dropping the server stops it.
+pub struct StatusServer {
+    addr: SocketAddr,
+    stop: sync::Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl StatusServer {
+    pub(crate) fn start(carousel: Carousel, listener: TcpListener) -> io::Result<StatusServer> {
+        let addr = listener.local_addr()?;
+        let stop = sync::Arc::new(AtomicBool::new(false));
+        let stopped = sync::Arc::clone(&stop);
+        let shared = sync::Arc::clone(&carousel.shared);
+        let thread = shared.spawn(String::from("status"), move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                let served = stream.and_then(|stream| serve(&carousel, stream));
+                if let Err(e) = served {
+                    println!("Status server error: {}", e);
+                }
+            }
+        });
+
+        Ok(StatusServer {
+            addr,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    /// Returns the address the server listens on, e.g. to find the port
+    /// picked for port `0`.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+}
+
+impl Drop for StatusServer {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        // Wakes the server up from waiting for a connection.
+        let _ = TcpStream::connect(self.addr);
+        if let Some(thread) = self.thread.take() {
+            crate::asynch::join(thread, "status");
+        }
+    }
+}
+
+fn serve(carousel: &Carousel, stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // The headers don't matter, but are read so the client sees the answer.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/stats")) => ("200 OK", "application/json", json(&carousel.stats())),
+        (Some("GET"), Some("/health")) => ("200 OK", "text/plain", String::from("ok\n")),
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            prometheus(&carousel.stats()),
+        ),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", String::from("not found\n")),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            String::from("method not allowed\n"),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    io::Write::write_all(&mut &stream, response.as_bytes())
+}
+
+/// Returns `s` as a JSON string.
+fn string(s: &str) -> String {
+    let mut json = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            c if (c as u32) < 0x20 => write!(json, "\\u{:04x}", c as u32).unwrap(),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn throughput(throughput: &Throughput) -> String {
+    let rate = |rate: &Rate| {
+        format!(
+            "{{\"messages_per_second\":{},\"bytes_per_second\":{}}}",
+            rate.messages_per_second, rate.bytes_per_second
+        )
+    };
+    format!(
+        "{{\"last_second\":{},\"last_minute\":{}}}",
+        rate(&throughput.last_second),
+        rate(&throughput.last_minute)
+    )
+}
+
+fn latency(latency: &Latency) -> String {
+    format!(
+        "{{\"p50_us\":{},\"p95_us\":{},\"p99_us\":{}}}",
+        latency.p50.as_micros(),
+        latency.p95.as_micros(),
+        latency.p99.as_micros()
+    )
+}
+
+/// Returns `stats` as JSON, with latencies in microseconds.
+fn json(stats: &CarouselStats) -> String {
+    let consumers: Vec<String> = stats
+        .consumers
+        .iter()
+        .map(|c| {
+            format!(
+                "{{\"id\":{},\"depth\":{},\"consumed\":{},\"expired\":{},\"corrupted\":{},\
+                 \"throughput\":{},\"latency\":{},\"dormant\":{},\"timed_out\":{},\"panics\":{},\"discarded\":{},\
+                 \"queued_bytes\":{},\"dropped\":{}}}",
+                c.id,
+                c.depth,
+                c.consumed,
+                c.expired,
+                c.corrupted,
+                throughput(&c.throughput),
+                latency(&c.latency),
+                c.dormant,
+                c.timed_out,
+                c.panics,
+                c.discarded,
+                c.queued_bytes,
+                c.dropped
+            )
+        })
+        .collect();
+    let namespaces: Vec<String> = stats
+        .namespaces
+        .iter()
+        .map(|n| {
+            format!(
+                "{{\"name\":{},\"put\":{},\"queued_bytes\":{},\"dropped\":{}}}",
+                string(&n.name),
+                n.put,
+                n.queued_bytes,
+                n.dropped
+            )
+        })
+        .collect();
+    let producers: Vec<String> = stats
+        .producers
+        .iter()
+        .map(|p| {
+            format!(
+                "{{\"name\":{},\"put\":{},\"bytes\":{},\"throttled\":{}}}",
+                string(&p.name),
+                p.put,
+                p.bytes,
+                p.throttled
+            )
+        })
+        .collect();
+    format!(
+        "{{\"consumers\":[{}],\"expired_retained\":{},\"queued_bytes\":{},\"dropped\":{},\
+         \"rejected\":{},\"discarded\":{},\"filtered\":{},\"namespaces\":[{}],\"producers\":[{}],\
+         \"throughput\":{}}}\n",
+        consumers.join(","),
+        stats.expired_retained,
+        stats.queued_bytes,
+        stats.dropped,
+        stats.rejected,
+        stats.discarded,
+        stats.filtered,
+        namespaces.join(","),
+        producers.join(","),
+        throughput(&stats.throughput)
+    )
+}
+
+/// Returns `stats` in the Prometheus text exposition format.
+fn prometheus(stats: &CarouselStats) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, samples: Vec<(String, String)>| {
+        writeln!(text, "# TYPE mp2c_{} {}", name, kind).unwrap();
+        for (labels, value) in samples {
+            writeln!(text, "mp2c_{}{} {}", name, labels, value).unwrap();
+        }
+    };
+
+    let carousel = |value: u64| vec![(String::new(), value.to_string())];
+    metric("put_messages_per_second", "gauge", {
+        carousel(stats.throughput.last_minute.messages_per_second)
+    });
+    metric(
+        "expired_retained_total",
+        "counter",
+        carousel(stats.expired_retained),
+    );
+    metric("queued_bytes", "gauge", carousel(stats.queued_bytes as u64));
+    metric("dropped_total", "counter", carousel(stats.dropped));
+    metric("rejected_total", "counter", carousel(stats.rejected));
+    metric("discarded_total", "counter", carousel(stats.discarded));
+    metric("filtered_total", "counter", carousel(stats.filtered));
+
+    let consumers = |value: &dyn Fn(&ConsumerStats) -> u64| {
+        stats
+            .consumers
+            .iter()
+            .map(|c| (format!("{{consumer=\"{}\"}}", c.id), value(c).to_string()))
+            .collect()
+    };
+    metric("consumer_depth", "gauge", consumers(&|c| c.depth as u64));
+    metric(
+        "consumer_consumed_total",
+        "counter",
+        consumers(&|c| c.consumed),
+    );
+    metric(
+        "consumer_expired_total",
+        "counter",
+        consumers(&|c| c.expired),
+    );
+    metric(
+        "consumer_corrupted_total",
+        "counter",
+        consumers(&|c| c.corrupted),
+    );
+    metric(
+        "consumer_timed_out_total",
+        "counter",
+        consumers(&|c| c.timed_out),
+    );
+    metric("consumer_panics_total", "counter", consumers(&|c| c.panics));
+    metric(
+        "consumer_discarded_total",
+        "counter",
+        consumers(&|c| c.discarded),
+    );
+    metric(
+        "consumer_queued_bytes",
+        "gauge",
+        consumers(&|c| c.queued_bytes as u64),
+    );
+    metric(
+        "consumer_dropped_total",
+        "counter",
+        consumers(&|c| c.dropped),
+    );
+    metric("consumer_messages_per_second", "gauge", {
+        consumers(&|c| c.throughput.last_minute.messages_per_second)
+    });
+    let quantiles = stats
+        .consumers
+        .iter()
+        .flat_map(|c| {
+            let latency = c.latency;
+            vec![
+                ("0.5", latency.p50),
+                ("0.95", latency.p95),
+                ("0.99", latency.p99),
+            ]
+            .into_iter()
+            .map(move |(quantile, latency)| {
+                (
+                    format!("{{consumer=\"{}\",quantile=\"{}\"}}", c.id, quantile),
+                    latency.as_secs_f64().to_string(),
+                )
+            })
+        })
+        .collect();
+    metric("consumer_latency_seconds", "gauge", quantiles);
+
+    let namespaces = |value: &dyn Fn(&NamespaceStats) -> u64| {
+        stats
+            .namespaces
+            .iter()
+            .map(|n| {
+                (
+                    format!("{{namespace={}}}", string(&n.name)),
+                    value(n).to_string(),
+                )
+            })
+            .collect()
+    };
+    metric("namespace_put_total", "counter", namespaces(&|n| n.put));
+    metric(
+        "namespace_dropped_total",
+        "counter",
+        namespaces(&|n| n.dropped),
+    );
+
+    let producers = |value: &dyn Fn(&ProducerStats) -> u64| {
+        stats
+            .producers
+            .iter()
+            .map(|p| {
+                (
+                    format!("{{producer={}}}", string(&p.name)),
+                    value(p).to_string(),
+                )
+            })
+            .collect()
+    };
+    metric("producer_put_total", "counter", producers(&|p| p.put));
+    metric("producer_bytes_total", "counter", producers(&|p| p.bytes));
+    metric(
+        "producer_throttled_total",
+        "counter",
+        producers(&|p| p.throttled),
+    );
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asynch::Carousel;
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+    use std::sync::mpsc;
+
+    fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn status_endpoints() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder().consumer(tx).build();
+        c.clone_as("ingest").put(vec![1]);
+        rx.recv().unwrap();
+        while c.stats().consumers[0].consumed == 0 {
+            std::thread::yield_now();
+        }
+
+        let server = c.serve_status("127.0.0.1:0").unwrap();
+        let addr = server.local_addr();
+        assert!(get(addr, "/health").ends_with("\r\n\r\nok\n"));
+        let stats = get(addr, "/stats");
+        assert!(stats.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(stats.contains("\"consumers\":[{\"id\":0,\"depth\":0,\"consumed\":1,"));
+        assert!(stats.contains("\"producers\":[{\"name\":\"ingest\",\"put\":1,"));
+        let metrics = get(addr, "/metrics");
+        assert!(metrics.contains("\nmp2c_consumer_consumed_total{consumer=\"0\"} 1\n"));
+        assert!(metrics.contains("\nmp2c_producer_put_total{producer=\"ingest\"} 1\n"));
+        assert!(get(addr, "/nope").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
@@ -0,0 +1,94 @@
+use std::cell::RefCell;
+use std::sync;
+use std::time::{Duration, Instant};
+
+/// `Lineage` is the path of a message through chained carousels, see
+/// `Builder::lineage`. A message put by a consumer while it consumes a
+/// message with a lineage, on a carousel of the same process, extends the
+/// lineage of that message.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{from_fn, Carousel, Ctx, CtxConsumer, Error};
+///
+/// struct Sink;
+///
+/// impl CtxConsumer for Sink {
+///   fn consume_with(&mut self, ctx: &Ctx<'_>, _data: Vec<u8>) -> Result<(), Error> {
+///     for (stage, latency) in ctx.lineage().unwrap().latencies() {
+///       println!("{} took {:?}", stage, latency);
+///     }
+///     Ok(())
+///   }
+/// }
+///
+/// let store = Carousel::builder().lineage("store").consumer(Sink).build();
+/// let forward = store.clone();
+/// let ingest = Carousel::builder()
+///   .lineage("ingest")
+///   .consumer(from_fn(move |data| forward.put(data)))
+///   .build();
+/// ingest.put(String::from("test").into_bytes());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Lineage {
+    stages: Vec<Stage>,
+}
+
+/// `Stage` is a carousel a message passed, with the time it was put on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stage {
+    pub name: sync::Arc<str>,
+    pub put: Instant,
+}
+
+impl Lineage {
+    /// Returns the stages the message passed, the first one first and the
+    /// stage of the `Carousel` it is consumed from last.
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    /// Returns the time the message spent in each stage before it was put on
+    /// the next one, leaving out the last stage.
+    pub fn latencies(&self) -> Vec<(&str, Duration)> {
+        self.stages
+            .windows(2)
+            .map(|pair| {
+                let latency = pair[1].put.saturating_duration_since(pair[0].put);
+                (&*pair[0].name, latency)
+            })
+            .collect()
+    }
+}
+
+thread_local! {
+    /// The lineage of the message consumed on this thread, if any.
+    static CURRENT: RefCell<Option<sync::Arc<Lineage>>> = const { RefCell::new(None) };
+}
+
+/// Returns the lineage of the message consumed on this thread extended by
+/// the stage `name`, or a new lineage starting at `name`.
+pub(crate) fn extend(name: &sync::Arc<str>, put: Instant) -> sync::Arc<Lineage> {
+    let mut lineage = CURRENT.with(|current| match &*current.borrow() {
+        Some(lineage) => Lineage::clone(lineage),
+        None => Lineage::default(),
+    });
+    lineage.stages.push(Stage {
+        name: sync::Arc::clone(name),
+        put,
+    });
+    sync::Arc::new(lineage)
+}
+
+/// Calls `f` with `lineage` as the lineage of the message consumed on this
+/// thread.
+pub(crate) fn scope<R>(lineage: Option<sync::Arc<Lineage>>, f: impl FnOnce() -> R) -> R {
+    if lineage.is_none() {
+        return f();
+    }
+    let outer = CURRENT.with(|current| current.replace(lineage));
+    let result = f();
+    CURRENT.with(|current| current.replace(outer));
+    result
+}
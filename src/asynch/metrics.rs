@@ -0,0 +1,230 @@
+use crate::asynch::{Latency, Rate, Throughput};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The number of seconds a `Window` keeps, the current one included.
+const SECONDS: u64 = 61;
+
+/// `Window` counts messages and their bytes per second over the last minute.
+/// Concurrent updates at the turn of a second may lose a count, which is fine
+/// for statistics.
+pub(crate) struct Window {
+    start: Instant,
+    buckets: Vec<Bucket>,
+}
+
+#[derive(Default)]
+struct Bucket {
+    /// The second counted in this bucket plus one, `0` if it is unused.
+    second: AtomicU64,
+    messages: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Window {
+    pub(crate) fn new(start: Instant) -> Window {
+        Window {
+            start,
+            buckets: (0..SECONDS).map(|_| Bucket::default()).collect(),
+        }
+    }
+
+    /// Counts a message of `bytes` bytes at `now`.
+    pub(crate) fn record(&self, now: Instant, bytes: usize) {
+        self.record_at(self.second(now), bytes);
+    }
+
+    fn second(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.start).as_secs()
+    }
+
+    fn record_at(&self, second: u64, bytes: usize) {
+        let bucket = &self.buckets[(second % SECONDS) as usize];
+        let stamp = bucket.second.load(Ordering::Relaxed);
+        if stamp != second + 1
+            && bucket
+                .second
+                .compare_exchange(stamp, second + 1, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            bucket.messages.store(0, Ordering::Relaxed);
+            bucket.bytes.store(0, Ordering::Relaxed);
+        }
+        bucket.messages.fetch_add(1, Ordering::Relaxed);
+        bucket.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Returns the rates over the last whole second and minute before `now`.
+    pub(crate) fn throughput(&self, now: Instant) -> Throughput {
+        self.throughput_at(self.second(now))
+    }
+
+    fn throughput_at(&self, now: u64) -> Throughput {
+        Throughput {
+            last_second: self.rate(now, 1),
+            last_minute: self.rate(now, 60),
+        }
+    }
+
+    /// Returns the average rate over the `seconds` whole seconds before `now`,
+    /// or over all whole seconds if the window is younger.
+    fn rate(&self, now: u64, seconds: u64) -> Rate {
+        let seconds = seconds.min(now);
+        if seconds == 0 {
+            return Rate::default();
+        }
+        let (messages, bytes) = self
+            .buckets
+            .iter()
+            .filter(|bucket| {
+                let stamp = bucket.second.load(Ordering::Relaxed);
+                stamp > now - seconds && stamp <= now
+            })
+            .fold((0, 0), |(messages, bytes), bucket| {
+                (
+                    messages + bucket.messages.load(Ordering::Relaxed),
+                    bytes + bucket.bytes.load(Ordering::Relaxed),
+                )
+            });
+        Rate {
+            messages_per_second: messages / seconds,
+            bytes_per_second: bytes / seconds,
+        }
+    }
+}
+
+/// The number of buckets per power of two of a `Histogram`, which bounds the
+/// error of a quantile to about 6%.
+const SUB_BUCKETS: u64 = 16;
+
+/// The largest latency a `Histogram` tells apart, in microseconds (about 25
+/// days). Larger latencies are counted as this one.
+const MAX_MICROS: u64 = (1 << 41) - 1;
+
+/// `Histogram` counts latencies in logarithmic buckets of linear sub-buckets,
+/// like an HDR histogram, so that recording is a single atomic increment.
+pub(crate) struct Histogram {
+    counts: Vec<AtomicU64>,
+}
+
+impl Histogram {
+    pub(crate) fn new() -> Histogram {
+        Histogram {
+            counts: (0..=Histogram::index(MAX_MICROS))
+                .map(|_| AtomicU64::new(0))
+                .collect(),
+        }
+    }
+
+    /// Returns the bucket of a latency of `micros` microseconds. Values below
+    /// `2 * SUB_BUCKETS` have a bucket of their own.
+    fn index(micros: u64) -> usize {
+        let micros = micros.min(MAX_MICROS);
+        let shift = (64 - micros.leading_zeros() as u64).saturating_sub(5);
+        (shift * SUB_BUCKETS + (micros >> shift)) as usize
+    }
+
+    /// Returns the largest latency in microseconds counted in bucket `index`.
+    fn highest(index: usize) -> u64 {
+        let index = index as u64;
+        if index < 2 * SUB_BUCKETS {
+            return index;
+        }
+        let shift = index / SUB_BUCKETS - 1;
+        let mantissa = index - shift * SUB_BUCKETS;
+        ((mantissa + 1) << shift) - 1
+    }
+
+    pub(crate) fn record(&self, latency: Duration) {
+        let micros = latency.as_micros().min(MAX_MICROS as u128) as u64;
+        self.counts[Histogram::index(micros)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn latency(&self) -> Latency {
+        let counts: Vec<u64> = self
+            .counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        let quantile = |q: f64| {
+            if total == 0 {
+                return Duration::ZERO;
+            }
+            let rank = ((q * total as f64).ceil() as u64).max(1);
+            let mut seen = 0;
+            let index = counts
+                .iter()
+                .position(|count| {
+                    seen += count;
+                    seen >= rank
+                })
+                .unwrap_or(counts.len() - 1);
+            Duration::from_micros(Histogram::highest(index))
+        };
+
+        Latency {
+            p50: quantile(0.5),
+            p95: quantile(0.95),
+            p99: quantile(0.99),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Histogram, Window, MAX_MICROS};
+    use crate::asynch::Rate;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn histogram_buckets() {
+        for micros in (0..5000).chain([MAX_MICROS]) {
+            let index = Histogram::index(micros);
+            assert!(Histogram::highest(index) >= micros);
+            assert!(index == 0 || Histogram::highest(index - 1) < micros);
+            assert!(Histogram::highest(index) - micros <= micros / 16);
+        }
+
+        let histogram = Histogram::new();
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let latency = histogram.latency();
+        assert_eq!(latency.p50, Duration::from_micros(51));
+        assert_eq!(latency.p95, Duration::from_micros(95));
+        assert_eq!(latency.p99, Duration::from_micros(99));
+    }
+
+    #[test]
+    fn rolling_window() {
+        let window = Window::new(Instant::now());
+        window.record_at(0, 10);
+        window.record_at(1, 10);
+        window.record_at(1, 30);
+        window.record_at(2, 10);
+
+        let throughput = window.throughput_at(2);
+        assert_eq!(
+            throughput.last_second,
+            Rate {
+                messages_per_second: 2,
+                bytes_per_second: 40
+            }
+        );
+        assert_eq!(
+            throughput.last_minute,
+            Rate {
+                messages_per_second: 1,
+                bytes_per_second: 25
+            }
+        );
+
+        // The bucket of second 0 is reused for second 61.
+        window.record_at(61, 600);
+        let throughput = window.throughput_at(62);
+        assert_eq!(throughput.last_second.bytes_per_second, 600);
+        assert_eq!(throughput.last_minute.messages_per_second, 0);
+        assert_eq!(throughput.last_minute.bytes_per_second, (10 + 600) / 60);
+    }
+}
@@ -0,0 +1,214 @@
+use crate::asynch::{wait, WaitStrategy, FORWARD_BATCH};
+use std::marker::PhantomData;
+use std::sync::mpsc;
+use std::thread;
+use std::vec;
+
+/// `Multiplier` is the fan-out of a `Carousel` on its own: a thread that
+/// hands a copy of every message received on its input channel to each of
+/// its output channels, in order, for applications that wire up their own
+/// channel topologies. The last output gets the message itself rather than a
+/// copy. It runs the same loop as the multiplier threads of a `Carousel`.
+///
+/// Outputs whose receiver was dropped are left out from then on. The thread
+/// stops once every sender of the input channel was dropped, or no output is
+/// left.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::Multiplier;
+/// use std::sync::mpsc;
+///
+/// let (tx, input) = mpsc::channel();
+/// let (out1, rx1) = mpsc::channel();
+/// let (out2, rx2) = mpsc::channel();
+/// let multiplier = Multiplier::spawn(input, vec![out1, out2]);
+///
+/// tx.send(String::from("test")).unwrap();
+/// drop(tx);
+/// assert_eq!(multiplier.join(), 1);
+/// assert_eq!(rx1.recv().unwrap(), "test");
+/// assert_eq!(rx2.recv().unwrap(), "test");
+/// ```
+pub struct Multiplier<T> {
+    thread: thread::JoinHandle<u64>,
+    messages: PhantomData<fn(T)>,
+}
+
+impl<T> Multiplier<T>
+where
+    T: Clone + Send + 'static,
+{
+    /// Starts a thread forwarding the messages of `input` to `outputs`.
+    pub fn spawn(input: mpsc::Receiver<T>, outputs: Vec<mpsc::Sender<T>>) -> Multiplier<T> {
+        Multiplier::spawn_with(input, outputs, WaitStrategy::Block)
+    }
+
+    /// Starts a thread forwarding the messages of `input` to `outputs`, which
+    /// waits for messages as directed by `wait`, see `Builder::wait_strategy`.
+    pub fn spawn_with(
+        input: mpsc::Receiver<T>,
+        outputs: Vec<mpsc::Sender<T>>,
+        wait: WaitStrategy,
+    ) -> Multiplier<T> {
+        let mut outputs: Vec<Option<mpsc::Sender<T>>> = outputs.into_iter().map(Some).collect();
+        let thread = thread::Builder::new()
+            .name(String::from("multiplier"))
+            .spawn(move || {
+                let mut forwarded = 0;
+                if !outputs.is_empty() {
+                    run(&input, wait, |messages| {
+                        for message in messages {
+                            forward(&mut outputs, message);
+                            forwarded += 1;
+                        }
+                        !outputs.is_empty()
+                    });
+                }
+                forwarded
+            })
+            .unwrap();
+
+        Multiplier {
+            thread,
+            messages: PhantomData,
+        }
+    }
+
+    /// Waits until the thread stopped and returns the number of messages it
+    /// forwarded.
+    pub fn join(self) -> u64 {
+        self.thread.join().unwrap()
+    }
+}
+
+/// Receives the messages of `input` in batches of up to `FORWARD_BATCH`,
+/// waiting for the first one of each as directed by `wait`, and hands every
+/// batch to `dispatch` until it returns `false` or every sender of `input`
+/// was dropped. The multiplier threads of a `Carousel` run on it too.
+pub(crate) fn run<T, F>(input: &mpsc::Receiver<T>, wait: WaitStrategy, mut dispatch: F)
+where
+    F: FnMut(vec::Drain<'_, T>) -> bool,
+{
+    let mut batch = Vec::with_capacity(FORWARD_BATCH);
+    loop {
+        match wait::recv(input, wait) {
+            Ok(message) => batch.push(message),
+            Err(_) => return,
+        }
+        while batch.len() < FORWARD_BATCH {
+            match input.try_recv() {
+                Ok(message) => batch.push(message),
+                Err(_) => break,
+            }
+        }
+        if !dispatch(batch.drain(..)) {
+            return;
+        }
+    }
+}
+
+/// Hands `message` to each of `outputs` that `accepts` it with `deliver`,
+/// the last one getting `message` itself rather than a copy.
+pub(crate) fn broadcast<I, T, P, F>(outputs: I, message: T, mut accepts: P, mut deliver: F)
+where
+    I: IntoIterator,
+    T: Clone,
+    P: FnMut(&I::Item, &T) -> bool,
+    F: FnMut(I::Item, T),
+{
+    let mut previous = None;
+    for output in outputs {
+        if !accepts(&output, &message) {
+            continue;
+        }
+        if let Some(previous) = previous.replace(output) {
+            deliver(previous, message.clone());
+        }
+    }
+    if let Some(last) = previous {
+        deliver(last, message);
+    }
+}
+
+/// Hands `message` to every output, dropping those that are disconnected.
+fn forward<T: Clone>(outputs: &mut Vec<Option<mpsc::Sender<T>>>, message: T) {
+    let mut disconnected = false;
+    let all = |_: &_, _: &_| true;
+    broadcast(outputs.iter_mut(), message, all, |output, message| {
+        if output.as_ref().is_some_and(|o| o.send(message).is_err()) {
+            *output = None;
+            disconnected = true;
+        }
+    });
+    if disconnected {
+        outputs.retain(Option::is_some);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::broadcast;
+    use crate::asynch::{Multiplier, WaitStrategy};
+    use std::cell::Cell;
+    use std::sync::mpsc;
+
+    #[test]
+    fn multiplier() {
+        let (tx, input) = mpsc::channel();
+        let (out1, rx1) = mpsc::channel();
+        let (out2, rx2) = mpsc::channel();
+        let (out3, rx3) = mpsc::channel();
+        let multiplier = Multiplier::spawn_with(
+            input,
+            vec![out1, out2, out3],
+            WaitStrategy::SpinThenPark { spins: 10 },
+        );
+
+        tx.send(1).unwrap();
+        assert_eq!(rx2.recv().unwrap(), 1);
+        // The second output is left out once its receiver is gone.
+        drop(rx2);
+        for i in 2..5 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+
+        assert_eq!(multiplier.join(), 4);
+        assert_eq!(rx1.iter().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+        assert_eq!(rx3.iter().collect::<Vec<_>>(), vec![1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn stops_without_outputs() {
+        let (tx, input) = mpsc::channel();
+        let (out, rx) = mpsc::channel();
+        let multiplier = Multiplier::spawn(input, vec![out]);
+        drop(rx);
+
+        tx.send(()).unwrap();
+        assert_eq!(multiplier.join(), 1);
+    }
+
+    #[test]
+    fn broadcast_copies_for_all_but_the_last() {
+        struct Counted<'a>(&'a Cell<usize>);
+
+        impl Clone for Counted<'_> {
+            fn clone(&self) -> Self {
+                self.0.set(self.0.get() + 1);
+                Counted(self.0)
+            }
+        }
+
+        let clones = Cell::new(0);
+        let mut delivered = Vec::new();
+        let odd = |output: &usize, _: &Counted<'_>| output % 2 == 1;
+        broadcast(0..6, Counted(&clones), odd, |output, _| {
+            delivered.push(output)
+        });
+
+        assert_eq!(delivered, vec![1, 3, 5]);
+        assert_eq!(clones.get(), 2);
+    }
+}
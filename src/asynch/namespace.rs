@@ -0,0 +1,148 @@
+use crate::asynch::budget::Budget;
+use crate::asynch::{
+    Carousel, ConsumerStats, CtxConsumer, Envelope, NamespaceStats, Selector, SubscriptionId,
+    TopicPattern,
+};
+use std::sync;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// `Tenant` is the state shared by all handles to a namespace and by the
+/// messages put into it.
+pub(super) struct Tenant {
+    pub(super) name: sync::Arc<str>,
+    pub(super) quota: Option<sync::Arc<Budget>>,
+    pub(super) put: AtomicU64,
+}
+
+impl Tenant {
+    pub(super) fn new(name: &str, quota: Option<Budget>) -> Tenant {
+        Tenant {
+            name: sync::Arc::from(name),
+            quota: quota.map(sync::Arc::new),
+            put: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the statistics of the namespace without its consumers.
+    pub(super) fn stats(&self) -> NamespaceStats {
+        NamespaceStats {
+            name: String::from(&*self.name),
+            consumers: Vec::new(),
+            put: self.put.load(Ordering::SeqCst),
+            queued_bytes: self.quota.as_ref().map_or(0, |q| q.used()),
+            dropped: self.quota.as_ref().map_or(0, |q| q.dropped()),
+        }
+    }
+}
+
+/// `Namespace` is a handle to a namespace of a `Carousel`, returned by
+/// `Carousel::namespace`.
+///
+/// Messages put into a namespace are only sent to consumers subscribed within
+/// the same namespace, and consumers subscribed within a namespace only
+/// receive messages put into it, so that several independent components of
+/// an application can share one `Carousel` without seeing each other's
+/// messages. Topics, retained messages and memory quotas are kept per
+/// namespace, see `Builder::namespace_quota`.
+///
+/// Derived messages put by a consumer through its `Ctx` stay in the
+/// namespace of the consumer.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{from_fn, Carousel};
+///
+/// let c = Carousel::builder().consumer(from_fn(|_| {})).build();
+///
+/// let billing = c.namespace("billing");
+/// billing.subscribe_all(from_fn(|data| println!("{:?}", data)));
+/// billing.put(String::from("invoice").into_bytes());
+/// ```
+#[derive(Clone)]
+pub struct Namespace {
+    pub(super) carousel: Carousel,
+    pub(super) tenant: sync::Arc<Tenant>,
+}
+
+impl Namespace {
+    /// Returns the name of the namespace.
+    pub fn name(&self) -> &str {
+        &self.tenant.name
+    }
+
+    /// Puts a message into the namespace, see `Carousel::put`.
+    pub fn put(&self, data: Vec<u8>) {
+        self.send(self.carousel.envelope(data));
+    }
+
+    /// Puts a message on `topic` within the namespace, see `Carousel::put_to`.
+    pub fn put_to(&self, topic: &str, data: Vec<u8>) {
+        let envelope = Envelope {
+            topic: Some(sync::Arc::from(topic)),
+            ..self.carousel.envelope(data)
+        };
+        self.send(envelope);
+    }
+
+    fn send(&self, envelope: Envelope) {
+        self.tenant.put.fetch_add(1, Ordering::SeqCst);
+        let envelope = Envelope {
+            namespace: Some(sync::Arc::clone(&self.tenant)),
+            ..envelope
+        };
+        self.carousel.send(envelope);
+    }
+
+    /// Subscribes `consumer` to every message put into the namespace and
+    /// returns the id identifying the consumer.
+    pub fn subscribe_all<T>(&self, consumer: T) -> SubscriptionId
+    where
+        T: CtxConsumer + Send + 'static,
+    {
+        self.carousel
+            .subscription(Some(&self.tenant), None, consumer)
+    }
+
+    /// Subscribes `consumer` to `topic` within the namespace, see
+    /// `Carousel::subscribe`.
+    pub fn subscribe<T>(&self, topic: &str, consumer: T) -> SubscriptionId
+    where
+        T: CtxConsumer + Send + 'static,
+    {
+        let topic = Selector::Topic(String::from(topic));
+        self.carousel
+            .subscription(Some(&self.tenant), Some(topic), consumer)
+    }
+
+    /// Subscribes `consumer` to all topics matching `pattern` within the
+    /// namespace, see `Carousel::subscribe_pattern`.
+    pub fn subscribe_pattern<T>(&self, pattern: TopicPattern, consumer: T) -> SubscriptionId
+    where
+        T: CtxConsumer + Send + 'static,
+    {
+        let topic = Selector::Pattern(pattern);
+        self.carousel
+            .subscription(Some(&self.tenant), Some(topic), consumer)
+    }
+
+    /// Returns a snapshot of the statistics of the namespace.
+    pub fn stats(&self) -> NamespaceStats {
+        let now = self.carousel.shared.clock.now();
+        let mut consumers: Vec<ConsumerStats> = self
+            .carousel
+            .shared
+            .routes
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|route| route.namespace() == Some(&*self.tenant.name))
+            .map(|route| route.slot.stats(now))
+            .collect();
+        consumers.sort_by_key(|stats| stats.id);
+
+        NamespaceStats {
+            consumers,
+            ..self.tenant.stats()
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+
+/// `TopicPattern` matches hierarchical topics made of `.` separated segments,
+/// such as `logs.eu.error`. In a pattern `*` matches any single segment, and
+/// `#` as the last segment matches any number of remaining segments,
+/// including none. `logs.*.error` matches `logs.eu.error` but not
+/// `logs.error`, and `logs.#` matches `logs`, `logs.eu` and `logs.eu.error`.
+///
+/// Patterns are parsed once, when a consumer subscribes with them, and
+/// matched against the topic of each message by the multiplier.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopicPattern {
+    segments: Vec<Segment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Exact(String),
+    One,
+    Rest,
+}
+
+impl TopicPattern {
+    /// Parses `pattern`, failing if `#` is used other than as the last segment.
+    pub fn new(pattern: &str) -> Result<TopicPattern, PatternError> {
+        let segments: Vec<Segment> = pattern
+            .split('.')
+            .map(|segment| match segment {
+                "*" => Segment::One,
+                "#" => Segment::Rest,
+                _ => Segment::Exact(String::from(segment)),
+            })
+            .collect();
+        if let Some(i) = segments.iter().position(|s| *s == Segment::Rest) {
+            if i + 1 != segments.len() {
+                return Err(PatternError {
+                    pattern: String::from(pattern),
+                });
+            }
+        }
+        Ok(TopicPattern { segments })
+    }
+
+    /// Returns `true` if `topic` matches the pattern.
+    pub fn matches(&self, topic: &str) -> bool {
+        let mut topic = topic.split('.');
+        for segment in &self.segments {
+            match (segment, topic.next()) {
+                (Segment::Rest, _) => return true,
+                (Segment::One, Some(_)) => {}
+                (Segment::Exact(exact), Some(next)) if exact == next => {}
+                _ => return false,
+            }
+        }
+        topic.next().is_none()
+    }
+}
+
+impl fmt::Display for TopicPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            match segment {
+                Segment::Exact(segment) => f.write_str(segment)?,
+                Segment::One => f.write_str("*")?,
+                Segment::Rest => f.write_str("#")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for TopicPattern {
+    type Err = PatternError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        TopicPattern::new(pattern)
+    }
+}
+
+/// `PatternError` reports a topic pattern that can't be parsed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PatternError {
+    pattern: String,
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "`#` must be the last segment of pattern {:?}",
+            self.pattern
+        )
+    }
+}
+
+impl error::Error for PatternError {}
+
+#[cfg(test)]
+mod tests {
+    use super::TopicPattern;
+
+    #[test]
+    fn wildcards() {
+        let one = TopicPattern::new("logs.*.error").unwrap();
+        assert!(one.matches("logs.eu.error"));
+        assert!(!one.matches("logs.error"));
+        assert!(!one.matches("logs.eu.west.error"));
+
+        let rest = TopicPattern::new("logs.#").unwrap();
+        assert!(rest.matches("logs"));
+        assert!(rest.matches("logs.eu.error"));
+        assert!(!rest.matches("metrics.eu"));
+
+        assert!(TopicPattern::new("logs.#.error").is_err());
+    }
+}
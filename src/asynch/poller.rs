@@ -0,0 +1,299 @@
+use crate::asynch::{wait, ConsumerPanic, Error, TryConsumer, WaitStrategy};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{self, mpsc};
+use std::thread;
+
+/// `PollerOptions` configures a `Poller`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PollerOptions {
+    /// How often a failed message is retried before it is given up on, see
+    /// `Builder::retries`.
+    pub retries: usize,
+    /// Whether a consumer that panics carries on with the next message rather
+    /// than ending the poller thread, see `Builder::panic_policy`.
+    pub catch_panics: bool,
+    /// How the poller waits for messages, see `Builder::wait_strategy`.
+    pub wait: WaitStrategy,
+}
+
+/// `PollerStats` counts what became of the messages of a `Poller`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PollerStats {
+    /// The messages consumed successfully.
+    pub consumed: u64,
+    /// The messages given up on after all retries.
+    pub failed: u64,
+    /// The calls to the consumer that panicked.
+    pub panics: u64,
+}
+
+#[derive(Default)]
+struct Counters {
+    consumed: AtomicU64,
+    failed: AtomicU64,
+    panics: AtomicU64,
+}
+
+impl Counters {
+    fn stats(&self) -> PollerStats {
+        PollerStats {
+            consumed: self.consumed.load(Ordering::SeqCst),
+            failed: self.failed.load(Ordering::SeqCst),
+            panics: self.panics.load(Ordering::SeqCst),
+        }
+    }
+}
+
+/// `Poller` is a consumer thread of a `Carousel` on its own: it hands every
+/// message received on a channel to a consumer, with the retries and panic
+/// handling of a `Carousel`, for applications that route messages to their
+/// own queues, e.g. from a `Multiplier` or a channel shared by several
+/// pollers.
+///
+/// The poller consumes every message sent before the last sender of its
+/// channel is dropped, then stops. Failed messages are printed.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{from_fn, Poller, PollerOptions};
+/// use std::sync::mpsc;
+///
+/// let (tx, rx) = mpsc::channel();
+/// let poller = Poller::spawn(
+///   from_fn(|data| println!("{:?}", data)),
+///   rx,
+///   PollerOptions { retries: 2, catch_panics: true, ..PollerOptions::default() },
+/// );
+/// tx.send(String::from("test").into_bytes()).unwrap();
+/// drop(tx);
+/// assert_eq!(poller.join().consumed, 1);
+/// ```
+pub struct Poller {
+    thread: thread::JoinHandle<()>,
+    counters: sync::Arc<Counters>,
+}
+
+impl Poller {
+    /// Starts a thread handing the messages of `receiver` to `consumer` as
+    /// configured by `options`.
+    pub fn spawn<T>(
+        consumer: T,
+        receiver: mpsc::Receiver<Vec<u8>>,
+        options: PollerOptions,
+    ) -> Poller
+    where
+        T: TryConsumer + Send + 'static,
+    {
+        let counters = sync::Arc::new(Counters::default());
+        let counted = sync::Arc::clone(&counters);
+        let thread = thread::Builder::new()
+            .name(String::from("poller"))
+            .spawn(move || {
+                let mut consumer = consumer;
+                while let Ok(data) = wait::recv(&receiver, options.wait) {
+                    consume(&mut consumer, data, &options, &counted);
+                }
+            })
+            .unwrap();
+
+        Poller { thread, counters }
+    }
+
+    /// Returns the statistics of the poller so far.
+    pub fn stats(&self) -> PollerStats {
+        self.counters.stats()
+    }
+
+    /// Waits until the poller stopped and returns its statistics. A poller
+    /// whose consumer panicked without `PollerOptions::catch_panics` stopped
+    /// at the panic.
+    pub fn join(self) -> PollerStats {
+        if self.thread.join().is_err() {
+            println!("A poller thread panicked.");
+        }
+        self.counters.stats()
+    }
+}
+
+/// Hands `data` to `consumer`, retrying failed attempts as configured.
+fn consume<T: TryConsumer>(
+    consumer: &mut T,
+    data: Vec<u8>,
+    options: &PollerOptions,
+    counters: &Counters,
+) {
+    let outcome = attempts(data, options.retries, false, |_, data| {
+        let result = match options.catch_panics {
+            true => catch(|| consumer.try_consume(data)).unwrap_or_else(|e| Err(Box::new(e))),
+            false => consumer.try_consume(data),
+        };
+        if panic_of(&result).is_some() {
+            counters.panics.fetch_add(1, Ordering::SeqCst);
+        }
+        match result {
+            Ok(()) => Attempt::Settled(true),
+            Err(e) => Attempt::Failed(Some(e)),
+        }
+    });
+    match outcome {
+        Ok(_) => {
+            counters.consumed.fetch_add(1, Ordering::SeqCst);
+        }
+        Err(failure) => {
+            counters.failed.fetch_add(1, Ordering::SeqCst);
+            println!("Poller failed to consume a message: {}", failure.error);
+        }
+    }
+}
+
+/// `Attempt` is the outcome of one call to a consumer, see `attempts`.
+pub(crate) enum Attempt {
+    /// The message is settled, consumed successfully or not, and isn't
+    /// retried.
+    Settled(bool),
+    /// The call failed and the message is retried while retries are left.
+    /// Without an error the failure was reported already.
+    Failed(Option<Error>),
+}
+
+/// `Failure` is a message given up on by `attempts`.
+pub(crate) struct Failure {
+    pub(crate) attempts: usize,
+    pub(crate) error: Error,
+    /// The message, if it was kept.
+    pub(crate) data: Option<Vec<u8>>,
+}
+
+/// Hands copies of `data` to `attempt`, along with the number of the attempt,
+/// until it settles the message or `retries` retries failed as well. The last
+/// attempt gets `data` itself unless it is kept for the failure. Returns
+/// whether the message was consumed, or the failure of the last attempt. Both
+/// the `Poller` and the pollers of a `Carousel` consume this way.
+pub(crate) fn attempts<F>(
+    data: Vec<u8>,
+    retries: usize,
+    keep: bool,
+    mut attempt: F,
+) -> Result<bool, Failure>
+where
+    F: FnMut(usize, Vec<u8>) -> Attempt,
+{
+    let mut data = Some(data);
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let copy = match keep || attempts <= retries {
+            true => data.clone(),
+            false => data.take(),
+        };
+        match attempt(attempts, copy.unwrap()) {
+            Attempt::Settled(consumed) => return Ok(consumed),
+            Attempt::Failed(_) if attempts <= retries => continue,
+            Attempt::Failed(None) => return Ok(false),
+            Attempt::Failed(Some(error)) => {
+                return Err(Failure {
+                    attempts,
+                    error,
+                    data,
+                })
+            }
+        }
+    }
+}
+
+/// Makes the call `call` to a consumer, returning the panic of a call that
+/// panicked instead of unwinding.
+pub(crate) fn catch<F>(call: F) -> Result<Result<(), Error>, ConsumerPanic>
+where
+    F: FnOnce() -> Result<(), Error>,
+{
+    panic::catch_unwind(AssertUnwindSafe(call))
+        .map_err(|payload| ConsumerPanic::new(payload.as_ref()))
+}
+
+/// Returns the panic a call to a consumer failed with, whether it was caught
+/// by `catch` or by the consumer itself, e.g. a supervised one.
+pub(crate) fn panic_of(result: &Result<(), Error>) -> Option<&ConsumerPanic> {
+    result.as_ref().err()?.downcast_ref::<ConsumerPanic>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{attempts, Attempt};
+    use crate::asynch::{Error, Poller, PollerOptions, PollerStats, TryConsumer};
+    use std::sync::mpsc;
+
+    #[test]
+    fn poller() {
+        // Fails the first attempt at every message, and panics on empty ones.
+        struct Flaky(bool, mpsc::Sender<Vec<u8>>);
+
+        impl TryConsumer for Flaky {
+            fn try_consume(&mut self, data: Vec<u8>) -> Result<(), Error> {
+                assert!(!data.is_empty());
+                self.0 = !self.0;
+                match self.0 {
+                    true => Err("flaky".into()),
+                    false => {
+                        self.1.send(data).unwrap();
+                        Ok(())
+                    }
+                }
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let (consumed_tx, consumed_rx) = mpsc::channel();
+        let poller = Poller::spawn(
+            Flaky(false, consumed_tx),
+            rx,
+            PollerOptions {
+                retries: 1,
+                catch_panics: true,
+                ..PollerOptions::default()
+            },
+        );
+        tx.send(vec![1]).unwrap();
+        tx.send(Vec::new()).unwrap();
+        tx.send(vec![2]).unwrap();
+        drop(tx);
+
+        let stats = poller.join();
+        assert_eq!(
+            stats,
+            PollerStats {
+                consumed: 2,
+                failed: 1,
+                panics: 2,
+            }
+        );
+        assert_eq!(
+            consumed_rx.iter().collect::<Vec<_>>(),
+            vec![vec![1], vec![2]]
+        );
+    }
+
+    #[test]
+    fn attempts_keep_the_message_of_a_failure() {
+        let failed = |_, _| Attempt::Failed(Some("failed".into()));
+        let failure = attempts(vec![1], 2, true, failed).err().unwrap();
+        assert_eq!(failure.attempts, 3);
+        assert_eq!(failure.data, Some(vec![1]));
+        assert!(attempts(vec![1], 0, false, failed)
+            .err()
+            .unwrap()
+            .data
+            .is_none());
+
+        // A failure reported already settles the message once retries are
+        // used up.
+        let mut calls = 0;
+        let reported = attempts(vec![1], 1, false, |_, _| {
+            calls += 1;
+            Attempt::Failed(None)
+        });
+        assert!(matches!(reported, Ok(false)));
+        assert_eq!(calls, 2);
+    }
+}
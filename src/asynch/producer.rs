@@ -0,0 +1,91 @@
+use crate::asynch::{Clock, ProducerStats};
+use std::sync;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// `ProducerQuota` limits how fast a named producer may put messages, see
+/// `Builder::producer_quota`. A producer over its quota is throttled: `put`
+/// waits until the message fits instead of dropping it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ProducerQuota {
+    /// The messages the producer may put per second, unlimited if `None`.
+    pub messages_per_second: Option<u32>,
+    /// The payload bytes the producer may put per second, unlimited if `None`.
+    pub bytes_per_second: Option<u64>,
+}
+
+impl ProducerQuota {
+    /// Returns how long a message of `bytes` bytes uses up the quota.
+    fn cost(&self, bytes: usize) -> Duration {
+        let messages = self
+            .messages_per_second
+            .map_or(Duration::ZERO, |per_second| {
+                Duration::from_secs(1) / per_second
+            });
+        let bytes = self.bytes_per_second.map_or(Duration::ZERO, |per_second| {
+            Duration::from_secs_f64(bytes as f64 / per_second as f64)
+        });
+        messages.max(bytes)
+    }
+}
+
+/// `Producer` is the state shared by all handles of a named producer, see
+/// `Carousel::clone_as`.
+pub(super) struct Producer {
+    pub(super) name: String,
+    /// The quota and the earliest time the next message fits in it, if the
+    /// producer put a message since the quota was set.
+    pace: sync::Mutex<(ProducerQuota, Option<Instant>)>,
+    put: AtomicU64,
+    bytes: AtomicU64,
+    throttled: AtomicU64,
+}
+
+impl Producer {
+    pub(super) fn new(name: &str, quota: ProducerQuota) -> Producer {
+        assert!(quota.messages_per_second != Some(0) && quota.bytes_per_second != Some(0));
+
+        Producer {
+            name: String::from(name),
+            pace: sync::Mutex::new((quota, None)),
+            put: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+            throttled: AtomicU64::new(0),
+        }
+    }
+
+    pub(super) fn set_quota(&self, quota: ProducerQuota) {
+        assert!(quota.messages_per_second != Some(0) && quota.bytes_per_second != Some(0));
+
+        *self.pace.lock().unwrap() = (quota, None);
+    }
+
+    /// Counts a message of `bytes` bytes, first waiting as long as the quota
+    /// requires on `clock`.
+    pub(super) fn admit(&self, bytes: usize, clock: &dyn Clock) {
+        let wait = {
+            let mut pace = self.pace.lock().unwrap();
+            let (quota, next) = &mut *pace;
+            let now = clock.now();
+            let next = next.get_or_insert(now);
+            let wait = next.saturating_duration_since(now);
+            *next = (*next).max(now) + quota.cost(bytes);
+            wait
+        };
+        if wait > Duration::ZERO {
+            self.throttled.fetch_add(1, Ordering::SeqCst);
+            clock.sleep(wait);
+        }
+        self.put.fetch_add(1, Ordering::SeqCst);
+        self.bytes.fetch_add(bytes as u64, Ordering::SeqCst);
+    }
+
+    pub(super) fn stats(&self) -> ProducerStats {
+        ProducerStats {
+            name: self.name.clone(),
+            put: self.put.load(Ordering::SeqCst),
+            bytes: self.bytes.load(Ordering::SeqCst),
+            throttled: self.throttled.load(Ordering::SeqCst),
+        }
+    }
+}
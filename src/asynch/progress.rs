@@ -0,0 +1,43 @@
+use std::sync;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// `Progress` wakes the threads waiting for the messages of a `Carousel` to
+/// move on, e.g. in `Carousel::quiesce`. It is notified whenever messages
+/// leave a multiplier, a put completes, a queue empties or a depth alert
+/// clears.
+#[derive(Default)]
+pub(crate) struct Progress {
+    /// Counts the notifications, so that none is missed between checking and
+    /// waiting.
+    notified: sync::Mutex<u64>,
+    changed: sync::Condvar,
+    /// The threads waiting, without which notifying is skipped.
+    waiting: AtomicUsize,
+}
+
+impl Progress {
+    pub(crate) fn notify(&self) {
+        if self.waiting.load(Ordering::SeqCst) == 0 {
+            return;
+        }
+        *self.notified.lock().unwrap() += 1;
+        self.changed.notify_all();
+    }
+
+    /// Waits until `done` returns `true`, checking it again after every
+    /// notification.
+    pub(crate) fn wait_until<F: FnMut() -> bool>(&self, mut done: F) {
+        self.waiting.fetch_add(1, Ordering::SeqCst);
+        loop {
+            let seen = *self.notified.lock().unwrap();
+            if done() {
+                break;
+            }
+            let mut notified = self.notified.lock().unwrap();
+            while *notified == seen {
+                notified = self.changed.wait(notified).unwrap();
+            }
+        }
+        self.waiting.fetch_sub(1, Ordering::SeqCst);
+    }
+}
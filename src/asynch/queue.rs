@@ -0,0 +1,194 @@
+use crate::asynch::wait::{Backoff, WaitStrategy};
+use crate::asynch::Event;
+use std::collections::VecDeque;
+use std::sync;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// `Queue` is the unbounded FIFO queue of events waiting for a poller.
+///
+/// Unlike a channel, events can be removed from a `Queue` while they are
+/// waiting, which is what the sweeper does with expired messages. A paused
+/// `Queue` keeps its events until it is resumed.
+pub(crate) struct Queue {
+    events: sync::Mutex<VecDeque<Event>>,
+    ready: sync::Condvar,
+    paused: AtomicBool,
+    /// Set while the poller of the queue has exited, see `Queue::sleep`. Only
+    /// changed with `events` locked.
+    asleep: AtomicBool,
+}
+
+impl Queue {
+    pub(crate) fn new() -> Queue {
+        Queue {
+            events: sync::Mutex::new(VecDeque::new()),
+            ready: sync::Condvar::new(),
+            paused: AtomicBool::new(false),
+            asleep: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::SeqCst);
+        // Taking the lock makes sure a poller sees the flag either before it
+        // waits or when it is woken up.
+        let _events = self.events.lock().unwrap();
+        self.ready.notify_all();
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::SeqCst)
+    }
+
+    fn is_ready(&self, events: &VecDeque<Event>) -> bool {
+        !events.is_empty() && !self.paused.load(Ordering::SeqCst)
+    }
+
+    /// Appends `event` and wakes the poller. Returns `true` if the poller has
+    /// exited and has to be started again.
+    pub(crate) fn push(&self, event: Event) -> bool {
+        let mut events = self.events.lock().unwrap();
+        events.push_back(event);
+        self.ready.notify_one();
+        self.asleep.swap(false, Ordering::SeqCst)
+    }
+
+    /// Appends all `events` under a single lock and wakes the poller once, see
+    /// `Queue::push`.
+    pub(crate) fn push_batch<I>(&self, events: I) -> bool
+    where
+        I: IntoIterator<Item = Event>,
+    {
+        self.events.lock().unwrap().extend(events);
+        self.ready.notify_one();
+        self.asleep.swap(false, Ordering::SeqCst)
+    }
+
+    /// Marks the queue as asleep if it is empty, so that the next push reports
+    /// that its poller has to be started again. Returns `false` if events
+    /// arrived meanwhile and the poller has to carry on.
+    pub(crate) fn sleep(&self) -> bool {
+        let events = self.events.lock().unwrap();
+        let empty = events.is_empty();
+        self.asleep.store(empty, Ordering::SeqCst);
+        empty
+    }
+
+    /// Moves up to `max` of the oldest events into `batch`, waiting for at
+    /// least one as selected by `wait` if the queue is empty or paused. Taking
+    /// several events per call saves locking the queue for every single event.
+    pub(crate) fn pop_batch(&self, batch: &mut VecDeque<Event>, max: usize, wait: WaitStrategy) {
+        self.pop_batch_timeout(batch, max, wait, None);
+    }
+
+    /// Moves events into `batch` like `Queue::pop_batch`, but gives up after
+    /// waiting for `timeout` and returns `false` then.
+    pub(crate) fn pop_batch_timeout(
+        &self,
+        batch: &mut VecDeque<Event>,
+        max: usize,
+        wait: WaitStrategy,
+        timeout: Option<Duration>,
+    ) -> bool {
+        let mut backoff = Backoff::new(wait);
+        let mut events = self.events.lock().unwrap();
+        while !self.is_ready(&events) {
+            drop(events);
+            let spin = backoff.snooze();
+            events = self.events.lock().unwrap();
+            if !spin {
+                break;
+            }
+        }
+        match timeout {
+            Some(timeout) => {
+                let waiting = |events: &mut VecDeque<Event>| !self.is_ready(events);
+                events = self
+                    .ready
+                    .wait_timeout_while(events, timeout, waiting)
+                    .unwrap()
+                    .0;
+                if !self.is_ready(&events) {
+                    return false;
+                }
+            }
+            None => {
+                while !self.is_ready(&events) {
+                    events = self.ready.wait(events).unwrap();
+                }
+            }
+        }
+        let n = max.min(events.len());
+        batch.extend(events.drain(..n));
+        true
+    }
+
+    /// Removes all events for which `keep` returns `false` and returns how
+    /// many events were removed.
+    pub(crate) fn retain<F>(&self, keep: F) -> usize
+    where
+        F: FnMut(&Event) -> bool,
+    {
+        let mut events = self.events.lock().unwrap();
+        let len = events.len();
+        events.retain(keep);
+        len - events.len()
+    }
+}
+
+/// `Inbox` is a poller's end of its `Queue`: the events taken off the queue in
+/// the last batch, plus events put aside while a consumer reads a stream.
+pub(crate) struct Inbox {
+    batch: VecDeque<Event>,
+    deferred: VecDeque<Event>,
+    max: usize,
+    wait: WaitStrategy,
+}
+
+impl Inbox {
+    /// Creates an `Inbox` taking up to `max` events at once off its queue.
+    pub(crate) fn new(max: usize, wait: WaitStrategy) -> Inbox {
+        Inbox {
+            batch: VecDeque::with_capacity(max),
+            deferred: VecDeque::new(),
+            max,
+            wait,
+        }
+    }
+
+    /// Returns the next event, taking events that were put aside first.
+    pub(crate) fn next(&mut self, queue: &Queue) -> Event {
+        match self.deferred.pop_front() {
+            Some(event) => event,
+            None => self.next_fresh(queue),
+        }
+    }
+
+    /// Returns the next event like `Inbox::next`, or `None` if none arrived
+    /// within `timeout`.
+    pub(crate) fn next_within(&mut self, queue: &Queue, timeout: Duration) -> Option<Event> {
+        if let Some(event) = self.deferred.pop_front().or_else(|| self.batch.pop_front()) {
+            return Some(event);
+        }
+        if !queue.pop_batch_timeout(&mut self.batch, self.max, self.wait, Some(timeout)) {
+            return None;
+        }
+        self.batch.pop_front()
+    }
+
+    /// Returns the next event that wasn't put aside, waiting for it if needed.
+    pub(crate) fn next_fresh(&mut self, queue: &Queue) -> Event {
+        loop {
+            if let Some(event) = self.batch.pop_front() {
+                return event;
+            }
+            queue.pop_batch(&mut self.batch, self.max, self.wait);
+        }
+    }
+
+    /// Puts `event` aside to be returned by `next` later.
+    pub(crate) fn defer(&mut self, event: Event) {
+        self.deferred.push_back(event);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::asynch::{Ctx, CtxConsumer, Error};
+use std::sync;
+
+/// `Readiness` is handed to the factory of a consumer registered with
+/// `Builder::warming_consumer`. The consumer receives no messages until it
+/// is ready, either with `Readiness::ready` or once the `Readiness` is
+/// dropped, e.g. when the factory returns without keeping it. Messages put
+/// in the meantime wait in the consumer's queue.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{from_fn, Carousel};
+/// use std::sync::mpsc;
+/// use std::thread;
+///
+/// let (tx, rx) = mpsc::channel();
+/// let c = Carousel::builder()
+///   .warming_consumer(move |readiness| {
+///     // Loads the cache on another thread and gets ready afterwards.
+///     thread::spawn(move || readiness.ready());
+///     from_fn(move |data| tx.send(data).unwrap())
+///   })
+///   .build();
+/// c.put(String::from("early").into_bytes());
+/// drop(c);
+/// assert_eq!(rx.recv().unwrap(), b"early".to_vec());
+/// ```
+pub struct Readiness {
+    state: sync::Arc<State>,
+}
+
+#[derive(Default)]
+struct State {
+    ready: sync::Mutex<bool>,
+    changed: sync::Condvar,
+}
+
+impl Readiness {
+    pub(crate) fn new() -> (Readiness, Gate) {
+        let state = sync::Arc::new(State::default());
+        let gate = Gate {
+            state: Some(sync::Arc::clone(&state)),
+        };
+        (Readiness { state }, gate)
+    }
+
+    /// Lets the consumer receive messages.
+    pub fn ready(self) {}
+}
+
+impl Drop for Readiness {
+    fn drop(&mut self) {
+        *self.state.ready.lock().unwrap() = true;
+        self.state.changed.notify_all();
+    }
+}
+
+/// `Gate` holds back the first message of a warming consumer until it is
+/// ready.
+pub(crate) struct Gate {
+    state: Option<sync::Arc<State>>,
+}
+
+impl Gate {
+    fn wait(&mut self) {
+        if let Some(state) = self.state.take() {
+            let ready = state.ready.lock().unwrap();
+            let _ready = state.changed.wait_while(ready, |ready| !*ready).unwrap();
+        }
+    }
+}
+
+/// `Warming` is a consumer registered with `Builder::warming_consumer`.
+pub(crate) struct Warming<T> {
+    pub(crate) consumer: T,
+    pub(crate) gate: Gate,
+}
+
+impl<T: CtxConsumer> CtxConsumer for Warming<T> {
+    fn consume_with(&mut self, ctx: &Ctx<'_>, data: Vec<u8>) -> Result<(), Error> {
+        self.gate.wait();
+        self.consumer.consume_with(ctx, data)
+    }
+}
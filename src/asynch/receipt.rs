@@ -0,0 +1,103 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// `Receipt` confirms that every consumer is done with a message put with
+/// `Carousel::put_tracked`. It can be waited on, or awaited as a future.
+///
+/// A consumer is done with a message once it consumed it, failed to, or
+/// discarded it because it expired or didn't pass its filter. A message that
+/// was rejected or dropped for lack of memory, or that no consumer receives,
+/// is done right away.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{from_fn, Carousel};
+///
+/// let c = Carousel::builder().consumer(from_fn(|_| {})).build();
+///
+/// let receipt = c.put_tracked(String::from("test").into_bytes());
+/// receipt.wait();
+/// assert!(receipt.is_done());
+/// ```
+#[derive(Clone)]
+pub struct Receipt {
+    state: sync::Arc<State>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Whether the message is done, and the wakers of the tasks awaiting the
+    /// clones of the receipt.
+    done: sync::Mutex<(bool, Vec<Waker>)>,
+    ready: sync::Condvar,
+}
+
+/// `Ticket` travels with every copy of a tracked message. The receipt is done
+/// once the last copy is dropped.
+pub(crate) struct Ticket {
+    state: sync::Arc<State>,
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let mut done = self.state.done.lock().unwrap();
+        done.0 = true;
+        for waker in done.1.drain(..) {
+            waker.wake();
+        }
+        self.state.ready.notify_all();
+    }
+}
+
+impl Receipt {
+    pub(crate) fn new() -> (Receipt, sync::Arc<Ticket>) {
+        let state = sync::Arc::new(State::default());
+        let ticket = Ticket {
+            state: sync::Arc::clone(&state),
+        };
+        (Receipt { state }, sync::Arc::new(ticket))
+    }
+
+    /// Returns `true` if every consumer is done with the message.
+    pub fn is_done(&self) -> bool {
+        self.state.done.lock().unwrap().0
+    }
+
+    /// Blocks until every consumer is done with the message.
+    pub fn wait(&self) {
+        let done = self.state.done.lock().unwrap();
+        let _done = self.state.ready.wait_while(done, |done| !done.0).unwrap();
+    }
+
+    /// Blocks until every consumer is done with the message or `timeout`
+    /// passed. Returns `true` if the consumers are done.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let done = self.state.done.lock().unwrap();
+        let (done, _) = self
+            .state
+            .ready
+            .wait_timeout_while(done, timeout, |done| !done.0)
+            .unwrap();
+        done.0
+    }
+}
+
+impl Future for Receipt {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut done = self.state.done.lock().unwrap();
+        if done.0 {
+            return Poll::Ready(());
+        }
+        // A task polling again replaces its waker, the others keep theirs.
+        match done.1.iter_mut().find(|waker| waker.will_wake(cx.waker())) {
+            Some(waker) => waker.clone_from(cx.waker()),
+            None => done.1.push(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
@@ -0,0 +1,169 @@
+use crate::asynch::{Clock, MessageFilter, Selector, SubscriptionId, TopicPattern};
+use std::sync;
+use std::time::{Duration, Instant};
+
+/// `RoutingTable` is a set of changes to the routing of running consumers,
+/// applied at once by `Carousel::apply_routing`. Every message put before the
+/// table is applied is routed by the old table, every message put after by
+/// the new one. Consumers keep running, and consumers or settings left out of
+/// the table stay as they are.
+#[derive(Default)]
+pub struct RoutingTable {
+    pub(crate) entries: Vec<(SubscriptionId, Entry)>,
+}
+
+#[derive(Default)]
+pub(crate) struct Entry {
+    /// The new topic, where `Some(None)` routes all messages.
+    pub(crate) topic: Option<Option<Selector>>,
+    pub(crate) filter: Option<MessageFilter>,
+    pub(crate) rate_limit: Option<Option<u32>>,
+    /// The new sampling rate, where `Some(None)` forwards all messages.
+    pub(crate) sample: Option<Option<Sample>>,
+}
+
+impl RoutingTable {
+    /// Creates an empty `RoutingTable`.
+    pub fn new() -> RoutingTable {
+        RoutingTable::default()
+    }
+
+    /// Subscribes the consumer `id` to `topic` instead of its current topics.
+    pub fn subscribe(mut self, id: SubscriptionId, topic: &str) -> RoutingTable {
+        self.entry(id).topic = Some(Some(Selector::Topic(String::from(topic))));
+        self
+    }
+
+    /// Subscribes the consumer `id` to all topics matching `pattern` instead
+    /// of its current topics.
+    pub fn subscribe_pattern(mut self, id: SubscriptionId, pattern: TopicPattern) -> RoutingTable {
+        self.entry(id).topic = Some(Some(Selector::Pattern(pattern)));
+        self
+    }
+
+    /// Routes all messages to the consumer `id`, whatever their topic.
+    pub fn subscribe_all(mut self, id: SubscriptionId) -> RoutingTable {
+        self.entry(id).topic = Some(None);
+        self
+    }
+
+    /// Replaces the filter of the consumer `id`, see `Carousel::set_filter`.
+    pub fn filter<F>(mut self, id: SubscriptionId, filter: F) -> RoutingTable
+    where
+        F: FnMut(&[u8]) -> bool + Send + 'static,
+    {
+        self.entry(id).filter = Some(Box::new(filter));
+        self
+    }
+
+    /// Limits the consumer `id` to `per_second` messages a second, or lifts
+    /// its limit if `per_second` is `None`. A rate limited consumer waits
+    /// between messages instead of dropping them, so that its queue fills up
+    /// if more messages arrive.
+    pub fn rate_limit(mut self, id: SubscriptionId, per_second: Option<u32>) -> RoutingTable {
+        assert!(per_second != Some(0));
+
+        self.entry(id).rate_limit = Some(per_second);
+        self
+    }
+
+    /// Forwards only a random share of `rate` of the messages to the consumer
+    /// `id`, see `Builder::sampled_consumer`, or all of them if `rate` is
+    /// `None`.
+    pub fn sample(mut self, id: SubscriptionId, rate: Option<f64>) -> RoutingTable {
+        self.entry(id).sample = Some(rate.map(Sample::new));
+        self
+    }
+
+    fn entry(&mut self, id: SubscriptionId) -> &mut Entry {
+        let i = match self.entries.iter().position(|(entry, _)| *entry == id) {
+            Some(i) => i,
+            None => {
+                self.entries.push((id, Entry::default()));
+                self.entries.len() - 1
+            }
+        };
+        &mut self.entries[i].1
+    }
+}
+
+/// `Sample` selects a random share of the messages forwarded to a consumer.
+#[derive(Clone, Copy)]
+pub(crate) struct Sample {
+    threshold: u64,
+}
+
+impl Sample {
+    /// Creates a `Sample` of `rate` of all messages, between 0 and 1.
+    pub(crate) fn new(rate: f64) -> Sample {
+        assert!(rate > 0.0 && rate <= 1.0);
+
+        Sample {
+            threshold: (rate * u64::MAX as f64) as u64,
+        }
+    }
+
+    /// Returns `true` if the message drawing `draw` is part of the sample.
+    /// Draws are consecutive numbers, mixed into uniformly spread ones by
+    /// SplitMix64.
+    pub(crate) fn admits(&self, draw: u64) -> bool {
+        let mut z = draw.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        z <= self.threshold
+    }
+}
+
+/// `GateChange` changes the `Gate` of a poller.
+pub(crate) enum GateChange {
+    Filter(MessageFilter),
+    RateLimit(Option<u32>),
+}
+
+/// `Gate` holds the filter and rate limit a poller applies to messages before
+/// handing them to its consumer.
+pub(crate) struct Gate {
+    clock: sync::Arc<dyn Clock>,
+    filter: Option<MessageFilter>,
+    /// The time between two messages and the earliest time for the next one.
+    pace: Option<(Duration, Instant)>,
+}
+
+impl Gate {
+    pub(crate) fn new(clock: sync::Arc<dyn Clock>) -> Gate {
+        Gate {
+            clock,
+            filter: None,
+            pace: None,
+        }
+    }
+
+    pub(crate) fn change(&mut self, change: GateChange) {
+        match change {
+            GateChange::Filter(filter) => self.filter = Some(filter),
+            GateChange::RateLimit(per_second) => {
+                let now = self.clock.now();
+                self.pace = per_second.map(|per_second| (Duration::from_secs(1) / per_second, now))
+            }
+        }
+    }
+
+    /// Returns `false` if the filter rejects `data`. Otherwise waits as long
+    /// as the rate limit requires and returns `true`.
+    pub(crate) fn admit(&mut self, data: &[u8]) -> bool {
+        if let Some(filter) = &mut self.filter {
+            if !filter(data) {
+                return false;
+            }
+        }
+        if let Some((interval, next)) = &mut self.pace {
+            let now = self.clock.now();
+            if *next > now {
+                self.clock.sleep(*next - now);
+            }
+            *next = (*next).max(now) + *interval;
+        }
+        true
+    }
+}
@@ -0,0 +1,298 @@
+use crate::asynch::{Error, TryConsumer};
+use std::convert::TryInto;
+use std::error;
+use std::fmt;
+use std::sync;
+
+/// The length of an HMAC-SHA256 tag.
+const TAG: usize = 32;
+
+/// The length of the key id in front of a signed message.
+const KEY_ID: usize = 4;
+
+/// `KeyProvider` supplies the keys messages are signed and verified with, see
+/// `Builder::signer` and `verify`. Keys are identified by an id that travels
+/// with every signed message, so that keys can be rotated while messages
+/// signed with the previous key are still being verified.
+pub trait KeyProvider: Send + Sync {
+    /// Returns the id and the key to sign new messages with.
+    fn signing_key(&self) -> (u32, Vec<u8>);
+
+    /// Returns the key with the id `id`, or `None` if it is unknown or was
+    /// revoked.
+    fn key(&self, id: u32) -> Option<Vec<u8>>;
+}
+
+impl<T: KeyProvider + ?Sized> KeyProvider for sync::Arc<T> {
+    fn signing_key(&self) -> (u32, Vec<u8>) {
+        (**self).signing_key()
+    }
+
+    fn key(&self, id: u32) -> Option<Vec<u8>> {
+        (**self).key(id)
+    }
+}
+
+/// `StaticKey` is a `KeyProvider` with a single key with the id `0`.
+pub struct StaticKey {
+    key: Vec<u8>,
+}
+
+impl StaticKey {
+    /// Creates a provider signing and verifying all messages with `key`.
+    pub fn new<K: Into<Vec<u8>>>(key: K) -> StaticKey {
+        StaticKey { key: key.into() }
+    }
+}
+
+impl KeyProvider for StaticKey {
+    fn signing_key(&self) -> (u32, Vec<u8>) {
+        (0, self.key.clone())
+    }
+
+    fn key(&self, id: u32) -> Option<Vec<u8>> {
+        match id {
+            0 => Some(self.key.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Signs `data` with the signing key of `keys`. A signed message is the
+/// little endian `u32` key id, the payload and its HMAC-SHA256 over the key
+/// id and payload.
+pub(crate) fn sign(keys: &dyn KeyProvider, data: &[u8]) -> Vec<u8> {
+    let (id, key) = keys.signing_key();
+    let mut signed = Vec::with_capacity(KEY_ID + data.len() + TAG);
+    signed.extend_from_slice(&id.to_le_bytes());
+    signed.extend_from_slice(data);
+    let tag = hmac(&key, &signed);
+    signed.extend_from_slice(&tag);
+    signed
+}
+
+/// Checks the signature of a message signed with `sign` and returns its
+/// payload.
+pub(crate) fn open(keys: &dyn KeyProvider, mut signed: Vec<u8>) -> Result<Vec<u8>, SignatureError> {
+    if signed.len() < KEY_ID + TAG {
+        return Err(SignatureError::Malformed);
+    }
+    let id = u32::from_le_bytes(signed[..KEY_ID].try_into().unwrap());
+    let key = keys.key(id).ok_or(SignatureError::UnknownKey(id))?;
+    let tag = signed.split_off(signed.len() - TAG);
+    if !equal(&hmac(&key, &signed), &tag) {
+        return Err(SignatureError::Mismatch);
+    }
+    signed.drain(..KEY_ID);
+    Ok(signed)
+}
+
+/// `SignatureError` reports a message that failed verification, see `verify`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignatureError {
+    /// The message is too short to be signed.
+    Malformed,
+    /// The message was signed with a key the `KeyProvider` doesn't know.
+    UnknownKey(u32),
+    /// The signature doesn't match, so the message was tampered with or
+    /// signed with a different key.
+    Mismatch,
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Malformed => write!(f, "message is not signed"),
+            SignatureError::UnknownKey(id) => {
+                write!(f, "message is signed with unknown key {}", id)
+            }
+            SignatureError::Mismatch => write!(f, "message signature doesn't match"),
+        }
+    }
+}
+
+impl error::Error for SignatureError {}
+
+/// `Verify` is the consumer returned by `verify`.
+pub struct Verify<C> {
+    consumer: C,
+    keys: sync::Arc<dyn KeyProvider>,
+}
+
+/// Creates a consumer that checks the signature of every message before it
+/// hands the payload to `consumer`. A message whose signature doesn't check
+/// out fails with a `SignatureError`, which is routed to the error handler
+/// like any other failure, see `Builder::error_handler`.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{from_fn, verify, Carousel, StaticKey};
+/// use std::sync::Arc;
+///
+/// let keys = Arc::new(StaticKey::new(b"secret".to_vec()));
+///
+/// let c = Carousel::builder()
+///   .consumer(verify(from_fn(|data| assert_eq!(data, b"test")), keys.clone()))
+///   .signer(keys)
+///   .build();
+///
+/// c.put(String::from("test").into_bytes());
+/// ```
+pub fn verify<C, K>(consumer: C, keys: K) -> Verify<C>
+where
+    C: TryConsumer,
+    K: KeyProvider + 'static,
+{
+    Verify {
+        consumer,
+        keys: sync::Arc::new(keys),
+    }
+}
+
+impl<C: TryConsumer> TryConsumer for Verify<C> {
+    fn try_consume(&mut self, data: Vec<u8>) -> Result<(), Error> {
+        let data = open(self.keys.as_ref(), data)?;
+        self.consumer.try_consume(data)
+    }
+}
+
+/// Compares two tags in time independent of where they differ.
+fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Returns the HMAC-SHA256 of `data` under `key`, see RFC 2104.
+fn hmac(key: &[u8], data: &[u8]) -> [u8; TAG] {
+    let mut block = [0u8; 64];
+    if key.len() > block.len() {
+        block[..TAG].copy_from_slice(&sha256(&[key]));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let ipad: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    let opad: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    let inner = sha256(&[&ipad, data]);
+    sha256(&[&opad, &inner])
+}
+
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Returns the SHA-256 of the concatenation of `parts`, see FIPS 180-4.
+fn sha256(parts: &[&[u8]]) -> [u8; 32] {
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+    let len: usize = parts.iter().map(|part| part.len()).sum();
+    let mut message = Vec::with_capacity(len + 72);
+    parts
+        .iter()
+        .for_each(|part| message.extend_from_slice(part));
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((len as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = hh
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    let mut digest = [0u8; 32];
+    for (bytes, h) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&h.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hmac, open, sign, SignatureError, StaticKey};
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn hmac_sha256_test_vectors() {
+        // RFC 4231, test cases 1, 2 and 6.
+        assert_eq!(
+            hex(&hmac(&[0x0b; 20], b"Hi There")),
+            "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"
+        );
+        assert_eq!(
+            hex(&hmac(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&hmac(
+                &[0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First"
+            )),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn tampered_messages_are_rejected() {
+        let keys = StaticKey::new(b"secret".to_vec());
+
+        let signed = sign(&keys, b"payload");
+        assert_eq!(open(&keys, signed.clone()).unwrap(), b"payload".to_vec());
+
+        let mut tampered = signed.clone();
+        tampered[5] ^= 1;
+        assert_eq!(open(&keys, tampered), Err(SignatureError::Mismatch));
+        let other = StaticKey::new(b"other".to_vec());
+        assert_eq!(open(&other, signed), Err(SignatureError::Mismatch));
+        assert_eq!(
+            open(&keys, b"short".to_vec()),
+            Err(SignatureError::Malformed)
+        );
+    }
+}
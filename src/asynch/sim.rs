@@ -0,0 +1,352 @@
+use crate::asynch::{
+    Clock, ConsumeError, ErrorHandler, MockClock, Selector, SubscriptionId, TopicPattern,
+    TryConsumer,
+};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// `SimCarousel` is a single threaded stand-in for a `Carousel` for
+/// deterministic tests, available with the `sim` feature.
+///
+/// Nothing happens on its own: each call to `SimCarousel::step` lets either
+/// the multiplier fan out one message or one poller hand one message to its
+/// consumer, taking turns in a fixed order, and `SimCarousel::run_until_idle`
+/// steps until there is nothing left to do. Time is kept by a `MockClock`
+/// that only moves with `SimCarousel::advance`, so orderings, expiries and
+/// retries play out the same way on every run.
+///
+/// # Example
+/// ```
+/// # #[cfg(feature = "sim")]
+/// # {
+/// use mp2c::asynch::SimCarousel;
+/// use std::time::Duration;
+///
+/// let (tx, rx) = std::sync::mpsc::channel();
+/// let mut c = SimCarousel::new().ttl(Duration::from_secs(1));
+/// c.consumer(tx);
+///
+/// c.put(b"fresh".to_vec());
+/// c.step();
+/// c.advance(Duration::from_secs(1));
+/// c.run_until_idle();
+///
+/// // The message was fanned out in time but expired before it was consumed.
+/// assert!(rx.try_recv().is_err());
+/// # }
+/// ```
+pub struct SimCarousel {
+    clock: MockClock,
+    ttl: Option<Duration>,
+    retries: usize,
+    error_handler: Option<ErrorHandler>,
+    /// The messages put but not yet fanned out by the multiplier.
+    pending: VecDeque<Message>,
+    consumers: Vec<Poller>,
+    /// The party next in turn, `0` for the multiplier and `i + 1` for the
+    /// poller of consumer `i`.
+    turn: usize,
+}
+
+#[derive(Clone)]
+struct Message {
+    data: Vec<u8>,
+    topic: Option<String>,
+    expires: Option<Instant>,
+    /// The number of times the message was handed to the consumer.
+    attempts: usize,
+}
+
+struct Poller {
+    topic: Option<Selector>,
+    consumer: Box<dyn TryConsumer>,
+    queue: VecDeque<Message>,
+    consumed: u64,
+    expired: u64,
+}
+
+impl Poller {
+    fn accepts(&self, message: &Message) -> bool {
+        match (&self.topic, message.topic.as_deref()) {
+            (None, _) => true,
+            (Some(Selector::Topic(topic)), Some(put)) => topic == put,
+            (Some(Selector::Pattern(pattern)), Some(put)) => pattern.matches(put),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+impl SimCarousel {
+    /// Creates a simulation without consumers whose clock stands at the
+    /// current time.
+    pub fn new() -> SimCarousel {
+        SimCarousel {
+            clock: MockClock::new(),
+            ttl: None,
+            retries: 0,
+            error_handler: None,
+            pending: VecDeque::new(),
+            consumers: Vec::new(),
+            turn: 0,
+        }
+    }
+
+    /// Expires messages `ttl` after they were put, see `Builder::ttl`.
+    pub fn ttl(mut self, ttl: Duration) -> SimCarousel {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Retries a failed message up to `retries` times, see `Builder::retries`.
+    /// Each attempt takes a step of its own.
+    pub fn retries(mut self, retries: usize) -> SimCarousel {
+        self.retries = retries;
+        self
+    }
+
+    /// Hands messages a consumer failed to consume to `handler`, see
+    /// `Builder::error_handler`.
+    pub fn error_handler<F>(mut self, handler: F) -> SimCarousel
+    where
+        F: Fn(ConsumeError) + Send + Sync + 'static,
+    {
+        self.error_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Adds a consumer of every message, which receives the messages put
+    /// after the multiplier's next step.
+    pub fn consumer<T>(&mut self, consumer: T) -> SubscriptionId
+    where
+        T: TryConsumer + 'static,
+    {
+        self.add(None, consumer)
+    }
+
+    /// Adds a consumer of `topic`, see `Carousel::subscribe`.
+    pub fn subscribe<T>(&mut self, topic: &str, consumer: T) -> SubscriptionId
+    where
+        T: TryConsumer + 'static,
+    {
+        self.add(Some(Selector::Topic(String::from(topic))), consumer)
+    }
+
+    /// Adds a consumer of all topics matching `pattern`, see
+    /// `Carousel::subscribe_pattern`.
+    pub fn subscribe_pattern<T>(&mut self, pattern: TopicPattern, consumer: T) -> SubscriptionId
+    where
+        T: TryConsumer + 'static,
+    {
+        self.add(Some(Selector::Pattern(pattern)), consumer)
+    }
+
+    fn add<T>(&mut self, topic: Option<Selector>, consumer: T) -> SubscriptionId
+    where
+        T: TryConsumer + 'static,
+    {
+        self.consumers.push(Poller {
+            topic,
+            consumer: Box::new(consumer),
+            queue: VecDeque::new(),
+            consumed: 0,
+            expired: 0,
+        });
+        SubscriptionId(self.consumers.len() - 1)
+    }
+
+    /// Puts a message, see `Carousel::put`.
+    pub fn put(&mut self, data: Vec<u8>) {
+        self.send(None, data);
+    }
+
+    /// Puts a message on `topic`, see `Carousel::put_to`.
+    pub fn put_to(&mut self, topic: &str, data: Vec<u8>) {
+        self.send(Some(String::from(topic)), data);
+    }
+
+    fn send(&mut self, topic: Option<String>, data: Vec<u8>) {
+        let expires = self.ttl.map(|ttl| self.clock.now() + ttl);
+        self.pending.push_back(Message {
+            data,
+            topic,
+            expires,
+            attempts: 0,
+        });
+    }
+
+    /// Returns the clock of the simulation.
+    pub fn clock(&self) -> &MockClock {
+        &self.clock
+    }
+
+    /// Moves the clock of the simulation forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        self.clock.advance(duration);
+    }
+
+    /// Returns the number of messages forwarded to `consumer` which it hasn't
+    /// consumed yet.
+    pub fn depth(&self, consumer: SubscriptionId) -> usize {
+        self.consumers[consumer.0].queue.len()
+    }
+
+    /// Returns the number of messages `consumer` consumed successfully.
+    pub fn consumed(&self, consumer: SubscriptionId) -> u64 {
+        self.consumers[consumer.0].consumed
+    }
+
+    /// Returns the number of messages discarded because they expired before
+    /// `consumer` got to them.
+    pub fn expired(&self, consumer: SubscriptionId) -> u64 {
+        self.consumers[consumer.0].expired
+    }
+
+    /// Lets the next party with work take a step: the multiplier fans out the
+    /// oldest message put, or a poller hands its oldest message to its
+    /// consumer. Returns `false` if there was nothing to do.
+    pub fn step(&mut self) -> bool {
+        let parties = self.consumers.len() + 1;
+        for _ in 0..parties {
+            let turn = self.turn;
+            self.turn = (self.turn + 1) % parties;
+            let stepped = match turn {
+                0 => self.multiplex(),
+                i => self.poll(i - 1),
+            };
+            if stepped {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Steps until no party has work left and returns the number of steps.
+    pub fn run_until_idle(&mut self) -> usize {
+        let mut steps = 0;
+        while self.step() {
+            steps += 1;
+        }
+        steps
+    }
+
+    fn multiplex(&mut self) -> bool {
+        let message = match self.pending.pop_front() {
+            Some(message) => message,
+            None => return false,
+        };
+        for poller in self.consumers.iter_mut() {
+            if poller.accepts(&message) {
+                poller.queue.push_back(message.clone());
+            }
+        }
+        true
+    }
+
+    fn poll(&mut self, consumer: usize) -> bool {
+        let now = self.clock.now();
+        let poller = &mut self.consumers[consumer];
+        let mut message = match poller.queue.pop_front() {
+            Some(message) => message,
+            None => return false,
+        };
+        if message.expires.is_some_and(|expires| expires <= now) {
+            poller.expired += 1;
+            return true;
+        }
+
+        message.attempts += 1;
+        match poller.consumer.try_consume(message.data.clone()) {
+            Ok(()) => poller.consumed += 1,
+            Err(_) if message.attempts <= self.retries => poller.queue.push_front(message),
+            Err(error) => {
+                let error = ConsumeError {
+                    consumer,
+                    attempts: message.attempts,
+                    data: message.data,
+                    error,
+                };
+                match &self.error_handler {
+                    Some(handler) => handler(error),
+                    None => println!(
+                        "Consumer {} failed to consume a message: {}",
+                        consumer, error.error
+                    ),
+                }
+            }
+        }
+        true
+    }
+}
+
+impl Default for SimCarousel {
+    fn default() -> SimCarousel {
+        SimCarousel::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SimCarousel;
+    use crate::asynch::{from_fn, Error, SubscriptionId, TryConsumer};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn steps_are_deterministic() {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut c = SimCarousel::new();
+        for name in ["a", "b"] {
+            let log = Rc::clone(&log);
+            c.consumer(from_fn(move |data| {
+                log.borrow_mut().push(format!("{}{}", name, data[0]))
+            }));
+        }
+
+        c.put(vec![1]);
+        c.put(vec![2]);
+        assert_eq!(c.run_until_idle(), 6);
+        assert!(!c.step());
+        // The multiplier and the pollers take turns.
+        assert_eq!(*log.borrow(), vec!["a1", "b1", "a2", "b2"]);
+    }
+
+    #[test]
+    fn expiry_and_retries() {
+        struct Flaky(usize, mpsc::Sender<Vec<u8>>);
+
+        impl TryConsumer for Flaky {
+            fn try_consume(&mut self, data: Vec<u8>) -> Result<(), Error> {
+                self.0 += 1;
+                if self.0 < 3 {
+                    return Err("flaky".into());
+                }
+                self.1.send(data).unwrap();
+                Ok(())
+            }
+        }
+
+        let (tx, rx) = mpsc::channel();
+        let (failed_tx, failed_rx) = mpsc::channel();
+        let mut c = SimCarousel::new()
+            .ttl(Duration::from_secs(1))
+            .retries(1)
+            .error_handler(move |e| failed_tx.send((e.attempts, e.data)).unwrap());
+        c.consumer(Flaky(0, tx));
+
+        c.put(vec![1]);
+        c.put(vec![2]);
+        c.run_until_idle();
+        c.put(vec![3]);
+        c.step();
+        c.advance(Duration::from_secs(1));
+        c.run_until_idle();
+
+        // 1 fails twice, 2 succeeds on its retry, 3 expires in the queue.
+        assert_eq!(failed_rx.try_iter().collect::<Vec<_>>(), vec![(2, vec![1])]);
+        assert_eq!(rx.try_iter().collect::<Vec<_>>(), vec![vec![2]]);
+        assert_eq!(c.consumed(SubscriptionId(0)), 1);
+        assert_eq!(c.expired(SubscriptionId(0)), 1);
+    }
+}
@@ -0,0 +1,162 @@
+use std::time::Duration;
+
+/// `CarouselStats` is a snapshot of the state of a `Carousel`, returned by
+/// `Carousel::stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CarouselStats {
+    /// The statistics of each consumer, in registration order.
+    pub consumers: Vec<ConsumerStats>,
+    /// The number of retained messages removed because they expired.
+    pub expired_retained: u64,
+    /// The payload bytes held in memory, if `Builder::memory_budget` is set.
+    pub queued_bytes: usize,
+    /// The number of messages dropped for lack of memory.
+    pub dropped: u64,
+    /// The number of messages rejected by the validator.
+    pub rejected: u64,
+    /// The number of messages discarded before they were forwarded, see
+    /// `DropPolicy::Discard`.
+    pub discarded: u64,
+    /// The number of messages dropped by the transform, see
+    /// `Builder::transform`.
+    pub filtered: u64,
+    /// The statistics of each namespace, by name.
+    pub namespaces: Vec<NamespaceStats>,
+    /// The statistics of each named producer, by name.
+    pub producers: Vec<ProducerStats>,
+    /// The rate at which messages are put.
+    pub throughput: Throughput,
+}
+
+/// `ConsumerStats` is a snapshot of the state of a single consumer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumerStats {
+    /// The position of the consumer in registration order.
+    pub id: usize,
+    /// The number of messages forwarded to the consumer which it hasn't
+    /// finished consuming yet.
+    pub depth: usize,
+    /// The number of messages handed to the consumer.
+    pub consumed: u64,
+    /// The number of messages discarded because they expired before the
+    /// consumer got to them.
+    pub expired: u64,
+    /// The number of messages discarded because they failed their checksum.
+    pub corrupted: u64,
+    /// The rate at which messages are handed to the consumer.
+    pub throughput: Throughput,
+    /// The time from putting a message to handing it to the consumer.
+    pub latency: Latency,
+    /// Whether the poller thread of the consumer exited while idle, see
+    /// `Builder::idle_timeout`.
+    pub dormant: bool,
+    /// The number of calls to the consumer that timed out, see
+    /// `Builder::consume_timeout`.
+    pub timed_out: u64,
+    /// The number of times the consumer panicked, see `Builder::panic_policy`
+    /// and `Builder::supervised`.
+    pub panics: u64,
+    /// The number of messages discarded from the queue of the consumer, see
+    /// `DropPolicy::Discard`.
+    pub discarded: u64,
+    /// The payload bytes queued for the consumer, if
+    /// `Builder::consumer_memory_limit` is set for it.
+    pub queued_bytes: usize,
+    /// The number of messages dropped for the consumer for lack of memory.
+    pub dropped: u64,
+}
+
+/// `Throughput` is the rate of messages over the last whole second and the
+/// last whole minute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Throughput {
+    pub last_second: Rate,
+    /// The average rate over the last minute, or since the `Carousel` was
+    /// built if that was less than a minute ago.
+    pub last_minute: Rate,
+}
+
+/// `Rate` is a number of messages and payload bytes per second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rate {
+    pub messages_per_second: u64,
+    pub bytes_per_second: u64,
+}
+
+/// `Latency` holds percentiles of the latencies of a consumer since it was
+/// subscribed. Each is accurate to about 6%, rounded up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Latency {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+/// `NamespaceStats` is a snapshot of the state of a namespace, returned by
+/// `Namespace::stats` and as part of `Carousel::stats`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamespaceStats {
+    /// The name of the namespace.
+    pub name: String,
+    /// The statistics of the consumers subscribed within the namespace. Empty
+    /// in `CarouselStats::namespaces`, whose consumers are listed in
+    /// `CarouselStats::consumers`.
+    pub consumers: Vec<ConsumerStats>,
+    /// The number of messages put into the namespace.
+    pub put: u64,
+    /// The payload bytes held against the quota of the namespace, if any.
+    pub queued_bytes: usize,
+    /// The number of messages dropped for lack of quota.
+    pub dropped: u64,
+}
+
+/// `ProducerStats` is a snapshot of the messages put by a named producer, see
+/// `Carousel::clone_as`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProducerStats {
+    /// The name of the producer.
+    pub name: String,
+    /// The number of messages put by the producer.
+    pub put: u64,
+    /// The payload bytes put by the producer.
+    pub bytes: u64,
+    /// The number of messages the producer had to wait for its quota.
+    pub throttled: u64,
+}
+
+/// `ShutdownReport` tells what became of the messages of a `Carousel` that
+/// stopped, see `Carousel::shutdown` and `Builder::on_shutdown`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// The time from building the `Carousel` to the end of its shutdown.
+    pub uptime: Duration,
+    /// The report of each consumer, including consumers that were removed,
+    /// in registration order.
+    pub consumers: Vec<ConsumerReport>,
+    /// The number of messages discarded before they were forwarded, see
+    /// `DropPolicy::Discard`.
+    pub discarded: u64,
+}
+
+impl ShutdownReport {
+    /// Returns the ids of the consumers that panicked.
+    pub fn panicked(&self) -> Vec<usize> {
+        self.consumers
+            .iter()
+            .filter(|report| report.panics > 0)
+            .map(|report| report.id)
+            .collect()
+    }
+}
+
+/// `ConsumerReport` is the part of a `ShutdownReport` about one consumer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConsumerReport {
+    pub id: usize,
+    /// The number of messages handed to the consumer.
+    pub consumed: u64,
+    /// The number of messages discarded from the queue of the consumer.
+    pub discarded: u64,
+    /// The number of times the consumer panicked.
+    pub panics: u64,
+}
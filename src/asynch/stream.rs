@@ -0,0 +1,202 @@
+use crate::asynch::queue::Inbox;
+use crate::asynch::{
+    unshare, watched, ConsumeError, DropReason, Envelope, Error, Event, Shared, Slot,
+};
+use std::io::{self, Read};
+use std::sync::atomic::Ordering;
+
+/// `StreamingConsumer` is a consumer that reads each message as a stream,
+/// see `Builder::streaming_consumer`.
+///
+/// Messages split into chunks with `Builder::chunk_size` are streamed chunk by
+/// chunk as they arrive, so a consumer never has to hold a whole message in
+/// memory.
+pub trait StreamingConsumer {
+    /// Consumes the message read from `payload`. Reading a message which lost
+    /// one of its chunks fails with `io::ErrorKind::UnexpectedEof`.
+    ///
+    /// An error is routed to the error handler without retrying, as the
+    /// stream can't be read again.
+    fn consume(&mut self, payload: &mut dyn Read) -> Result<(), Error>;
+}
+
+impl<T: StreamingConsumer + ?Sized> StreamingConsumer for Box<T> {
+    fn consume(&mut self, payload: &mut dyn Read) -> Result<(), Error> {
+        (**self).consume(payload)
+    }
+}
+
+/// Handles a single message on the poller thread of a streaming `consumer`.
+/// The chunks following the first one are taken off the queue while the
+/// consumer reads them; other events taken off meanwhile are put aside.
+/// Returns `true` if the message was consumed successfully.
+pub(crate) fn handle(
+    consumer: &mut dyn StreamingConsumer,
+    mut envelope: Envelope,
+    inbox: &mut Inbox,
+    slot: &Slot,
+    shared: &Shared,
+) -> bool {
+    if envelope.chunk.is_some_and(|chunk| chunk.index > 0) {
+        // The consumer missed the start of the message.
+        return false;
+    }
+    if envelope.is_expired(shared.clock.now()) {
+        slot.expired.fetch_add(1, Ordering::SeqCst);
+        shared.dropped(Some(slot.id), DropReason::Expired, 1);
+        return false;
+    }
+    if let (Some(store), Some(id)) = (&shared.dedup, envelope.id) {
+        if store.is_processed(slot.id, id) {
+            return true;
+        }
+    }
+
+    let data = match envelope.spilled.take() {
+        Some(spilled) => match spilled.read() {
+            Ok(data) => data,
+            Err(e) => {
+                println!(
+                    "Consumer {} failed to read a spilled message: {}",
+                    slot.id, e
+                );
+                return false;
+            }
+        },
+        None => unshare(envelope.data),
+    };
+    // A chunked message can only be verified once all chunks were read.
+    let whole = envelope.chunk.is_none();
+    let crc = envelope.crc.filter(|_| whole);
+    let data = match shared.verify(crc, data, slot) {
+        Some(data) => data,
+        None => return false,
+    };
+    let handed = shared.clock.now();
+    let mut reader = ChunkReader {
+        data,
+        pos: 0,
+        read: 0,
+        next: envelope
+            .chunk
+            .filter(|chunk| !chunk.last)
+            .map(|chunk| (chunk.message, chunk.index + 1)),
+        shard: envelope.chunk.map_or(0, |chunk| chunk.shard),
+        failed: false,
+        inbox,
+        slot,
+    };
+
+    // The stream can't be read again, so the call is never retried.
+    let mut call = || watched(1, slot, shared, || consumer.consume(&mut reader));
+    let (result, timed_out) = match (&shared.propagator, &envelope.context) {
+        (Some(propagator), Some(context)) => {
+            let mut result = (Ok(()), false);
+            propagator.scope(context, &mut || result = call());
+            result
+        }
+        _ => call(),
+    };
+    slot.consumed.fetch_add(1, Ordering::SeqCst);
+    slot.handed(envelope.put, handed, reader.read);
+
+    match result {
+        Ok(()) => {
+            if let (Some(store), Some(id)) = (&shared.dedup, envelope.id) {
+                if let Err(e) = store.mark_processed(slot.id, id) {
+                    println!(
+                        "Consumer {} failed to mark message {} as processed: {}",
+                        slot.id, id, e
+                    );
+                }
+            }
+            true
+        }
+        // The watchdog reported the call already.
+        Err(_) if timed_out => false,
+        Err(error) => {
+            shared.report(ConsumeError {
+                consumer: slot.id,
+                attempts: 1,
+                data: Vec::new(),
+                error,
+            });
+            false
+        }
+    }
+}
+
+/// `ChunkReader` reads a message chunk by chunk off the queue of a poller.
+struct ChunkReader<'a> {
+    data: Vec<u8>,
+    pos: usize,
+    /// The number of bytes the consumer read.
+    read: usize,
+    /// The message and index of the next chunk, if any.
+    next: Option<(u64, usize)>,
+    /// The shard the chunks of the message follow one another on.
+    shard: usize,
+    failed: bool,
+    inbox: &'a mut Inbox,
+    slot: &'a Slot,
+}
+
+impl ChunkReader<'_> {
+    fn incomplete(&mut self) -> io::Error {
+        self.failed = true;
+        self.next = None;
+        io::Error::new(io::ErrorKind::UnexpectedEof, "message lost a chunk")
+    }
+}
+
+impl Read for ChunkReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.data.len() {
+            if self.failed {
+                return Err(self.incomplete());
+            }
+            let (message, index) = match self.next {
+                Some(next) => next,
+                None => return Ok(0),
+            };
+            match self.inbox.next_fresh(&self.slot.queue) {
+                Event::Message(mut envelope)
+                    if envelope.chunk.is_some_and(|chunk| chunk.message == message) =>
+                {
+                    self.slot.dequeued();
+                    let chunk = envelope.chunk.unwrap();
+                    if chunk.index != index {
+                        return Err(self.incomplete());
+                    }
+                    self.data = match envelope.spilled.take() {
+                        Some(spilled) => spilled.read().inspect_err(|_| self.failed = true)?,
+                        None => unshare(envelope.data),
+                    };
+                    self.pos = 0;
+                    self.next = Some((message, index + 1)).filter(|_| !chunk.last);
+                }
+                // A later message of the shard means the remaining chunks
+                // were lost.
+                Event::Message(envelope)
+                    if envelope
+                        .chunk
+                        .is_some_and(|chunk| chunk.shard == self.shard) =>
+                {
+                    self.inbox.defer(Event::Message(envelope));
+                    return Err(self.incomplete());
+                }
+                Event::Terminate => {
+                    self.inbox.defer(Event::Terminate);
+                    return Err(self.incomplete());
+                }
+                event => self.inbox.defer(event),
+            }
+        }
+
+        let n = buf.len().min(self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        self.read += n;
+        Ok(n)
+    }
+}
@@ -0,0 +1,247 @@
+use crate::asynch::{poller, Ctx, CtxConsumer, Error, SubscriptionId};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::sync;
+use std::time::{Duration, Instant};
+
+/// `Supervisor` is the restart strategy of a consumer registered with
+/// `Builder::supervised`. A consumer that panics is dropped and replaced by
+/// a new one from its factory, one for one: other consumers carry on
+/// undisturbed.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::Supervisor;
+/// use std::time::Duration;
+///
+/// // Wait 10ms before the first restart and at most a second before later
+/// // ones, give up after 5 restarts within a minute, and hand a message to
+/// // the restarted consumer twice before it fails.
+/// let supervisor = Supervisor::one_for_one()
+///   .backoff(Duration::from_millis(10), Duration::from_secs(1))
+///   .max_restarts(5, Duration::from_secs(60))
+///   .redeliver(2);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Supervisor {
+    backoff: Option<(Duration, Duration)>,
+    max_restarts: Option<(usize, Duration)>,
+    redeliver: usize,
+}
+
+impl Supervisor {
+    /// Restarts a consumer right away every time it panics.
+    pub fn one_for_one() -> Supervisor {
+        Supervisor {
+            backoff: None,
+            max_restarts: None,
+            redeliver: 0,
+        }
+    }
+
+    /// Waits `initial` before restarting a consumer, doubling the wait for
+    /// every further restart up to `max`. The wait starts over at `initial`
+    /// once the consumer consumed a message successfully.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Supervisor {
+        assert!(initial <= max);
+
+        self.backoff = Some((initial, max));
+        self
+    }
+
+    /// Gives up on a consumer that would be restarted more than `restarts`
+    /// times within `within`. It is removed from the `Carousel`, see
+    /// `Carousel::remove`, and the messages still forwarded to it fail with
+    /// an `Escalated` error.
+    pub fn max_restarts(mut self, restarts: usize, within: Duration) -> Supervisor {
+        self.max_restarts = Some((restarts, within));
+        self
+    }
+
+    /// Hands the message during which the consumer panicked to the restarted
+    /// consumer again, up to `times` times, so that a crash doesn't lose the
+    /// message it was consuming. A message the consumer keeps panicking on
+    /// then fails with a `ConsumerPanic` error, reported with the message to
+    /// the handler of `Builder::error_handler`, e.g. to dead letter it.
+    ///
+    /// The message is copied before it is consumed while redelivery is
+    /// configured.
+    pub fn redeliver(mut self, times: usize) -> Supervisor {
+        self.redeliver = times;
+        self
+    }
+}
+
+/// `PanicPolicy` is the reaction to a consumer that panics, see
+/// `Builder::panic_policy`. Either way the message fails with a
+/// `ConsumerPanic` error and the panic is counted in `ConsumerStats::panics`.
+#[derive(Clone)]
+pub enum PanicPolicy {
+    /// Keeps polling the same consumer, which carries on with the next
+    /// message in whatever state the panic left it. To replace it with a new
+    /// consumer instead, register it with `Builder::supervised`.
+    Restart,
+    /// Removes the consumer from the `Carousel`, see `Carousel::remove`.
+    RemoveConsumer,
+    /// Shuts the `Carousel` down: it refuses new messages, `Carousel::try_put`
+    /// fails with `PutError::Closed`, its multipliers stop once they
+    /// forwarded the messages put before, and all consumers are removed.
+    ShutdownCarousel,
+    /// Calls the handler with the consumer and its panic, and keeps the
+    /// consumer like `PanicPolicy::Restart`.
+    Callback(PanicHandler),
+}
+
+type PanicHandler = sync::Arc<dyn Fn(SubscriptionId, &ConsumerPanic) + Send + Sync>;
+
+/// `ConsumerPanic` is the error of a message during which a consumer
+/// panicked, see `Builder::supervised` and `Builder::panic_policy`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumerPanic {
+    /// The message of the panic, if it had one.
+    pub message: Option<String>,
+}
+
+impl ConsumerPanic {
+    pub(crate) fn new(payload: &(dyn Any + Send)) -> ConsumerPanic {
+        let message = match payload.downcast_ref::<&str>() {
+            Some(message) => Some(String::from(*message)),
+            None => payload.downcast_ref::<String>().cloned(),
+        };
+        ConsumerPanic { message }
+    }
+}
+
+impl fmt::Display for ConsumerPanic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "consumer panicked: {}", message),
+            None => write!(f, "consumer panicked"),
+        }
+    }
+}
+
+impl error::Error for ConsumerPanic {}
+
+/// `Escalated` is the error of a message forwarded to a supervised consumer
+/// after its supervisor gave up on it, see `Supervisor::max_restarts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Escalated {
+    pub restarts: usize,
+    pub within: Duration,
+}
+
+impl fmt::Display for Escalated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "consumer panicked more than {} times within {:?}",
+            self.restarts, self.within
+        )
+    }
+}
+
+impl error::Error for Escalated {}
+
+/// `Supervised` is a consumer that restarts the consumers created by
+/// `factory` as directed by its `Supervisor`.
+pub(crate) struct Supervised<F, T> {
+    supervisor: Supervisor,
+    factory: F,
+    /// The current consumer, `None` once the supervisor gave up.
+    consumer: Option<T>,
+    /// The times of the restarts within the window of `max_restarts`.
+    restarts: VecDeque<Instant>,
+    /// The last wait before a restart since the last successful message.
+    delay: Option<Duration>,
+}
+
+impl<F, T> Supervised<F, T>
+where
+    F: FnMut() -> T,
+    T: CtxConsumer,
+{
+    pub(crate) fn new(supervisor: Supervisor, mut factory: F) -> Supervised<F, T> {
+        let consumer = Some(factory());
+        Supervised {
+            supervisor,
+            factory,
+            consumer,
+            restarts: VecDeque::new(),
+            delay: None,
+        }
+    }
+
+    /// Replaces the consumer that panicked, unless it restarted too often.
+    fn restart(&mut self, ctx: &Ctx<'_>) {
+        let clock = ctx.carousel.shared.clock.as_ref();
+        if let Some((restarts, within)) = self.supervisor.max_restarts {
+            let now = clock.now();
+            while let Some(restart) = self.restarts.front() {
+                if now.saturating_duration_since(*restart) < within {
+                    break;
+                }
+                self.restarts.pop_front();
+            }
+            if self.restarts.len() >= restarts {
+                println!(
+                    "Consumer {} panicked too often and is removed.",
+                    ctx.consumer
+                );
+                ctx.carousel.remove(SubscriptionId(ctx.consumer));
+                return;
+            }
+            self.restarts.push_back(now);
+        }
+        if let Some((initial, max)) = self.supervisor.backoff {
+            let delay = self.delay.map_or(initial, |delay| (delay * 2).min(max));
+            self.delay = Some(delay);
+            clock.sleep(delay);
+        }
+        self.consumer = Some((self.factory)());
+    }
+}
+
+impl<F, T> CtxConsumer for Supervised<F, T>
+where
+    F: FnMut() -> T,
+    T: CtxConsumer,
+{
+    fn consume_with(&mut self, ctx: &Ctx<'_>, mut data: Vec<u8>) -> Result<(), Error> {
+        let mut redelivered = 0;
+        loop {
+            let consumer = match &mut self.consumer {
+                Some(consumer) => consumer,
+                None => {
+                    let (restarts, within) = self.supervisor.max_restarts.unwrap();
+                    return Err(Box::new(Escalated { restarts, within }));
+                }
+            };
+            let copy = match redelivered < self.supervisor.redeliver {
+                true => Some(data.clone()),
+                false => None,
+            };
+            match poller::catch(|| consumer.consume_with(ctx, data)) {
+                Ok(result) => {
+                    if result.is_ok() {
+                        self.delay = None;
+                    }
+                    return result;
+                }
+                Err(error) => {
+                    self.consumer = None;
+                    self.restart(ctx);
+                    match (copy, &self.consumer) {
+                        (Some(copy), Some(_)) => {
+                            redelivered += 1;
+                            data = copy;
+                        }
+                        _ => return Err(Box::new(error)),
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::error;
+use std::fmt;
+
+/// `Validator` checks every message at `put`, see `Builder::validator`.
+pub(crate) type Validator = Box<dyn Fn(&[u8]) -> Result<(), ValidationError> + Send + Sync>;
+
+/// `ValidationError` is returned by a validator registered with
+/// `Builder::validator` to reject a malformed message, and handed back to the
+/// producer by `Carousel::try_put` as `PutError::Invalid`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    reason: String,
+}
+
+impl ValidationError {
+    /// Creates a `ValidationError` explaining why a message was rejected.
+    pub fn new<S: Into<String>>(reason: S) -> ValidationError {
+        ValidationError {
+            reason: reason.into(),
+        }
+    }
+
+    /// Returns why the message was rejected.
+    pub fn reason(&self) -> &str {
+        &self.reason
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid message: {}", self.reason)
+    }
+}
+
+impl error::Error for ValidationError {}
+
+/// `PutError` is why `Carousel::try_put` refused a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PutError {
+    /// The validator rejected the message.
+    Invalid(ValidationError),
+    /// The `Carousel` takes no new messages, see `Carousel::quiesce`.
+    Quiesced,
+    /// The buffer of the handle is full, see `Carousel::buffered`.
+    BufferFull,
+    /// A consumer panic shut the `Carousel` down, see
+    /// `PanicPolicy::ShutdownCarousel`.
+    Closed,
+}
+
+impl From<ValidationError> for PutError {
+    fn from(e: ValidationError) -> PutError {
+        PutError::Invalid(e)
+    }
+}
+
+impl fmt::Display for PutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PutError::Invalid(e) => e.fmt(f),
+            PutError::Quiesced => write!(f, "the carousel is quiesced"),
+            PutError::BufferFull => write!(f, "the buffer of the handle is full"),
+            PutError::Closed => write!(f, "the carousel is closed"),
+        }
+    }
+}
+
+impl error::Error for PutError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            PutError::Invalid(e) => Some(e),
+            PutError::Quiesced | PutError::BufferFull | PutError::Closed => None,
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use std::hint;
+use std::sync::mpsc;
+use std::thread;
+
+/// `WaitStrategy` selects how the pollers and multiplier threads of a
+/// `Carousel` wait for work, see `Builder::wait_strategy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WaitStrategy {
+    /// Threads block until work arrives. Idle threads use no CPU, but each
+    /// message pays for waking the thread up. This is the default.
+    #[default]
+    Block,
+    /// Threads poll for work `spins` times with a spin-loop hint, then yield
+    /// to the scheduler for another `spins` polls, and only then block.
+    SpinThenPark { spins: u32 },
+    /// Threads never block and poll for work in a loop. Delivery latency is
+    /// lowest, but every poller and multiplier thread keeps a core busy.
+    BusySpin,
+}
+
+/// `Backoff` paces the polls of a thread waiting for work.
+pub(crate) struct Backoff {
+    strategy: WaitStrategy,
+    step: u32,
+}
+
+impl Backoff {
+    pub(crate) fn new(strategy: WaitStrategy) -> Backoff {
+        Backoff { strategy, step: 0 }
+    }
+
+    /// Waits briefly before the next poll. Returns `false` if the caller
+    /// should block instead of polling again.
+    pub(crate) fn snooze(&mut self) -> bool {
+        match self.strategy {
+            WaitStrategy::Block => false,
+            WaitStrategy::SpinThenPark { spins } => {
+                if self.step < spins {
+                    hint::spin_loop();
+                } else if self.step < spins.saturating_mul(2) {
+                    thread::yield_now();
+                } else {
+                    return false;
+                }
+                self.step += 1;
+                true
+            }
+            WaitStrategy::BusySpin => {
+                hint::spin_loop();
+                true
+            }
+        }
+    }
+}
+
+/// Receives from `rx`, polling it according to `strategy` before blocking.
+pub(crate) fn recv<T>(
+    rx: &mpsc::Receiver<T>,
+    strategy: WaitStrategy,
+) -> Result<T, mpsc::RecvError> {
+    let mut backoff = Backoff::new(strategy);
+    loop {
+        match rx.try_recv() {
+            Ok(t) => return Ok(t),
+            Err(mpsc::TryRecvError::Disconnected) => return Err(mpsc::RecvError),
+            Err(mpsc::TryRecvError::Empty) if backoff.snooze() => continue,
+            Err(mpsc::TryRecvError::Empty) => return rx.recv(),
+        }
+    }
+}
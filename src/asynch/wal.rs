@@ -0,0 +1,481 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::io::{self, Read, Seek, Write};
+use std::ops::RangeBounds;
+use std::path::{Path, PathBuf};
+use std::sync;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const LOG: &str = "wal";
+const HEADER: usize = 24;
+/// The length of a missing namespace or topic.
+const NONE: u16 = u16::MAX;
+
+/// `Record` is a message read back from the write-ahead log.
+pub(crate) struct Record {
+    pub(crate) offset: u64,
+    /// When the message was appended to the log.
+    pub(crate) time: SystemTime,
+    pub(crate) namespace: Option<String>,
+    pub(crate) topic: Option<String>,
+    pub(crate) data: Vec<u8>,
+}
+
+impl Record {
+    /// Returns the key the log is compacted by: the namespace and topic of
+    /// the message, or `None` for messages without a topic.
+    fn key(&self) -> Option<(Option<&str>, &str)> {
+        let topic = self.topic.as_deref()?;
+        Some((self.namespace.as_deref(), topic))
+    }
+}
+
+struct Log {
+    file: fs::File,
+    next: u64,
+}
+
+/// `Wal` is the write-ahead log of a persistent `Carousel`.
+///
+/// Every message is appended to the log file in the persistence directory as a
+/// little endian `u64` offset, `u64` time in milliseconds since the Unix epoch,
+/// `u16` namespace length, `u16` topic length and `u32` length followed by the
+/// namespace, the topic and the message bytes. A missing namespace or topic
+/// has the length `u16::MAX`.
+/// The last offset each consumer has handled is kept in a `consumer-<id>.offset`
+/// file next to the log.
+pub(crate) struct Wal {
+    dir: PathBuf,
+    log: sync::Mutex<Log>,
+}
+
+impl Wal {
+    /// Opens the log in `dir`, creating both if necessary, and returns it along
+    /// with the records it already holds. A record that was only partially
+    /// written, e.g. because the process crashed, is cut off.
+    pub(crate) fn open<P: AsRef<Path>>(dir: P) -> io::Result<(Wal, Vec<Record>)> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut file = fs::OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(dir.join(LOG))?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let (records, pos) = parse(&bytes);
+
+        if pos < bytes.len() {
+            file.set_len(pos as u64)?;
+            file.seek(io::SeekFrom::End(0))?;
+        }
+
+        let next = records.last().map_or(0, |r| r.offset + 1);
+
+        Ok((
+            Wal {
+                dir,
+                log: sync::Mutex::new(Log { file, next }),
+            },
+            records,
+        ))
+    }
+
+    /// Appends `data`, put on `topic` into `namespace` at `time`, to the log
+    /// and returns its offset.
+    pub(crate) fn append(
+        &self,
+        namespace: Option<&str>,
+        topic: Option<&str>,
+        time: SystemTime,
+        data: &[u8],
+    ) -> io::Result<u64> {
+        let name_len = |name: Option<&str>| name.map_or(0, str::len);
+        if name_len(namespace) >= NONE as usize || name_len(topic) >= NONE as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "namespace or topic too long to persist",
+            ));
+        }
+        let mut log = self.log.lock().unwrap();
+        let offset = log.next;
+        let record = Record {
+            offset,
+            time,
+            namespace: namespace.map(String::from),
+            topic: topic.map(String::from),
+            data: data.to_vec(),
+        };
+        log.file.write_all(&encode(&record))?;
+        log.file.flush()?;
+
+        log.next += 1;
+        Ok(offset)
+    }
+
+    /// Returns the records appended to the log within `range`.
+    pub(crate) fn read<R: RangeBounds<SystemTime>>(&self, range: R) -> io::Result<Vec<Record>> {
+        // Holding the lock keeps the last record from being read half written.
+        let _log = self.log.lock().unwrap();
+        let bytes = fs::read(self.dir.join(LOG))?;
+        let (mut records, _) = parse(&bytes);
+        records.retain(|record| range.contains(&record.time));
+        Ok(records)
+    }
+
+    /// Rewrites the log without the keyed records appended before `horizon`
+    /// that a later record with the same key supersedes, and returns how many
+    /// were removed.
+    pub(crate) fn compact(&self, horizon: SystemTime) -> io::Result<usize> {
+        let mut log = self.log.lock().unwrap();
+        let bytes = fs::read(self.dir.join(LOG))?;
+        let (records, _) = parse(&bytes);
+        let latest: HashMap<_, u64> = records
+            .iter()
+            .filter_map(|record| Some((record.key()?, record.offset)))
+            .collect();
+        let kept: Vec<&Record> = records
+            .iter()
+            .filter(|record| match record.key() {
+                Some(key) => record.time >= horizon || latest[&key] == record.offset,
+                None => true,
+            })
+            .collect();
+        if kept.len() == records.len() {
+            return Ok(0);
+        }
+
+        // The compacted log replaces the old one at once, so a crash leaves
+        // either of them.
+        let tmp = self.dir.join(LOG).with_extension("tmp");
+        let mut file = fs::File::create(&tmp)?;
+        for record in &kept {
+            file.write_all(&encode(record))?;
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, self.dir.join(LOG))?;
+        log.file = fs::OpenOptions::new()
+            .append(true)
+            .open(self.dir.join(LOG))?;
+        Ok(records.len() - kept.len())
+    }
+
+    /// Returns the last offset `consumer` has handled, if any.
+    pub(crate) fn committed(&self, consumer: usize) -> io::Result<Option<u64>> {
+        match fs::read(self.offset_path(consumer)) {
+            Ok(bytes) => match bytes.try_into() {
+                Ok(bytes) => Ok(Some(u64::from_le_bytes(bytes))),
+                Err(_) => Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("malformed offset of consumer {}", consumer),
+                )),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Records that `consumer` has handled every message up to `offset`.
+    pub(crate) fn commit(&self, consumer: usize, offset: u64) -> io::Result<()> {
+        let path = self.offset_path(consumer);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, offset.to_le_bytes())?;
+        fs::rename(tmp, path)
+    }
+
+    fn offset_path(&self, consumer: usize) -> PathBuf {
+        self.dir.join(format!("consumer-{}.offset", consumer))
+    }
+}
+
+/// Returns the whole records in `bytes`, and where the first incomplete one
+/// starts.
+fn parse(bytes: &[u8]) -> (Vec<Record>, usize) {
+    let mut records = Vec::new();
+    let mut pos = 0;
+    while bytes.len() - pos >= HEADER {
+        let u16_at = |at: usize| u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap());
+        let offset = u64::from_le_bytes(bytes[pos..pos + 8].try_into().unwrap());
+        let time = u64::from_le_bytes(bytes[pos + 8..pos + 16].try_into().unwrap());
+        let namespace_len = u16_at(pos + 16);
+        let topic_len = u16_at(pos + 18);
+        let len = u32::from_le_bytes(bytes[pos + 20..pos + HEADER].try_into().unwrap()) as usize;
+        let name_len = |len: u16| if len == NONE { 0 } else { len as usize };
+        let namespace = pos + HEADER;
+        let topic = namespace + name_len(namespace_len);
+        let data = topic + name_len(topic_len);
+        if bytes.len() < data + len {
+            break;
+        }
+        let name = |at: usize, len: u16| match len {
+            NONE => None,
+            len => Some(String::from_utf8_lossy(&bytes[at..at + len as usize]).into_owned()),
+        };
+        records.push(Record {
+            offset,
+            time: UNIX_EPOCH + Duration::from_millis(time),
+            namespace: name(namespace, namespace_len),
+            topic: name(topic, topic_len),
+            data: bytes[data..data + len].to_vec(),
+        });
+        pos = data + len;
+    }
+    (records, pos)
+}
+
+/// Returns the bytes of `record` in the log.
+fn encode(record: &Record) -> Vec<u8> {
+    let millis = record
+        .time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let namespace = record.namespace.as_deref().map(str::as_bytes);
+    let topic = record.topic.as_deref().map(str::as_bytes);
+    let name_len = |name: Option<&[u8]>| name.map_or(NONE, |name| name.len() as u16);
+
+    let mut bytes = Vec::with_capacity(HEADER + record.data.len());
+    bytes.extend_from_slice(&record.offset.to_le_bytes());
+    bytes.extend_from_slice(&millis.to_le_bytes());
+    bytes.extend_from_slice(&name_len(namespace).to_le_bytes());
+    bytes.extend_from_slice(&name_len(topic).to_le_bytes());
+    bytes.extend_from_slice(&(record.data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(namespace.unwrap_or_default());
+    bytes.extend_from_slice(topic.unwrap_or_default());
+    bytes.extend_from_slice(&record.data);
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asynch::wal::Wal;
+    use crate::asynch::{Carousel, MockClock, SubscriptionId};
+    use std::io::Write;
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant, SystemTime};
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("mp2c-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn partial_record_is_cut_off() {
+        let dir = temp_dir("wal-partial");
+
+        let (wal, _) = Wal::open(&dir).unwrap();
+        wal.append(None, None, SystemTime::now(), b"one").unwrap();
+        drop(wal);
+
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(dir.join("wal"))
+            .unwrap();
+        file.write_all(&[1, 0, 0]).unwrap();
+        drop(file);
+
+        let (wal, records) = Wal::open(&dir).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(
+            wal.append(None, None, SystemTime::now(), b"two").unwrap(),
+            1
+        );
+        drop(wal);
+
+        let (_, records) = Wal::open(&dir).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].data, b"two".to_vec());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compaction_keeps_the_last_message_per_key() {
+        let dir = temp_dir("wal-compact");
+
+        let (wal, _) = Wal::open(&dir).unwrap();
+        wal.append(None, Some("a"), SystemTime::now(), b"a1")
+            .unwrap();
+        wal.append(None, Some("b"), SystemTime::now(), b"b1")
+            .unwrap();
+        wal.append(None, None, SystemTime::now(), b"untopiced")
+            .unwrap();
+        wal.append(None, Some("a"), SystemTime::now(), b"a2")
+            .unwrap();
+        assert_eq!(wal.compact(SystemTime::UNIX_EPOCH).unwrap(), 0);
+        let horizon = SystemTime::now() + Duration::from_secs(60);
+        assert_eq!(wal.compact(horizon).unwrap(), 1);
+        assert_eq!(wal.compact(horizon).unwrap(), 0);
+        assert_eq!(
+            wal.append(None, Some("b"), SystemTime::now(), b"b2")
+                .unwrap(),
+            4
+        );
+        drop(wal);
+
+        let (_, records) = Wal::open(&dir).unwrap();
+        let kept: Vec<(u64, &[u8])> = records.iter().map(|r| (r.offset, &*r.data)).collect();
+        assert_eq!(
+            kept,
+            vec![
+                (1, &b"b1"[..]),
+                (2, &b"untopiced"[..]),
+                (3, &b"a2"[..]),
+                (4, &b"b2"[..])
+            ]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compaction_follows_the_clock() {
+        let dir = temp_dir("wal-compact-clock");
+
+        let clock = MockClock::new();
+        let c = Carousel::builder()
+            .clock(clock.clone())
+            .persistence(&dir)
+            .unwrap()
+            .compact_log(Duration::from_secs(60), Duration::from_millis(1))
+            .build();
+        c.put_to("t", b"t1".to_vec());
+        c.put_to("t", b"t2".to_vec());
+        let wal = c.shared.wal.as_ref().unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while wal.read(..).unwrap().len() < 2 {
+            assert!(Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        // The horizon passes on the clock rather than in real time.
+        clock.advance(Duration::from_secs(120));
+        while wal.read(..).unwrap().len() > 1 {
+            assert!(Instant::now() < deadline);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(wal.read(..).unwrap()[0].data, b"t2".to_vec());
+        drop(c);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn consumers_resume_from_their_offsets() {
+        let dir = temp_dir("wal-resume");
+
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx)
+            .persistence(&dir)
+            .unwrap()
+            .build();
+        c.put(String::from("one").into_bytes());
+        c.put(String::from("two").into_bytes());
+        drop(c);
+        assert_eq!(rx.iter().count(), 2);
+
+        let (tx1, rx1) = mpsc::channel();
+        let (tx2, rx2) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx1)
+            .consumer(tx2)
+            .persistence(&dir)
+            .unwrap()
+            .build();
+        c.put(String::from("three").into_bytes());
+        drop(c);
+
+        assert_eq!(rx1.iter().collect::<Vec<_>>(), vec![b"three".to_vec()]);
+        assert_eq!(
+            rx2.iter().collect::<Vec<_>>(),
+            vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay_keeps_topics() {
+        let dir = temp_dir("wal-topics");
+
+        let c = Carousel::builder().persistence(&dir).unwrap().build();
+        c.put_to("b", b"for-b".to_vec());
+        c.put_to("a", b"for-a".to_vec());
+        c.namespace("n").put_to("a", b"for-n".to_vec());
+        drop(c);
+
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder()
+            .subscriber("a", tx)
+            .persistence(&dir)
+            .unwrap()
+            .build();
+        assert_eq!(rx.recv().unwrap(), b"for-a".to_vec());
+        assert_eq!(c.replay(.., SubscriptionId(0)).unwrap(), 1);
+        drop(c);
+        assert_eq!(rx.iter().collect::<Vec<_>>(), vec![b"for-a".to_vec()]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay_by_time() {
+        let dir = temp_dir("wal-replay");
+
+        let (tx1, rx1) = mpsc::channel();
+        let (tx2, rx2) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx1)
+            .consumer(tx2)
+            .persistence(&dir)
+            .unwrap()
+            .build();
+        c.put(b"one".to_vec());
+        rx1.recv().unwrap();
+        // The log keeps times in milliseconds.
+        std::thread::sleep(Duration::from_millis(2));
+        let since = SystemTime::now();
+        std::thread::sleep(Duration::from_millis(2));
+        c.put(b"two".to_vec());
+        // Both consumers are done with the live messages before the replay.
+        c.put_tracked(b"three".to_vec()).wait();
+        for _ in 0..2 {
+            rx1.recv().unwrap();
+        }
+
+        assert_eq!(c.replay(since.., SubscriptionId(1)).unwrap(), 2);
+        assert!(c.replay(.., SubscriptionId(2)).is_err());
+        drop(c);
+        assert!(rx1.try_recv().is_err());
+        assert_eq!(
+            rx2.iter().collect::<Vec<_>>(),
+            vec![
+                b"one".to_vec(),
+                b"two".to_vec(),
+                b"three".to_vec(),
+                b"two".to_vec(),
+                b"three".to_vec()
+            ]
+        );
+
+        // The replay doesn't move the offsets.
+        let (tx1, rx1) = mpsc::channel();
+        let (tx2, rx2) = mpsc::channel();
+        let c = Carousel::builder()
+            .consumer(tx1)
+            .consumer(tx2)
+            .persistence(&dir)
+            .unwrap()
+            .build();
+        drop(c);
+        assert_eq!(rx1.iter().count(), 0);
+        assert_eq!(rx2.iter().count(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
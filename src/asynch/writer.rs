@@ -0,0 +1,138 @@
+use crate::asynch::Carousel;
+use std::io;
+
+/// `Writer` is an `io::Write` adapter returned by `Carousel::writer` which puts
+/// what is written to it on the `Carousel`.
+///
+/// By default each call to `write` with a non-empty buffer becomes one
+/// message. A delimited `Writer` instead buffers the written bytes and puts
+/// every chunk terminated by the delimiter as one message, without the
+/// delimiter. A trailing chunk without a delimiter is put when the `Writer` is
+/// dropped.
+///
+/// # Example
+/// ```
+/// use mp2c::asynch::{Carousel, Consumer};
+/// use std::io::Write;
+///
+/// struct TestConsumer;
+///
+/// impl Consumer for TestConsumer {
+///   fn consume(&mut self, data: Vec<u8>) {
+///     // called once for "first" and once for "second"
+///   }
+/// }
+///
+/// let c = Carousel::builder().consumer(TestConsumer).build();
+///
+/// let mut w = c.writer().delimited(b'\n');
+/// writeln!(w, "first").unwrap();
+/// writeln!(w, "second").unwrap();
+/// ```
+pub struct Writer {
+    carousel: Carousel,
+    delimiter: Option<u8>,
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub(crate) fn new(carousel: Carousel) -> Writer {
+        Writer {
+            carousel,
+            delimiter: None,
+            buf: Vec::new(),
+        }
+    }
+
+    /// Puts one message per chunk terminated by `delimiter` instead of one
+    /// message per `write`.
+    pub fn delimited(mut self, delimiter: u8) -> Writer {
+        self.delimiter = Some(delimiter);
+        self
+    }
+}
+
+impl io::Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // `io::Write` callers may write nothing, which isn't a message.
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.delimiter {
+            None => self.carousel.put(buf.to_vec()),
+            Some(delimiter) => {
+                for chunk in buf.split_inclusive(|b| *b == delimiter) {
+                    match chunk.split_last() {
+                        Some((last, data)) if *last == delimiter => {
+                            self.buf.extend_from_slice(data);
+                            self.carousel.put(std::mem::take(&mut self.buf));
+                        }
+                        _ => self.buf.extend_from_slice(chunk),
+                    }
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        if !self.buf.is_empty() {
+            self.carousel.put(std::mem::take(&mut self.buf));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::asynch::{Carousel, Consumer};
+    use std::io::Write;
+    use std::sync::mpsc;
+
+    struct Collector(mpsc::Sender<Vec<u8>>);
+
+    impl Consumer for Collector {
+        fn consume(&mut self, data: Vec<u8>) {
+            self.0.send(data).unwrap();
+        }
+    }
+
+    #[test]
+    fn empty_writes() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder().consumer(Collector(tx)).build();
+
+        let mut w = c.writer();
+        assert_eq!(w.write(b"").unwrap(), 0);
+        w.write_all(b"one").unwrap();
+        w.write_all(b"").unwrap();
+        drop(w);
+        drop(c);
+
+        let received: Vec<Vec<u8>> = rx.iter().collect();
+        assert_eq!(received, vec![b"one".to_vec()]);
+    }
+
+    #[test]
+    fn delimited() {
+        let (tx, rx) = mpsc::channel();
+        let c = Carousel::builder().consumer(Collector(tx)).build();
+
+        let mut w = c.writer().delimited(b'\n');
+        w.write_all(b"one\ntw").unwrap();
+        w.write_all(b"o\nthree").unwrap();
+        drop(w);
+        drop(c);
+
+        let received: Vec<Vec<u8>> = rx.iter().collect();
+        assert_eq!(
+            received,
+            vec![b"one".to_vec(), b"two".to_vec(), b"three".to_vec()]
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! `mp2c-top` shows the consumers of a running `Carousel` live in the
+//! terminal, from the `/metrics` of its `StatusServer`, see
+//! `Carousel::serve_status`.
+//!
+//! ```text
+//! mp2c-top 127.0.0.1:9090 [refresh interval in ms]
+//! ```
+
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// The samples of a consumer, by metric name without the `mp2c_consumer_`
+/// prefix.
+type Samples = BTreeMap<String, f64>;
+
+/// Returns the samples of each consumer in the Prometheus text `metrics`.
+fn parse(metrics: &str) -> BTreeMap<usize, Samples> {
+    let mut consumers: BTreeMap<usize, Samples> = BTreeMap::new();
+    for line in metrics.lines() {
+        let rest = match line.strip_prefix("mp2c_consumer_") {
+            Some(rest) => rest,
+            None => continue,
+        };
+        let (name, rest) = match rest.split_once("{consumer=\"") {
+            Some(split) => split,
+            None => continue,
+        };
+        let (id, rest) = match rest.split_once('"') {
+            Some(split) => split,
+            None => continue,
+        };
+        // Latency quantiles carry a second label.
+        let name = match rest.split_once("quantile=\"") {
+            Some((_, quantile)) => format!("{}_{}", name, quantile.split('"').next().unwrap()),
+            None => String::from(name),
+        };
+        let value = rest.rsplit(' ').next().and_then(|v| v.parse().ok());
+        if let (Ok(id), Some(value)) = (id.parse(), value) {
+            consumers.entry(id).or_default().insert(name, value);
+        }
+    }
+    consumers
+}
+
+fn fetch(addr: &str) -> io::Result<String> {
+    let mut stream = TcpStream::connect(addr)?;
+    write!(stream, "GET /metrics HTTP/1.1\r\nHost: {}\r\n\r\n", addr)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    match response.split_once("\r\n\r\n") {
+        Some((head, body)) if head.starts_with("HTTP/1.1 200") => Ok(String::from(body)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unexpected response",
+        )),
+    }
+}
+
+/// Returns the failures of a consumer counted so far.
+fn errors(samples: &Samples) -> f64 {
+    ["timed_out_total", "panics_total", "corrupted_total"]
+        .iter()
+        .filter_map(|name| samples.get(*name))
+        .sum()
+}
+
+fn render(
+    addr: &str,
+    consumers: &BTreeMap<usize, Samples>,
+    previous: &BTreeMap<usize, Samples>,
+    elapsed: Duration,
+) -> String {
+    let get = |samples: &Samples, name: &str| samples.get(name).copied().unwrap_or(0.0);
+    // Clears the screen and moves the cursor home.
+    let mut screen = format!("\x1b[2J\x1b[H mp2c-top {}\n\n", addr);
+    screen.push_str(&format!(
+        "{:>8} {:>10} {:>12} {:>10} {:>10} {:>10} {:>10}\n",
+        "CONSUMER", "LAG", "CONSUMED", "MSG/S", "P99 MS", "ERRORS", "ERR/S"
+    ));
+    for (id, samples) in consumers {
+        let error_rate = match previous.get(id) {
+            Some(before) if elapsed > Duration::ZERO => {
+                (errors(samples) - errors(before)).max(0.0) / elapsed.as_secs_f64()
+            }
+            _ => 0.0,
+        };
+        screen.push_str(&format!(
+            "{:>8} {:>10} {:>12} {:>10} {:>10.3} {:>10} {:>10.1}\n",
+            id,
+            get(samples, "depth"),
+            get(samples, "consumed_total"),
+            get(samples, "messages_per_second"),
+            get(samples, "latency_seconds_0.99") * 1000.0,
+            errors(samples),
+            error_rate
+        ));
+    }
+    screen
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let addr = match args.get(1) {
+        Some(addr) => addr.clone(),
+        None => {
+            eprintln!("usage: mp2c-top <address of the status server> [interval in ms]");
+            process::exit(2);
+        }
+    };
+    let interval = args
+        .get(2)
+        .and_then(|ms| ms.parse().ok())
+        .map_or(Duration::from_secs(1), Duration::from_millis);
+
+    let mut previous = BTreeMap::new();
+    let mut fetched = Instant::now();
+    loop {
+        match fetch(&addr) {
+            Ok(metrics) => {
+                let consumers = parse(&metrics);
+                let now = Instant::now();
+                print!("{}", render(&addr, &consumers, &previous, now - fetched));
+                previous = consumers;
+                fetched = now;
+            }
+            Err(e) => print!("\x1b[2J\x1b[H mp2c-top {}\n\n {}\n", addr, e),
+        }
+        io::stdout().flush().unwrap();
+        thread::sleep(interval);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{errors, parse};
+
+    #[test]
+    fn parses_consumer_metrics() {
+        let metrics = "# TYPE mp2c_dropped_total counter\n\
+                       mp2c_dropped_total 0\n\
+                       mp2c_consumer_depth{consumer=\"3\"} 7\n\
+                       mp2c_consumer_panics_total{consumer=\"3\"} 2\n\
+                       mp2c_consumer_timed_out_total{consumer=\"3\"} 1\n\
+                       mp2c_consumer_latency_seconds{consumer=\"3\",quantile=\"0.99\"} 0.25\n";
+        let consumers = parse(metrics);
+        let samples = &consumers[&3];
+        assert_eq!(samples["depth"], 7.0);
+        assert_eq!(samples["latency_seconds_0.99"], 0.25);
+        assert_eq!(errors(samples), 3.0);
+    }
+}
@@ -0,0 +1,128 @@
+//! A C API to embed a `Carousel` in applications written in other languages,
+//! available with the `ffi` feature. Build it as a shared library with
+//! `cargo rustc --lib --release --features ffi --crate-type cdylib`, and
+//! declare the functions in C as
+//!
+//! ```c
+//! typedef struct Mp2c Mp2c;
+//! typedef void (*mp2c_consumer)(const uint8_t *data, size_t len, void *user);
+//!
+//! Mp2c *mp2c_new(void);
+//! void mp2c_put(const Mp2c *carousel, const uint8_t *data, size_t len);
+//! size_t mp2c_add_consumer(const Mp2c *carousel, mp2c_consumer consumer, void *user);
+//! void mp2c_shutdown(Mp2c *carousel);
+//! ```
+
+use crate::asynch::{Carousel, Ctx, CtxConsumer, Error};
+use std::ffi::c_void;
+use std::slice;
+
+/// `Mp2c` is the `Carousel` behind the pointer handed to C.
+pub struct Mp2c {
+    carousel: Carousel,
+}
+
+/// `Mp2cConsumer` is a C consumer, called with each message and the `user`
+/// pointer it was added with.
+pub type Mp2cConsumer = extern "C" fn(data: *const u8, len: usize, user: *mut c_void);
+
+/// `Callback` calls a C consumer on its poller thread.
+struct Callback {
+    consumer: Mp2cConsumer,
+    user: *mut c_void,
+}
+
+// The caller of `mp2c_add_consumer` vouches that `user` may be used on the
+// poller thread.
+unsafe impl Send for Callback {}
+
+impl CtxConsumer for Callback {
+    fn consume_with(&mut self, _ctx: &Ctx<'_>, data: Vec<u8>) -> Result<(), Error> {
+        (self.consumer)(data.as_ptr(), data.len(), self.user);
+        Ok(())
+    }
+}
+
+/// Creates a `Carousel` without consumers. It must be shut down with
+/// `mp2c_shutdown`.
+#[no_mangle]
+pub extern "C" fn mp2c_new() -> *mut Mp2c {
+    let carousel = Carousel::builder().build();
+    Box::into_raw(Box::new(Mp2c { carousel }))
+}
+
+/// Puts a copy of the `len` bytes at `data` on the carousel.
+///
+/// # Safety
+///
+/// `carousel` must come from `mp2c_new` and not be shut down, and `data` must
+/// point to `len` readable bytes unless `len` is `0`.
+#[no_mangle]
+pub unsafe extern "C" fn mp2c_put(carousel: *const Mp2c, data: *const u8, len: usize) {
+    let data = match len {
+        0 => Vec::new(),
+        _ => slice::from_raw_parts(data, len).to_vec(),
+    };
+    (*carousel).carousel.put(data);
+}
+
+/// Adds a consumer of every message put from now on and returns its
+/// subscription id. `consumer` is called on a poller thread of its own with
+/// each message, which is only valid during the call, and with `user`.
+///
+/// # Safety
+///
+/// `carousel` must come from `mp2c_new` and not be shut down, and `user` must
+/// be safe to use on another thread until the carousel is shut down.
+#[no_mangle]
+pub unsafe extern "C" fn mp2c_add_consumer(
+    carousel: *const Mp2c,
+    consumer: Mp2cConsumer,
+    user: *mut c_void,
+) -> usize {
+    let callback = Callback { consumer, user };
+    (*carousel).carousel.subscribe_all(callback).0
+}
+
+/// Waits for the consumers to consume the messages put and frees the
+/// carousel. A null `carousel` is ignored.
+///
+/// # Safety
+///
+/// `carousel` must be null or come from `mp2c_new` and not be shut down yet.
+#[no_mangle]
+pub unsafe extern "C" fn mp2c_shutdown(carousel: *mut Mp2c) {
+    if !carousel.is_null() {
+        drop(Box::from_raw(carousel));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mp2c_add_consumer, mp2c_new, mp2c_put, mp2c_shutdown};
+    use std::ffi::c_void;
+    use std::sync::mpsc;
+
+    extern "C" fn consume(data: *const u8, len: usize, user: *mut c_void) {
+        let tx = unsafe { &*(user as *const mpsc::Sender<Vec<u8>>) };
+        let data = unsafe { std::slice::from_raw_parts(data, len) };
+        tx.send(data.to_vec()).unwrap();
+    }
+
+    #[test]
+    fn c_api() {
+        let (tx, rx) = mpsc::channel::<Vec<u8>>();
+        let user = &tx as *const mpsc::Sender<Vec<u8>> as *mut c_void;
+        unsafe {
+            let c = mp2c_new();
+            assert_eq!(mp2c_add_consumer(c, consume, user), 0);
+            mp2c_put(c, b"test".as_ptr(), 4);
+            mp2c_put(c, std::ptr::null(), 0);
+            mp2c_shutdown(c);
+        }
+        assert_eq!(
+            rx.try_iter().collect::<Vec<_>>(),
+            vec![b"test".to_vec(), vec![]]
+        );
+    }
+}
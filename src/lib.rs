@@ -5,3 +5,5 @@
 //! completely asynchronously.
 
 pub mod asynch;
+#[cfg(feature = "ffi")]
+pub mod ffi;